@@ -0,0 +1,71 @@
+use std::env;
+
+use corp::corp::Corpus;
+use corp::lex::MapLex;
+use corp::normalize::{is_normalized, normalize, NormForm};
+
+fn print_usage() {
+    println!("checknorm");
+    println!();
+    println!("Usage:");
+    println!("  checknorm <corpus> [NFC|NFKC]");
+    println!();
+    println!("Lists lexicon entries that are not normalized, one per line as");
+    println!("attribute, id, value and normalized value. The form defaults to");
+    println!("NORMALIZE of each attribute, or NFC. Exits with 1 if any are found.");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.len() > 2 || args.iter().any(|a| a == "-h" || a == "--help") {
+        print_usage();
+        return Ok(());
+    }
+    let corp = Corpus::open(&args[0])?;
+    let forced = args.get(1).map(|f| NormForm::parse(f)).transpose()?;
+    let conf = &corp.conf;
+
+    let mut attrs = Vec::new();
+    for name in conf.attrnames_in_order() {
+        let block = conf.attribute(name).ok_or("attribute not found")?;
+        if block.value("DYNAMIC").is_none() {
+            attrs.push((name.to_string(), NormForm::from_conf(conf, block)?));
+        }
+    }
+    for sname in conf.structnames_in_order() {
+        let sblock = conf.structure(sname).ok_or("structure not found")?;
+        for aname in sblock.attrnames_in_order() {
+            let ablock = sblock.attribute(aname).ok_or("structure attribute not found")?;
+            if ablock.value("DYNAMIC").is_none() {
+                attrs.push((format!("{}.{}", sname, aname), NormForm::from_conf(conf, ablock)?));
+            }
+        }
+    }
+
+    let mut total = 0;
+    for (name, form) in attrs {
+        let form = forced.or(form).unwrap_or(NormForm::Nfc);
+        let base = format!("{}{}", corp.path, name);
+        // empty lexicons cannot be mapped
+        if std::fs::metadata(base.clone() + ".lex.idx")?.len() == 0 {
+            continue;
+        }
+        let lex = MapLex::open(&base)?;
+        let mut count = 0;
+        for id in 0..lex.id_range() {
+            let value = lex.id2str(id);
+            if !is_normalized(value, form) {
+                println!("{}\t{}\t{}\t{}", name, id, value, normalize(value, form));
+                count += 1;
+            }
+        }
+        if count > 0 {
+            eprintln!("{}: {} of {} values not in {:?}", name, count, lex.id_range(), form);
+        }
+        total += count;
+    }
+    if total > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
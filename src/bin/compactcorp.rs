@@ -0,0 +1,16 @@
+use std::env;
+use std::path::PathBuf;
+
+use corp::corp::Corpus;
+use corp::tombstone;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: compactcorp <corpus> <outdir>");
+        eprintln!("  writes the corpus without its tombstoned structures to outdir");
+        return Ok(());
+    }
+    let corp = Corpus::open(&args[0])?;
+    tombstone::compact(&corp, &PathBuf::from(&args[1]))
+}
@@ -0,0 +1,106 @@
+use std::env;
+use std::io::{self, BufWriter, Write};
+
+use corp::corp::Corpus;
+use corp::decode;
+
+fn print_usage() {
+    println!("decodevert");
+    println!();
+    println!("Usage:");
+    println!("  decodevert <corpus> [options]");
+    println!();
+    println!("Options:");
+    println!("  --subc FILE        export only the ranges of a subcorpus file (not with");
+    println!("                     --nums, --ids or --where)");
+    println!("  --struct NAME      structure used by --nums and --ids (default doc)");
+    println!("  --nums N,N,...     export only the given structure numbers");
+    println!("  --ids V,V,...      export only structures whose id attribute has");
+    println!("                     one of the given values (see --idattr); with");
+    println!("                     --nums, the structures selected by either");
+    println!("  --idattr NAME      structure attribute used by --ids (default id)");
+    println!("  --where COND       export only structures whose NUMERIC or DATE");
+    println!("                     attribute satisfies COND, e.g. year>=1990,");
+    println!("                     year=1990..2005 or date=2004-03..2004-06-15;");
+    println!("                     repeated conditions must all hold, and they");
+    println!("                     filter the structures given by --nums and --ids");
+    println!("  --text             write the running text of each selected range on");
+    println!("                     one line instead of the vertical text, tokens");
+    println!("                     separated by spaces except at glue tags");
+    println!();
+    println!("The vertical text is written to stdout.");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
+        print_usage();
+        return Ok(());
+    }
+    let corp = Corpus::open(&args[0])?;
+
+    let mut subc = None;
+    let mut structname = "doc".to_string();
+    let mut idattr = "id".to_string();
+    let mut nums: Option<Vec<u64>> = None;
+    let mut ids: Option<Vec<String>> = None;
+    let mut text = false;
+    let mut conds: Vec<String> = Vec::new();
+    let mut it = args[1..].iter();
+    while let Some(a) = it.next() {
+        let mut val = || it.next().cloned().ok_or(format!("missing value for {}", a));
+        match a.as_str() {
+            "--subc" => subc = Some(val()?),
+            "--struct" => structname = val()?,
+            "--idattr" => idattr = val()?,
+            "--nums" => nums = Some(val()?.split(',')
+                                    .map(|n| n.trim().parse::<u64>())
+                                    .collect::<Result<_, _>>()?),
+            "--text" => text = true,
+            "--where" => conds.push(val()?),
+            "--ids" => ids = Some(val()?.split(',').map(|v| v.to_string()).collect()),
+            _ => return Err(format!("unknown option {}", a).into()),
+        }
+    }
+
+    if subc.is_some() && (nums.is_some() || ids.is_some() || !conds.is_empty()) {
+        return Err("--subc cannot be combined with --nums, --ids or --where".into());
+    }
+    let ranges = if let Some(path) = subc {
+        decode::read_subc(&path)?
+    } else if nums.is_some() || ids.is_some() || !conds.is_empty() {
+        let mut sel = if nums.is_some() || ids.is_some() {
+            let mut sel = nums.unwrap_or_default();
+            if let Some(ids) = ids {
+                let vals: Vec<&str> = ids.iter().map(|s| s.as_str()).collect();
+                sel.extend(decode::struct_nums_by_value(&corp, &structname, &idattr, &vals)?);
+            }
+            Some(sel)
+        } else {
+            None
+        };
+        for cond in &conds {
+            let found = decode::struct_nums_where(&corp, &structname, cond)?;
+            sel = Some(match sel {
+                Some(sel) => sel.into_iter().filter(|n| found.binary_search(n).is_ok()).collect(),
+                None => found,
+            });
+        }
+        let sel = sel.unwrap_or_default();
+        let s = corp.open_struct(&structname)?;
+        decode::struct_ranges(s.as_ref(), &sel)?
+    } else {
+        vec![(0, decode::corpus_size(&corp)?)]
+    };
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if text {
+        for &(beg, end) in &ranges {
+            writeln!(out, "{}", corp.running_text(beg, end)?)?;
+        }
+        return Ok(());
+    }
+    decode::decode_vert(&corp, &ranges, &mut out)?;
+    Ok(())
+}
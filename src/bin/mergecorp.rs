@@ -0,0 +1,36 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use corp::corp::{rebase_path, Corpus};
+use corp::merge;
+
+fn print_usage() {
+    println!("mergecorp");
+    println!();
+    println!("Usage:");
+    println!("  mergecorp <config> <corpus> <corpus> [...]");
+    println!();
+    println!("Merges compiled corpora, in the order given, into the corpus");
+    println!("described by config (written to its PATH). All attributes and");
+    println!("structures of config have to exist in every input corpus.");
+    println!("Corpora with deleted structures have to be compacted first.");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 || args.iter().any(|a| a == "-h" || a == "--help") {
+        print_usage();
+        return Ok(());
+    }
+    let mut buf = String::new();
+    File::open(&args[0])?.read_to_string(&mut buf)?;
+    let conf = corpconf::parse_conf_opt(&buf)?;
+    let out_path = rebase_path(&args[0], conf.value("PATH").ok_or("PATH not set in config")?)?;
+
+    let inputs = args[1..].iter()
+        .map(|name| Corpus::open(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    merge::merge_corpora(&inputs, &conf, &PathBuf::from(out_path))
+}
@@ -0,0 +1,14 @@
+use std::env;
+
+use corp::wrlex;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("Usage: mkfld <base>");
+        eprintln!("  base is the attribute base path without extension (e.g., /path/word)");
+        return Ok(());
+    }
+    let base = std::path::PathBuf::from(args.remove(0));
+    wrlex::write_folded(&base)
+}
@@ -0,0 +1,15 @@
+use std::env;
+
+use corp::collate::{self, Collator};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: mklsrt <base> <locale>");
+        eprintln!("  base is the attribute base path without extension (e.g., /path/word)");
+        eprintln!("  locale is e.g. cs_CZ.UTF-8 or de_DE@collation=phonebook");
+        return Ok(());
+    }
+    let base = std::path::PathBuf::from(&args[0]);
+    collate::write_lsrt(&base, &Collator::new(&args[1]))
+}
@@ -0,0 +1,14 @@
+use std::env;
+
+use corp::numeric;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("Usage: mknidx <base>");
+        eprintln!("  base is the structure attribute base path without extension (e.g., /path/doc.year)");
+        return Ok(());
+    }
+    let base = std::path::PathBuf::from(args.remove(0));
+    numeric::write_index(&base)
+}
@@ -0,0 +1,39 @@
+use std::env;
+
+use corp::corp::Corpus;
+use corp::tombstone;
+
+fn print_usage() {
+    println!("tombstone");
+    println!();
+    println!("Usage:");
+    println!("  tombstone <corpus> <struct> N,N,...   mark structures as deleted");
+    println!("  tombstone <corpus> --list             list deleted structures");
+    println!();
+    println!("Deleted structures are hidden from rev iteration and frequencies;");
+    println!("compactcorp removes them physically.");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 || args.iter().any(|a| a == "-h" || a == "--help") {
+        print_usage();
+        return Ok(());
+    }
+    let corp = Corpus::open(&args[0])?;
+    if args[1] == "--list" {
+        let mut entries: Vec<_> = tombstone::load(&corp)?.into_iter()
+            .flat_map(|(s, nums)| nums.into_iter().map(move |n| (s.clone(), n)))
+            .collect();
+        entries.sort();
+        for (s, n) in entries {
+            println!("{}\t{}", s, n);
+        }
+        return Ok(());
+    }
+    let nums = args.get(2).ok_or("missing structure numbers")?
+        .split(',')
+        .map(|n| n.trim().parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    tombstone::add(&corp, &args[1], &nums)
+}
@@ -0,0 +1,64 @@
+use std::env;
+
+use corp::corp::Corpus;
+use corp::dates::Period;
+use corp::trends::{self, Method};
+
+fn print_usage() {
+    println!("trends");
+    println!();
+    println!("Usage:");
+    println!("  trends <corpus> <attr> <dateattr> [options]");
+    println!();
+    println!("Options:");
+    println!("  --period P         day, week, month, quarter, year (default) or decade");
+    println!("  --method M         regression (default) or mk (Mann-Kendall)");
+    println!("  --minfreq N        leave out values occurring fewer times (default 5)");
+    println!("  --maxp P           leave out trends with a higher p-value (default 0.05)");
+    println!("  --top N            number of rising and falling values (default 20)");
+    println!();
+    println!("Lists the values of attr rising and falling most over the time slices");
+    println!("of the structures dated by dateattr (e.g. doc.date), one per line as");
+    println!("direction, value, frequency, score, p-value and the frequencies per");
+    println!("million positions in each slice with dated text.");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 3 || args.iter().any(|a| a == "-h" || a == "--help") {
+        print_usage();
+        return Ok(());
+    }
+    let corp = Corpus::open(&args[0])?;
+    let mut period = Period::Year;
+    let mut method = Method::Regression;
+    let mut min_freq = 5;
+    let mut max_p = 0.05;
+    let mut top = 20;
+    let mut it = args[3..].iter();
+    while let Some(a) = it.next() {
+        let mut val = || it.next().cloned().ok_or(format!("missing value for {}", a));
+        match a.as_str() {
+            "--period" => period = Period::parse(&val()?)?,
+            "--method" => method = Method::parse(&val()?)?,
+            "--minfreq" => min_freq = val()?.parse()?,
+            "--maxp" => max_p = val()?.parse()?,
+            "--top" => top = val()?.parse()?,
+            _ => return Err(format!("unknown option {}", a).into()),
+        }
+    }
+
+    let attr = corp.open_attribute(&args[1])?;
+    let trends = trends::trends(&corp, &args[1], &args[2], period, min_freq)?;
+    let starts: Vec<String> = trends.series_starts().iter().map(|d| d.to_string()).collect();
+    println!("#slices\t{}", starts.join("\t"));
+    let (rising, falling) = trends.top(method, max_p, top);
+    for (dir, items) in [("rising", rising), ("falling", falling)] {
+        for t in items {
+            let series: Vec<String> = t.series.iter().map(|f| format!("{:.2}", f)).collect();
+            println!("{}\t{}\t{}\t{:.4}\t{:.4}\t{}", dir, attr.id2str(t.id), t.freq,
+                     t.score(method), t.p_value(method), series.join("\t"));
+        }
+    }
+    Ok(())
+}
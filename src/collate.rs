@@ -0,0 +1,305 @@
+//! Locale-aware collation of lexicon strings.
+//!
+//! A simplified Unicode Collation Algorithm: strings are compared by base
+//! letters first, then by diacritics and then by case, each level left to
+//! right. Latin letters with diacritics are decomposed by a built-in
+//! table; other letters sort after Latin by code point and non-alphanumeric
+//! characters before digits. Tailorings exist for Czech (`č`, `ř`, `š`,
+//! `ž` and `ch` are letters of their own) and German phonebook order
+//! (`ä` sorts as `ae`); other locales use the root order.
+//!
+//! The collated order of a lexicon is stored in `.lex.lsrt` as ids, like
+//! `.lex.srt`.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::lex::MapLex;
+use crate::util::add_suffix;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strength {
+    /// base letters only: case and diacritics are ignored
+    Primary,
+    /// base letters and diacritics, case is ignored
+    Secondary,
+    /// base letters, diacritics and case
+    Tertiary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tailoring {
+    Root,
+    Czech,
+    GermanPhonebook,
+}
+
+#[derive(Clone, Debug)]
+pub struct Collator {
+    pub locale: String,
+    tailoring: Tailoring,
+}
+
+/// Collation weights of a string, one vector per level.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey {
+    primary: Vec<u32>,
+    secondary: Vec<u16>,
+    tertiary: Vec<u8>,
+}
+
+impl SortKey {
+    pub fn cmp_at(&self, other: &SortKey, strength: Strength) -> Ordering {
+        let ord = self.primary.cmp(&other.primary);
+        if ord != Ordering::Equal || strength == Strength::Primary {
+            return ord;
+        }
+        let ord = self.secondary.cmp(&other.secondary);
+        if ord != Ordering::Equal || strength == Strength::Secondary {
+            return ord;
+        }
+        self.tertiary.cmp(&other.tertiary)
+    }
+}
+
+const PUNCT_BASE: u32 = 1;
+const DIGIT_BASE: u32 = 0x20_0000;
+const LETTER_BASE: u32 = 0x30_0000;
+const OTHER_BASE: u32 = 0x40_0000;
+
+// diacritics in the order of their secondary weights
+const ACUTE: u8 = 1;
+const GRAVE: u8 = 2;
+const BREVE: u8 = 3;
+const CIRCUMFLEX: u8 = 4;
+const CARON: u8 = 5;
+const RING: u8 = 6;
+const DIAERESIS: u8 = 7;
+const DOUBLE_ACUTE: u8 = 8;
+const TILDE: u8 = 9;
+const DOT_ABOVE: u8 = 10;
+const CEDILLA: u8 = 11;
+const OGONEK: u8 = 12;
+const MACRON: u8 = 13;
+const COMMA_BELOW: u8 = 14;
+const STROKE: u8 = 15;
+const OTHER: u8 = 16;
+
+/// tertiary weight of letters coming from an expansion such as `ß`
+const EXPANDED: u8 = 2;
+
+/// Latin letters with diacritics: (letter, base letter, mark, second mark)
+const DECOMPOSITION: &[(char, char, u8, u8)] = &[
+    ('À', 'A', GRAVE, 0), ('Á', 'A', ACUTE, 0), ('Â', 'A', CIRCUMFLEX, 0),
+    ('Ã', 'A', TILDE, 0), ('Ä', 'A', DIAERESIS, 0), ('Å', 'A', RING, 0),
+    ('Ç', 'C', CEDILLA, 0), ('È', 'E', GRAVE, 0), ('É', 'E', ACUTE, 0),
+    ('Ê', 'E', CIRCUMFLEX, 0), ('Ë', 'E', DIAERESIS, 0), ('Ì', 'I', GRAVE, 0),
+    ('Í', 'I', ACUTE, 0), ('Î', 'I', CIRCUMFLEX, 0), ('Ï', 'I', DIAERESIS, 0),
+    ('Ñ', 'N', TILDE, 0), ('Ò', 'O', GRAVE, 0), ('Ó', 'O', ACUTE, 0),
+    ('Ô', 'O', CIRCUMFLEX, 0), ('Õ', 'O', TILDE, 0), ('Ö', 'O', DIAERESIS, 0),
+    ('Ù', 'U', GRAVE, 0), ('Ú', 'U', ACUTE, 0), ('Û', 'U', CIRCUMFLEX, 0),
+    ('Ü', 'U', DIAERESIS, 0), ('Ý', 'Y', ACUTE, 0), ('à', 'a', GRAVE, 0),
+    ('á', 'a', ACUTE, 0), ('â', 'a', CIRCUMFLEX, 0), ('ã', 'a', TILDE, 0),
+    ('ä', 'a', DIAERESIS, 0), ('å', 'a', RING, 0), ('ç', 'c', CEDILLA, 0),
+    ('è', 'e', GRAVE, 0), ('é', 'e', ACUTE, 0), ('ê', 'e', CIRCUMFLEX, 0),
+    ('ë', 'e', DIAERESIS, 0), ('ì', 'i', GRAVE, 0), ('í', 'i', ACUTE, 0),
+    ('î', 'i', CIRCUMFLEX, 0), ('ï', 'i', DIAERESIS, 0), ('ñ', 'n', TILDE, 0),
+    ('ò', 'o', GRAVE, 0), ('ó', 'o', ACUTE, 0), ('ô', 'o', CIRCUMFLEX, 0),
+    ('õ', 'o', TILDE, 0), ('ö', 'o', DIAERESIS, 0), ('ù', 'u', GRAVE, 0),
+    ('ú', 'u', ACUTE, 0), ('û', 'u', CIRCUMFLEX, 0), ('ü', 'u', DIAERESIS, 0),
+    ('ý', 'y', ACUTE, 0), ('ÿ', 'y', DIAERESIS, 0), ('Ā', 'A', MACRON, 0),
+    ('ā', 'a', MACRON, 0), ('Ă', 'A', BREVE, 0), ('ă', 'a', BREVE, 0),
+    ('Ą', 'A', OGONEK, 0), ('ą', 'a', OGONEK, 0), ('Ć', 'C', ACUTE, 0),
+    ('ć', 'c', ACUTE, 0), ('Ĉ', 'C', CIRCUMFLEX, 0), ('ĉ', 'c', CIRCUMFLEX, 0),
+    ('Ċ', 'C', DOT_ABOVE, 0), ('ċ', 'c', DOT_ABOVE, 0), ('Č', 'C', CARON, 0),
+    ('č', 'c', CARON, 0), ('Ď', 'D', CARON, 0), ('ď', 'd', CARON, 0),
+    ('Ē', 'E', MACRON, 0), ('ē', 'e', MACRON, 0), ('Ĕ', 'E', BREVE, 0),
+    ('ĕ', 'e', BREVE, 0), ('Ė', 'E', DOT_ABOVE, 0), ('ė', 'e', DOT_ABOVE, 0),
+    ('Ę', 'E', OGONEK, 0), ('ę', 'e', OGONEK, 0), ('Ě', 'E', CARON, 0),
+    ('ě', 'e', CARON, 0), ('Ĝ', 'G', CIRCUMFLEX, 0), ('ĝ', 'g', CIRCUMFLEX, 0),
+    ('Ğ', 'G', BREVE, 0), ('ğ', 'g', BREVE, 0), ('Ġ', 'G', DOT_ABOVE, 0),
+    ('ġ', 'g', DOT_ABOVE, 0), ('Ģ', 'G', CEDILLA, 0), ('ģ', 'g', CEDILLA, 0),
+    ('Ĥ', 'H', CIRCUMFLEX, 0), ('ĥ', 'h', CIRCUMFLEX, 0), ('Ĩ', 'I', TILDE, 0),
+    ('ĩ', 'i', TILDE, 0), ('Ī', 'I', MACRON, 0), ('ī', 'i', MACRON, 0),
+    ('Ĭ', 'I', BREVE, 0), ('ĭ', 'i', BREVE, 0), ('Į', 'I', OGONEK, 0),
+    ('į', 'i', OGONEK, 0), ('İ', 'I', DOT_ABOVE, 0), ('Ĵ', 'J', CIRCUMFLEX, 0),
+    ('ĵ', 'j', CIRCUMFLEX, 0), ('Ķ', 'K', CEDILLA, 0), ('ķ', 'k', CEDILLA, 0),
+    ('Ĺ', 'L', ACUTE, 0), ('ĺ', 'l', ACUTE, 0), ('Ļ', 'L', CEDILLA, 0),
+    ('ļ', 'l', CEDILLA, 0), ('Ľ', 'L', CARON, 0), ('ľ', 'l', CARON, 0),
+    ('Ń', 'N', ACUTE, 0), ('ń', 'n', ACUTE, 0), ('Ņ', 'N', CEDILLA, 0),
+    ('ņ', 'n', CEDILLA, 0), ('Ň', 'N', CARON, 0), ('ň', 'n', CARON, 0),
+    ('Ō', 'O', MACRON, 0), ('ō', 'o', MACRON, 0), ('Ŏ', 'O', BREVE, 0),
+    ('ŏ', 'o', BREVE, 0), ('Ő', 'O', DOUBLE_ACUTE, 0), ('ő', 'o', DOUBLE_ACUTE, 0),
+    ('Ŕ', 'R', ACUTE, 0), ('ŕ', 'r', ACUTE, 0), ('Ŗ', 'R', CEDILLA, 0),
+    ('ŗ', 'r', CEDILLA, 0), ('Ř', 'R', CARON, 0), ('ř', 'r', CARON, 0),
+    ('Ś', 'S', ACUTE, 0), ('ś', 's', ACUTE, 0), ('Ŝ', 'S', CIRCUMFLEX, 0),
+    ('ŝ', 's', CIRCUMFLEX, 0), ('Ş', 'S', CEDILLA, 0), ('ş', 's', CEDILLA, 0),
+    ('Š', 'S', CARON, 0), ('š', 's', CARON, 0), ('Ţ', 'T', CEDILLA, 0),
+    ('ţ', 't', CEDILLA, 0), ('Ť', 'T', CARON, 0), ('ť', 't', CARON, 0),
+    ('Ũ', 'U', TILDE, 0), ('ũ', 'u', TILDE, 0), ('Ū', 'U', MACRON, 0),
+    ('ū', 'u', MACRON, 0), ('Ŭ', 'U', BREVE, 0), ('ŭ', 'u', BREVE, 0),
+    ('Ů', 'U', RING, 0), ('ů', 'u', RING, 0), ('Ű', 'U', DOUBLE_ACUTE, 0),
+    ('ű', 'u', DOUBLE_ACUTE, 0), ('Ų', 'U', OGONEK, 0), ('ų', 'u', OGONEK, 0),
+    ('Ŵ', 'W', CIRCUMFLEX, 0), ('ŵ', 'w', CIRCUMFLEX, 0), ('Ŷ', 'Y', CIRCUMFLEX, 0),
+    ('ŷ', 'y', CIRCUMFLEX, 0), ('Ÿ', 'Y', DIAERESIS, 0), ('Ź', 'Z', ACUTE, 0),
+    ('ź', 'z', ACUTE, 0), ('Ż', 'Z', DOT_ABOVE, 0), ('ż', 'z', DOT_ABOVE, 0),
+    ('Ž', 'Z', CARON, 0), ('ž', 'z', CARON, 0), ('Ǎ', 'A', CARON, 0),
+    ('ǎ', 'a', CARON, 0), ('Ǐ', 'I', CARON, 0), ('ǐ', 'i', CARON, 0),
+    ('Ǒ', 'O', CARON, 0), ('ǒ', 'o', CARON, 0), ('Ǔ', 'U', CARON, 0),
+    ('ǔ', 'u', CARON, 0), ('Ǖ', 'U', DIAERESIS, MACRON), ('ǖ', 'u', DIAERESIS, MACRON),
+    ('Ǘ', 'U', DIAERESIS, ACUTE), ('ǘ', 'u', DIAERESIS, ACUTE), ('Ǚ', 'U', DIAERESIS, CARON),
+    ('ǚ', 'u', DIAERESIS, CARON), ('Ǜ', 'U', DIAERESIS, GRAVE), ('ǜ', 'u', DIAERESIS, GRAVE),
+    ('Ș', 'S', COMMA_BELOW, 0), ('ș', 's', COMMA_BELOW, 0), ('Ț', 'T', COMMA_BELOW, 0),
+    ('ț', 't', COMMA_BELOW, 0),
+];
+
+/// Base letter and marks of a precomposed Latin letter.
+fn decompose(c: char) -> Option<(char, u8, u8)> {
+    let i = DECOMPOSITION.binary_search_by(|e| e.0.cmp(&c)).ok()?;
+    let (_, base, m1, m2) = DECOMPOSITION[i];
+    Some((base, m1, m2))
+}
+
+fn letter_primary(base: char) -> u32 {
+    LETTER_BASE + (base.to_ascii_lowercase() as u32 - 'a' as u32) * 4
+}
+
+impl Collator {
+    /// Collator for a locale name such as `cs`, `cs_CZ.UTF-8` or
+    /// `de_DE@collation=phonebook`; unknown languages get the root order.
+    pub fn new(locale: &str) -> Collator {
+        let lang = locale.split(['_', '-', '.', '@']).next().unwrap_or("").to_ascii_lowercase();
+        let tailoring = match lang.as_str() {
+            "cs" => Tailoring::Czech,
+            "de" if locale.contains("phonebook") => Tailoring::GermanPhonebook,
+            _ => Tailoring::Root,
+        };
+        Collator { locale: locale.to_string(), tailoring }
+    }
+
+    pub fn sort_key(&self, s: &str) -> SortKey {
+        let mut key = SortKey { primary: Vec::new(), secondary: Vec::new(), tertiary: Vec::new() };
+        let mut push = |p: u32, s: u16, t: u8| {
+            key.primary.push(p);
+            key.secondary.push(s);
+            key.tertiary.push(t);
+        };
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            let upper = c.is_uppercase() as u8;
+            if self.tailoring == Tailoring::Czech && matches!(c, 'c' | 'C')
+                    && chars.peek().is_some_and(|n| matches!(n, 'h' | 'H')) {
+                chars.next();
+                push(letter_primary('h') + 2, 0, upper);
+                continue;
+            }
+            if c.is_ascii_alphabetic() {
+                push(letter_primary(c), 0, upper);
+                continue;
+            }
+            if let Some((base, m1, m2)) = decompose(c) {
+                let lower = base.to_ascii_lowercase();
+                match self.tailoring {
+                    Tailoring::Czech if m1 == CARON && m2 == 0 && matches!(lower, 'c' | 'r' | 's' | 'z') => {
+                        push(letter_primary(base) + 1, 0, upper);
+                    }
+                    Tailoring::GermanPhonebook if m1 == DIAERESIS && matches!(lower, 'a' | 'o' | 'u') => {
+                        push(letter_primary(base), 0, upper);
+                        push(letter_primary('e'), DIAERESIS as u16, upper);
+                    }
+                    _ => push(letter_primary(base), m1 as u16 * 32 + m2 as u16, upper),
+                }
+                continue;
+            }
+            match c {
+                'ß' => {
+                    push(letter_primary('s'), 0, EXPANDED);
+                    push(letter_primary('s'), 0, EXPANDED);
+                }
+                'æ' | 'Æ' => {
+                    push(letter_primary('a'), 0, EXPANDED + upper);
+                    push(letter_primary('e'), 0, EXPANDED + upper);
+                }
+                'œ' | 'Œ' => {
+                    push(letter_primary('o'), 0, EXPANDED + upper);
+                    push(letter_primary('e'), 0, EXPANDED + upper);
+                }
+                'ø' | 'Ø' => push(letter_primary('o'), STROKE as u16 * 32, upper),
+                'đ' | 'Đ' | 'ð' | 'Ð' => push(letter_primary('d'), STROKE as u16 * 32, upper),
+                'ł' | 'Ł' => push(letter_primary('l'), STROKE as u16 * 32, upper),
+                'ħ' | 'Ħ' => push(letter_primary('h'), STROKE as u16 * 32, upper),
+                'ı' => push(letter_primary('i'), OTHER as u16 * 32, 0),
+                'þ' | 'Þ' => push(letter_primary('z') + 4, 0, upper),
+                _ if c.is_numeric() => {
+                    push(DIGIT_BASE + c.to_digit(10).unwrap_or(10), 0, 0);
+                }
+                _ if c.is_alphabetic() => {
+                    let lower = c.to_lowercase().next().unwrap_or(c);
+                    push(OTHER_BASE + lower as u32, 0, upper);
+                }
+                _ => push(PUNCT_BASE + c as u32, 0, 0),
+            }
+        }
+        key
+    }
+
+    /// Compares at the given strength.
+    pub fn compare_at(&self, a: &str, b: &str, strength: Strength) -> Ordering {
+        self.sort_key(a).cmp_at(&self.sort_key(b), strength)
+    }
+
+    /// Total order: collation order, ties broken by byte order.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.sort_key(a).cmp(&self.sort_key(b)).then_with(|| a.cmp(b))
+    }
+}
+
+/// `s` with case and/or diacritics removed, as for the `c` and `d` query
+/// flags. Diacritics are removed from the Latin letters that decompose
+/// into a base letter and marks.
+pub fn fold(s: &str, ignore_case: bool, ignore_diacritics: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        let c = match decompose(c) {
+            Some((base, _, _)) if ignore_diacritics => base,
+            _ => c,
+        };
+        if ignore_case {
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Collator for an attribute: `LOCALE` of the attribute or of the corpus,
+/// none for a missing value and the `C`/`POSIX` locales.
+pub fn from_conf(conf: &corpconf::Block, attr: &corpconf::Block) -> Option<Collator> {
+    let locale = attr.value("LOCALE").or(conf.value("LOCALE"))?;
+    match locale.split(['_', '-', '.', '@']).next().unwrap_or("") {
+        "" | "C" | "POSIX" => None,
+        _ => Some(Collator::new(locale)),
+    }
+}
+
+/// Writes `.lex.lsrt` for the lexicon at `base` in the order of `coll`.
+pub fn write_lsrt(base: &Path, coll: &Collator) -> Result<(), Box<dyn std::error::Error>> {
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".lex.lsrt"))?);
+    // empty files cannot be mapped
+    if std::fs::metadata(add_suffix(base, ".lex.idx"))?.len() == 0 {
+        return Ok(());
+    }
+    let lex = MapLex::open(base.to_str().ok_or("bad path")?)?;
+    let mut keys: Vec<(SortKey, &str, u32)> = (0..lex.id_range())
+        .map(|id| (coll.sort_key(lex.id2str(id)), lex.id2str(id), id))
+        .collect();
+    keys.sort_unstable();
+    for (_, _, id) in keys {
+        f.write_all(&id.to_le_bytes())?;
+    }
+    f.flush()?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! Mapping of vertical file columns to positional attributes.
+//!
+//! By default the i-th column holds the i-th non-dynamic attribute. The
+//! `COLUMN` option of an attribute selects its source instead: a column
+//! number (0-based), a column name from a `#` header line such as
+//! `# word lemma tag`, or a template combining columns such as
+//! `{lemma}-{tag:1}`, where `:n` takes the first n characters. Columns
+//! that no attribute refers to are skipped.
+
+use std::collections::HashMap;
+
+type Error = Box<dyn std::error::Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ColRef {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field { col: ColRef, chars: Option<usize> },
+}
+
+/// A piece with the column name resolved to its index.
+#[derive(Debug, Clone)]
+enum Resolved {
+    Text(String),
+    Field { col: usize, chars: Option<usize> },
+}
+
+fn parse_colref(s: &str) -> ColRef {
+    match s.parse() {
+        Ok(i) => ColRef::Index(i),
+        Err(_) => ColRef::Name(s.to_string()),
+    }
+}
+
+fn parse_column(spec: &str) -> Result<Vec<Piece>, Error> {
+    if !spec.contains('{') {
+        return Ok(vec![Piece::Field { col: parse_colref(spec.trim()), chars: None }]);
+    }
+    let mut pieces = Vec::new();
+    let mut rest = spec;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            pieces.push(Piece::Text(rest[..open].to_string()));
+        }
+        let close = rest[open..].find('}')
+            .ok_or(format!("unterminated {{ in COLUMN {}", spec))? + open;
+        let field = &rest[open + 1..close];
+        let (col, chars) = match field.split_once(':') {
+            Some((col, n)) => (col, Some(n.parse::<usize>()
+                .map_err(|_| format!("bad character count in COLUMN {}", spec))?)),
+            None => (field, None),
+        };
+        pieces.push(Piece::Field { col: parse_colref(col.trim()), chars });
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest.to_string()));
+    }
+    Ok(pieces)
+}
+
+/// Computes attribute values from the columns of a token line.
+#[derive(Debug, Clone)]
+pub struct ColumnMap {
+    columns: Vec<Vec<Piece>>,
+    defaults: Vec<String>,
+    resolved: Option<Vec<Vec<Resolved>>>,
+}
+
+impl ColumnMap {
+    /// The mapping for the non-dynamic positional attributes of `conf`,
+    /// or `None` if no attribute has `COLUMN`.
+    pub fn from_conf(conf: &corpconf::Block) -> Result<Option<ColumnMap>, Error> {
+        let mut columns = Vec::new();
+        let mut defaults = Vec::new();
+        let mut any = false;
+        for name in conf.attrnames_in_order() {
+            let block = conf.attribute(name).ok_or("attribute not found")?;
+            if block.value("DYNAMIC").is_some() {
+                continue;
+            }
+            let pieces = match block.value("COLUMN") {
+                Some(spec) => {
+                    any = true;
+                    parse_column(spec)?
+                }
+                None => vec![Piece::Field { col: ColRef::Index(columns.len()), chars: None }],
+            };
+            columns.push(pieces);
+            defaults.push(block.value("DEFAULTVALUE").unwrap_or("===NONE===").to_string());
+        }
+        if !any {
+            return Ok(None);
+        }
+        let mut map = ColumnMap { columns, defaults, resolved: None };
+        if map.names().is_empty() {
+            map.resolved = Some(map.resolve(&HashMap::new()));
+        }
+        Ok(Some(map))
+    }
+
+    /// Column names referred to, which have to come from a header line.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.columns.iter().flatten()
+            .filter_map(|p| match p {
+                Piece::Field { col: ColRef::Name(n), .. } => Some(n.as_str()),
+                _ => None,
+            })
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    fn resolve(&self, header: &HashMap<&str, usize>) -> Vec<Vec<Resolved>> {
+        self.columns.iter()
+            .map(|pieces| pieces.iter().map(|p| match p {
+                Piece::Text(t) => Resolved::Text(t.clone()),
+                Piece::Field { col, chars } => Resolved::Field {
+                    col: match col {
+                        ColRef::Index(i) => *i,
+                        ColRef::Name(n) => header[n.as_str()],
+                    },
+                    chars: *chars,
+                },
+            }).collect())
+            .collect()
+    }
+
+    /// Forgets the header of the previous input file.
+    pub fn reset(&mut self) {
+        if !self.names().is_empty() {
+            self.resolved = None;
+        }
+    }
+
+    /// Whether column names still wait for a header line.
+    pub fn needs_header(&self) -> bool {
+        self.resolved.is_none()
+    }
+
+    /// Takes a comment line as the header if it names all columns used;
+    /// names are separated by tabs, or by spaces in lines without tabs.
+    pub fn header(&mut self, line: &str) -> bool {
+        let fields: Vec<&str> = if line.contains('\t') {
+            line.split('\t').map(str::trim).collect()
+        } else {
+            line.split_whitespace().collect()
+        };
+        let mut header = HashMap::new();
+        for (i, f) in fields.into_iter().enumerate() {
+            header.entry(f).or_insert(i);
+        }
+        if self.names().iter().any(|n| !header.contains_key(n)) {
+            return false;
+        }
+        self.resolved = Some(self.resolve(&header));
+        true
+    }
+
+    /// Number of columns a token needs, once column names are resolved.
+    pub fn width(&self) -> Option<usize> {
+        let resolved = self.resolved.as_ref()?;
+        Some(resolved.iter().flatten()
+            .filter_map(|p| match p {
+                Resolved::Field { col, .. } => Some(col + 1),
+                Resolved::Text(_) => None,
+            })
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// Attribute values of a token; values whose columns are missing get
+    /// the attribute's `DEFAULTVALUE`.
+    pub fn apply(&self, fields: &[String]) -> Result<Vec<String>, Error> {
+        let resolved = self.resolved.as_ref().ok_or_else(|| {
+            format!("no header line naming the columns {}", self.names().join(", "))
+        })?;
+        let mut values = Vec::with_capacity(resolved.len());
+        for (pieces, default) in resolved.iter().zip(&self.defaults) {
+            let mut value = String::new();
+            let mut missing = false;
+            for p in pieces {
+                match p {
+                    Resolved::Text(t) => value.push_str(t),
+                    Resolved::Field { col, chars } => match fields.get(*col) {
+                        Some(f) => match chars {
+                            Some(n) => value.extend(f.chars().take(*n)),
+                            None => value.push_str(f),
+                        },
+                        None => missing = true,
+                    },
+                }
+            }
+            values.push(if missing { default.clone() } else { value });
+        }
+        Ok(values)
+    }
+}
@@ -1,11 +1,19 @@
 use fs_err::File;
+use std::collections::HashMap;
 use std::io::Read;
 use std::fmt;
+use std::sync::{Arc, OnceLock};
 
+use crate::dates;
+use crate::encode;
 use crate::lex;
+use crate::mem;
+use crate::numeric;
 use crate::text;
 use crate::rev;
 use crate::structure;
+use crate::tombstone;
+use crate::wrrev;
 
 use crate::text::Text;
 
@@ -82,6 +90,8 @@ pub trait Attr: std::fmt::Debug + Frequency + Sync + Send {
     fn iter_ids(&self, frompos: u64) -> Box<dyn Iterator<Item=u32> + '_>;
     fn id2str(&self, id: u32) -> &str;
     fn str2id(&self, s: &str) -> Option<u32>;
+    /// Ids matching `s` under query flags (`c`, `d`), see [`lex::MapLex::str2ids`].
+    fn str2ids(&self, s: &str, flags: &str) -> Vec<u32>;
     fn revidx(&self) -> &dyn rev::Rev;
     fn text(&self) -> &dyn text::Text;
     fn id_range(&self) -> u32;
@@ -96,6 +106,7 @@ impl Attr for StdAttr {
     }
     fn id2str(&self, id: u32) -> &str { self.lex.id2str(id) }
     fn str2id(&self, s: &str) -> Option<u32> { self.lex.str2id(s) }
+    fn str2ids(&self, s: &str, flags: &str) -> Vec<u32> { self.lex.str2ids(s, flags) }
     fn revidx(&self) -> &dyn rev::Rev { self.rev.as_ref() }
     fn text(&self) -> &dyn text::Text { self.text.as_ref() }
     fn id_range(&self) -> u32 { self.lex.id_range() }
@@ -129,6 +140,7 @@ impl Attr for DynAttr {
     }
     fn id2str(&self, id: u32) -> &str { self.lex.id2str(id) }
     fn str2id(&self, s: &str) -> Option<u32> { self.lex.str2id(s) }
+    fn str2ids(&self, s: &str, flags: &str) -> Vec<u32> { self.lex.str2ids(s, flags) }
     fn revidx(&self) -> &dyn rev::Rev { self.fromattr.revidx() }
     fn text(&self) -> &dyn text::Text { return self }
     fn id_range(&self) -> u32 { self.lex.id_range() }
@@ -187,6 +199,10 @@ pub struct Corpus {
     pub path: String,
     pub name: String,
     pub conf: corpconf::Block,
+    /// attributes and structures of a corpus built by [`mem::MemBuilder`]
+    mem: Option<Arc<mem::MemStore>>,
+    /// tombstones, read on first use
+    deleted: OnceLock<tombstone::Deleted>,
 }
 
 #[derive(Debug)]
@@ -211,6 +227,23 @@ pub fn rebase_path(conf_filename: &str, path: &str) -> Result<String, Box<dyn st
     })
 }
 
+/// Interprets a boolean configuration value (`y`, `yes`, `1`, `true`,
+/// case-insensitive); missing values are false.
+pub fn conf_bool(conf: &corpconf::Block, name: &str) -> bool {
+    conf.value(name).is_some_and(|v| {
+        matches!(v.to_ascii_lowercase().as_str(), "y" | "yes" | "1" | "true")
+    })
+}
+
+/// Component separator of an attribute with `MULTIVALUE` set (`MULTISEP`,
+/// `,` by default).
+pub fn conf_multisep(attr: &corpconf::Block) -> Option<String> {
+    conf_bool(attr, "MULTIVALUE").then(|| match attr.value("MULTISEP") {
+        Some(sep) if !sep.is_empty() => sep.to_string(),
+        _ => ",".to_string(),
+    })
+}
+
 const FALLBACK_MANATEE_REGISTRY: [&str; 1] = ["/corpora/registry/"];
 fn get_registry_paths() -> Vec<String> {
     std::env::var("MANATEE_REGISTRY")
@@ -247,7 +280,23 @@ impl Corpus {
         let conf = corpconf::parse_conf_opt(&buf)?;
         let path = rebase_path(&conf_filename, conf.value("PATH").ok_or(AttrNotFound{})?)?;
         let path = path.trim_end_matches('/').to_string() + "/";
-        Ok(Corpus{ path, name: conf_filename, conf })
+        // an append being committed is finished before reading any file
+        encode::complete_append(std::path::Path::new(&path))
+            .map_err(|e| format!("unfinished append to {}: {}", path, e))?;
+        Ok(Corpus{ path, name: conf_filename, conf, mem: None, deleted: OnceLock::new() })
+    }
+
+    pub(crate) fn in_memory(conf: corpconf::Block, store: Arc<mem::MemStore>) -> Corpus {
+        Corpus{ path: String::new(), name: "<memory>".to_string(), conf, mem: Some(store),
+                deleted: OnceLock::new() }
+    }
+
+    /// Fails for in-memory corpora, which have no files for `what`.
+    fn on_disk(&self, what: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match self.mem {
+            Some(_) => Err(format!("{} is not available in an in-memory corpus", what).into()),
+            None => Ok(()),
+        }
     }
 
     pub fn rebase_path(&self, path: &str) -> Result<String, Box<dyn std::error::Error>> {
@@ -256,6 +305,9 @@ impl Corpus {
 
     pub fn open_attribute<'a, 'b>(&'a self, name: &str) -> Result<Box<dyn Attr + Sync + Send + 'b>, Box<dyn std::error::Error>> 
     {
+        if let Some(store) = &self.mem {
+            return Ok(Box::new(store.attribute(name).ok_or(AttrNotFound{})?));
+        }
         let path = self.path.clone() + "/" + name;
 
         let attrconf = if name.contains('.') {
@@ -292,32 +344,84 @@ impl Corpus {
                 lrev: rev::open(&(self.path.clone() + "/" + name))?,
             }))
         } else {
+            let lex = lex::MapLex::open(&(self.path.clone() + "/" + name))?;
+            let text = self.open_text(
+                &(self.path.clone() + "/" + name),
+                if name.contains('.') {
+                    attrconf.value("TYPE").unwrap_or("Int")
+                } else {
+                    attrconf.value("TYPE").unwrap_or("MD_MD")
+                })?;
+            let rev = self.open_rev(name, &lex, text.as_ref(), attrconf)?;
             Ok(Box::new(StdAttr{
                 path,
                 name: name.to_string(),
                 conf: attrconf.clone(),
-                lex: lex::MapLex::open(&(self.path.clone() + "/" + name))?,
-                text: self.open_text(
-                    &(self.path.clone() + "/" + name),
-                    if name.contains('.') {
-                        attrconf.value("TYPE").unwrap_or("Int")
-                    } else {
-                        attrconf.value("TYPE").unwrap_or("MD_MD")
-                    })?,
-                rev: rev::open(&(self.path.clone() + "/" + name))?,
+                lex,
+                text,
+                rev,
             }))
         }
     }
 
+    /// Tombstones of the corpus, read on the first call.
+    fn deleted(&self) -> Result<&tombstone::Deleted, Box<dyn std::error::Error>> {
+        if let Some(deleted) = self.deleted.get() {
+            return Ok(deleted);
+        }
+        let deleted = if std::path::Path::new(&(self.path.clone() + tombstone::TOMBSTONE_FILE)).exists() {
+            tombstone::Deleted::load(self)?
+        } else {
+            tombstone::Deleted::default()
+        };
+        Ok(self.deleted.get_or_init(|| deleted))
+    }
+
+    /// Rev of an attribute, hiding the positions (or structure numbers)
+    /// of deleted structures.
+    fn open_rev(&self, name: &str, lex: &lex::MapLex, text: &dyn text::Text,
+                attrconf: &corpconf::Block)
+        -> Result<Box<dyn rev::Rev + Sync + Send>, Box<dyn std::error::Error>>
+    {
+        let rev = rev::open(&(self.path.clone() + "/" + name))?;
+        let deleted = self.deleted()?;
+        let deleted = match name.split_once('.') {
+            Some((sname, _)) => deleted.struct_nums(self, sname)?,
+            None => deleted.ranges.clone(),
+        };
+        if deleted.is_empty() {
+            return Ok(rev);
+        }
+        let mut removed: HashMap<u32, u64> = HashMap::new();
+        for &(b, e) in &deleted {
+            let ids: Box<dyn Iterator<Item = u32>> = match (text.posat(b), text.structat(b)) {
+                (Some(it), _) => Box::new(it),
+                (None, Some(it)) => Box::new(it),
+                _ => return Err("text type not supported".into()),
+            };
+            for id in ids.take((e - b) as usize) {
+                *removed.entry(id).or_default() += 1;
+            }
+        }
+        if let Some(sep) = conf_multisep(attrconf) {
+            for (id, n) in removed.clone() {
+                for c in wrrev::component_ids(lex, id, &sep) {
+                    *removed.entry(c).or_default() += n;
+                }
+            }
+        }
+        Ok(Box::new(rev::Filtered::new(rev, deleted, removed)))
+    }
+
     fn open_text<'a>(&self, path: &str, typecode: &str)
         -> Result<Box<dyn text::Text + Sync + Send + 'a>, Box<dyn std::error::Error>>
     {
         match typecode {
             "MD_MD" | "FD_FD" | "FD_MD"
                 => Ok(Box::new(text::Delta::open(path)?)),
-            "MD_MGD" | "FD_FGD" | "FD_MGD"
+            "MD_MGD" | "FD_FGD" | "FD_MGD" | "NoMem"
                 => Ok(Box::new(text::GigaDelta::open(path)?)),
-            "Int"
+            "Int" | "MD_MI"
                 => Ok(Box::new(text::Int::open(path)?)),
             _ => Err(Box::new(AttrNotFound{}))
         }
@@ -326,6 +430,9 @@ impl Corpus {
     pub fn open_struct<'a>(&self, name: &str)
         -> Result<Box<dyn structure::Struct + Sync + Send + 'a>, Box<dyn std::error::Error>>
     {
+        if let Some(store) = &self.mem {
+            return Ok(Box::new(store.structure(name).ok_or(AttrNotFound{})?));
+        }
         let s = self.conf.structure(name).ok_or(AttrNotFound{})?;
         let type64 = matches!(s.value("TYPE"), Some("file64") | Some("map64"));
         structure::open(
@@ -334,12 +441,93 @@ impl Corpus {
         )
     }
 
+    /// Numeric index of the structure attribute `name` (e.g. `doc.year`),
+    /// which has to be `NUMERIC`; deleted structures are left out.
+    pub fn open_numeric(&self, name: &str) -> Result<numeric::NumIndex, Box<dyn std::error::Error>> {
+        let (sname, aname) = name.split_once('.')
+            .ok_or_else(|| format!("{} is not a structure attribute", name))?;
+        let attrconf = self.conf.structure(sname).and_then(|s| s.attribute(aname))
+            .ok_or(AttrNotFound{})?;
+        if !conf_bool(attrconf, "NUMERIC") {
+            return Err(format!("attribute {} is not NUMERIC", name).into());
+        }
+        if self.mem.is_some() {
+            let attr = self.open_attribute(name)?;
+            return Ok(numeric::NumIndex::from_attr(name, attr.as_ref(), numeric::parse_number));
+        }
+        let index = numeric::NumIndex::open(&(self.path.clone() + name))?;
+        Ok(index.without(self.deleted()?.struct_nums(self, sname)?))
+    }
+
+    /// Date index of the structure attribute `name` (e.g. `doc.date`),
+    /// which has to have `DATE` or `DATEFORMAT`; deleted structures are
+    /// left out.
+    pub fn open_dates(&self, name: &str) -> Result<dates::DateIndex, Box<dyn std::error::Error>> {
+        let (sname, aname) = name.split_once('.')
+            .ok_or_else(|| format!("{} is not a structure attribute", name))?;
+        let attrconf = self.conf.structure(sname).and_then(|s| s.attribute(aname))
+            .ok_or(AttrNotFound{})?;
+        let parser = dates::DateParser::from_conf(attrconf)
+            .ok_or_else(|| format!("attribute {} is not a DATE", name))?;
+        if self.mem.is_some() {
+            let attr = self.open_attribute(name)?;
+            return Ok(dates::DateIndex::from_attr(name, attr.as_ref(), parser));
+        }
+        let index = dates::DateIndex::open(&(self.path.clone() + name), parser)?;
+        Ok(index.without(self.deleted()?.struct_nums(self, sname)?))
+    }
+
     pub fn open_structtext<'a>(&self, structname: &str, attrname: &str)
         -> Result<text::Int, Box<dyn std::error::Error>>
     {
+        self.on_disk("structure text")?;
         Ok(text::Int::open(&(self.path.clone() + "/" + structname + "." + attrname))?)
     }
 
+    /// Running text of the positions `from..to`: values of `DEFAULTATTR`
+    /// joined by spaces, except where a glue tag (an empty structure of
+    /// `GLUESTRUCT`) stands between two tokens.
+    pub fn running_text(&self, from: u64, to: u64) -> Result<String, Box<dyn std::error::Error>> {
+        let attr = self.open_attribute(&self.get_conf("DEFAULTATTR").unwrap_or_default())?;
+        let to = to.min(attr.text().size() as u64);
+        let glue = self.glue_positions(from + 1, to)?;
+        let mut out = String::new();
+        let mut glue = glue.iter().peekable();
+        for (pos, id) in (from..to).zip(attr.iter_ids(from)) {
+            if pos > from && glue.next_if_eq(&&pos).is_none() {
+                out.push(' ');
+            }
+            out.push_str(attr.id2str(id));
+        }
+        Ok(out)
+    }
+
+    /// Positions in `from..to` preceded by a glue tag, in ascending order;
+    /// empty if the corpus has no glue structure.
+    pub fn glue_positions(&self, from: u64, to: u64) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+        let name = self.get_conf("GLUESTRUCT").unwrap_or_default();
+        if self.conf.structure(&name).is_none() {
+            return Ok(Vec::new());
+        }
+        let s = self.open_struct(&name)?;
+        let (mut lo, mut hi) = (0, s.len() as u64);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if s.beg_at(mid) < from { lo = mid + 1 } else { hi = mid }
+        }
+        let mut out = Vec::new();
+        for n in lo..s.len() as u64 {
+            let beg = s.beg_at(n);
+            if beg >= to {
+                break;
+            }
+            if s.end_at(n) == beg && out.last() != Some(&beg) {
+                out.push(beg);
+            }
+        }
+        Ok(out)
+    }
+
     pub fn get_conf(&self, name: &str) -> Option<String> {
         if let Some(val) = self.conf.value(name) {
             if val != "" {
@@ -355,6 +543,7 @@ impl Corpus {
                 .or_else(|| self.get_conf("DEFAULTATTR"))
             },
             "DEFAULTATTR" => Some("word".to_string()),
+            "GLUESTRUCT" => Some("g".to_string()),
             "WSBASE" => Some(self.path.to_string()
                              + &self.get_conf("WSATTR").unwrap() + "-ws"),
             _ => None,
@@ -0,0 +1,297 @@
+//! Date-typed structure attributes.
+//!
+//! An attribute with `DATE` set (or with `DATEFORMAT`) gets `.didx`, an
+//! index in the format of [`crate::numeric`] whose values are the days
+//! since 1 January of year 1 (chrono's `num_days_from_ce`). Values are
+//! parsed with the `chrono` formats listed in `DATEFORMAT` (separated by
+//! `|`) and then as ISO 8601 dates: `2004-05-17`, `2004-05` and `2004`
+//! (indexed as the first day of the month or year), optionally followed
+//! by a time. Values that do not parse are left out. In conditions a
+//! month or a year stands for all its days.
+
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+use crate::corp::{conf_bool, Attr, Corpus};
+use crate::numeric::{self, NumIndex};
+use crate::structure::Struct;
+
+type Error = Box<dyn std::error::Error>;
+
+/// Reads the values of a date attribute.
+#[derive(Debug, Clone, Default)]
+pub struct DateParser {
+    /// `chrono` formats tried before ISO 8601
+    pub formats: Vec<String>,
+}
+
+impl DateParser {
+    /// The parser of `attr` if it is a date attribute.
+    pub fn from_conf(attr: &corpconf::Block) -> Option<DateParser> {
+        let formats = attr.value("DATEFORMAT").filter(|f| !f.is_empty());
+        if !conf_bool(attr, "DATE") && formats.is_none() {
+            return None;
+        }
+        Some(DateParser {
+            formats: formats.map_or(Vec::new(), |f| {
+                f.split('|').map(|f| f.to_string()).collect()
+            }),
+        })
+    }
+
+    pub fn parse(&self, s: &str) -> Option<NaiveDate> {
+        self.parse_span(s).map(|(first, _)| first)
+    }
+
+    /// The first and the last day of a value, which differ for a month
+    /// or a year.
+    pub fn parse_span(&self, s: &str) -> Option<(NaiveDate, NaiveDate)> {
+        let s = s.trim();
+        self.formats.iter()
+            .find_map(|f| NaiveDate::parse_from_str(s, f).ok())
+            .map(|d| (d, d))
+            .or_else(|| parse_iso(s))
+    }
+
+    /// Day number of a value, as stored in `.didx`.
+    pub fn parse_ordinal(&self, s: &str) -> Option<f64> {
+        self.parse(s).map(|d| d.num_days_from_ce() as f64)
+    }
+}
+
+/// An ISO 8601 date, possibly reduced to the month or the year and
+/// possibly followed by a time (`T` or a space and anything after it),
+/// as its first and last day.
+fn parse_iso(s: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let date = match s.find(['T', ' ']) {
+        Some(i) if i >= 4 => &s[..i],
+        _ => s,
+    };
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?;
+    if year.len() != 4 {
+        return None;
+    }
+    let year = year.parse().ok()?;
+    let mut num = || match parts.next() {
+        Some(p) if p.len() == 2 => p.parse::<u32>().ok().map(Some),
+        Some(_) => None,
+        None => Some(None),
+    };
+    match (num()?, num()?) {
+        (None, _) => Some((NaiveDate::from_ymd_opt(year, 1, 1)?,
+                           NaiveDate::from_ymd_opt(year, 12, 31)?)),
+        (Some(month), None) => {
+            let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+            let last = first.checked_add_months(chrono::Months::new(1))?.pred_opt()?;
+            Some((first, last))
+        }
+        (Some(month), Some(day)) => NaiveDate::from_ymd_opt(year, month, day).map(|d| (d, d)),
+    }
+}
+
+fn from_ordinal(v: f64) -> NaiveDate {
+    NaiveDate::from_num_days_from_ce_opt(v as i32).unwrap_or(NaiveDate::MIN)
+}
+
+/// Writes `.didx` for the structure attribute at `base`.
+pub fn write_index(base: &Path, parser: &DateParser) -> Result<(), Error> {
+    numeric::write_index_by(base, ".didx", |s| parser.parse_ordinal(s))
+}
+
+/// Structures of a date attribute sorted by date.
+#[derive(Debug)]
+pub struct DateIndex {
+    pub parser: DateParser,
+    index: NumIndex,
+}
+
+impl DateIndex {
+    pub fn open(base: &str, parser: DateParser) -> Result<DateIndex, std::io::Error> {
+        Ok(DateIndex { parser, index: NumIndex::open_as(base, ".didx")? })
+    }
+
+    /// An index built in memory from the structure attribute `attr`
+    /// named `name`.
+    pub fn from_attr(name: &str, attr: &dyn Attr, parser: DateParser) -> DateIndex {
+        let index = NumIndex::from_attr(name, attr, |s| parser.parse_ordinal(s));
+        DateIndex { parser, index }
+    }
+
+    /// The index without the structures in the sorted `deleted` ranges.
+    pub fn without(self, deleted: Vec<(u64, u64)>) -> DateIndex {
+        DateIndex { index: self.index.without(deleted), ..self }
+    }
+
+    /// Numbers of the structures dated within `range`, ascending.
+    pub fn range(&self, range: impl RangeBounds<NaiveDate>) -> Vec<u64> {
+        let day = |b: Bound<&NaiveDate>| b.map(|d| d.num_days_from_ce() as f64);
+        self.index.range((day(range.start_bound()), day(range.end_bound())))
+    }
+
+    /// Numbers of the structures dated from `from` to `to`, both included.
+    pub fn between(&self, from: NaiveDate, to: NaiveDate) -> Vec<u64> {
+        self.range(from..=to)
+    }
+
+    /// Numbers of the structures whose date satisfies `cond`, e.g.
+    /// `date>=2001-09-11` or `date=2004..2005-06` (from the start of 2004
+    /// to the end of June 2005), see [`numeric::parse_condition`].
+    pub fn select(&self, cond: &str) -> Result<Vec<u64>, Error> {
+        let day = |d: NaiveDate| d.num_days_from_ce() as f64;
+        let (_, range) = numeric::parse_condition_by(cond, |s| {
+            self.parser.parse_span(s).map(|(first, last)| (day(first), day(last)))
+        })?;
+        Ok(self.index.range(range))
+    }
+
+    /// (date, structure number) pairs in the order of dates.
+    pub fn iter(&self) -> impl Iterator<Item = (NaiveDate, u64)> + '_ {
+        self.index.iter().map(|(v, n)| (from_ordinal(v), n))
+    }
+
+    /// The earliest and the latest date.
+    pub fn span(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let mut it = self.iter();
+        let first = it.next()?.0;
+        Some((first, it.last().map_or(first, |(d, _)| d)))
+    }
+}
+
+/// Length of the time slices of [`trends`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+    Decade,
+}
+
+impl Period {
+    pub fn parse(s: &str) -> Result<Period, Error> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "day" => Period::Day,
+            "week" => Period::Week,
+            "month" => Period::Month,
+            "quarter" => Period::Quarter,
+            "year" => Period::Year,
+            "decade" => Period::Decade,
+            _ => return Err(format!("unknown period: {}", s).into()),
+        })
+    }
+
+    /// First day of the period containing `d`; weeks start on Monday.
+    pub fn start(self, d: NaiveDate) -> NaiveDate {
+        let ymd = |y, m| NaiveDate::from_ymd_opt(y, m, 1).unwrap_or(d);
+        match self {
+            Period::Day => d,
+            Period::Week => d - Duration::days(d.weekday().num_days_from_monday() as i64),
+            Period::Month => ymd(d.year(), d.month()),
+            Period::Quarter => ymd(d.year(), (d.month() - 1) / 3 * 3 + 1),
+            Period::Year => ymd(d.year(), 1),
+            Period::Decade => ymd(d.year().div_euclid(10) * 10, 1),
+        }
+    }
+
+    /// First day of the period after the one starting at `start`.
+    pub fn next(self, start: NaiveDate) -> NaiveDate {
+        let months = |n: u32| start.checked_add_months(chrono::Months::new(n))
+            .unwrap_or(NaiveDate::MAX);
+        match self {
+            Period::Day => start.succ_opt().unwrap_or(NaiveDate::MAX),
+            Period::Week => start.checked_add_signed(Duration::days(7)).unwrap_or(NaiveDate::MAX),
+            Period::Month => months(1),
+            Period::Quarter => months(3),
+            Period::Year => months(12),
+            Period::Decade => months(120),
+        }
+    }
+}
+
+/// Frequency of a value in one time slice.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeriodFreq {
+    /// first day of the period
+    pub start: NaiveDate,
+    /// occurrences of the value in structures dated within the period
+    pub hits: u64,
+    /// positions in the structures dated within the period
+    pub size: u64,
+}
+
+impl PeriodFreq {
+    /// Occurrences per million positions; 0 for periods without text.
+    pub fn per_million(&self) -> f64 {
+        if self.size == 0 { 0.0 } else { self.hits as f64 * 1e6 / self.size as f64 }
+    }
+}
+
+/// Time slices of the structures dated by `index`, from the earliest to
+/// the latest date: the first days of the periods, and the period of
+/// each of the `slen` structures (`None` for undated ones).
+pub fn slices(index: &DateIndex, slen: usize, period: Period)
+        -> (Vec<NaiveDate>, Vec<Option<usize>>) {
+    let mut starts: Vec<NaiveDate> = Vec::new();
+    let mut of_struct = vec![None; slen];
+    for (date, n) in index.iter() {
+        let start = period.start(date);
+        while starts.last().is_none_or(|&s| s < start) {
+            let next = match starts.last() {
+                Some(&s) => period.next(s),
+                None => start,
+            };
+            starts.push(next);
+        }
+        if let Some(slot) = of_struct.get_mut(n as usize) {
+            *slot = Some(starts.len() - 1);
+        }
+    }
+    (starts, of_struct)
+}
+
+/// Number of positions in each of the `nslices` time slices, given the
+/// slice of each structure of `s` (see [`slices`]).
+pub fn slice_sizes(s: &dyn Struct, of_struct: &[Option<usize>], nslices: usize) -> Vec<u64> {
+    let mut sizes = vec![0; nslices];
+    for (n, p) in of_struct.iter().enumerate() {
+        if let Some(p) = *p {
+            sizes[p] += s.end_at(n as u64) - s.beg_at(n as u64);
+        }
+    }
+    sizes
+}
+
+/// Per-period frequencies of `value` of the positional attribute `attr`
+/// in the structures dated by `dateattr` (e.g. `doc.date`), which must
+/// not nest. Periods between the earliest and the latest date are all
+/// listed, including those without dated structures.
+pub fn trends(corp: &Corpus, attr: &str, value: &str, dateattr: &str, period: Period)
+        -> Result<Vec<PeriodFreq>, Error> {
+    let (sname, _) = dateattr.split_once('.')
+        .ok_or_else(|| format!("{} is not a structure attribute", dateattr))?;
+    let s = corp.open_struct(sname)?;
+    let index = corp.open_dates(dateattr)?;
+    let (starts, of_struct) = slices(&index, s.len(), period);
+    let sizes = slice_sizes(s.as_ref(), &of_struct, starts.len());
+    let mut out: Vec<PeriodFreq> = starts.into_iter().zip(sizes)
+        .map(|(start, size)| PeriodFreq { start, hits: 0, size })
+        .collect();
+    let a = corp.open_attribute(attr)?;
+    let Some(id) = a.str2id(value) else { return Ok(out) };
+    // positions ascend, and so do the structures
+    let mut n = 0u64;
+    for pos in a.revidx().id2poss(id) {
+        while n < s.len() as u64 && s.end_at(n) <= pos {
+            n += 1;
+        }
+        if n < s.len() as u64 && s.beg_at(n) <= pos
+                && let Some(p) = of_struct[n as usize] {
+            out[p].hits += 1;
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,247 @@
+//! Export of a compiled corpus back to the vertical format.
+//!
+//! Positional attributes are written tab-separated in configuration
+//! order, structures as tags with their attributes. Dynamic attributes
+//! are derived data and are not exported. The output can be compiled
+//! again with encodevert using the same configuration.
+
+use std::collections::HashSet;
+use std::io::Write;
+
+use fs_err as fs;
+
+use crate::corp::{conf_bool, Attr, Corpus};
+use crate::dates::DateParser;
+use crate::lex::MapLex;
+use crate::numeric;
+use crate::structure::Struct;
+use crate::text::Int;
+use crate::vert;
+
+type Error = Box<dyn std::error::Error>;
+
+struct StructOut {
+    name: String,
+    s: Box<dyn Struct + Sync + Send>,
+    attrs: Vec<(String, MapLex, Int)>,
+    /// next structure number to consider
+    next: u64,
+}
+
+struct OpenItem {
+    structno: usize,
+    end: u64,
+}
+
+/// Decodes the whole corpus.
+pub fn decode_corpus<W: Write>(corp: &Corpus, out: &mut W) -> Result<(), Error> {
+    let size = corpus_size(corp)?;
+    decode_vert(corp, &[(0, size)], out)
+}
+
+/// Writes the corpus positions in `ranges` (half-open, sorted,
+/// non-overlapping) in the vertical format. Structures crossing a range
+/// boundary are clipped to the range; empty structures at the end of a
+/// range are written only at the end of the corpus, elsewhere they belong
+/// to the following position.
+pub fn decode_vert<W: Write>(corp: &Corpus, ranges: &[(u64, u64)], out: &mut W)
+        -> Result<(), Error> {
+    let entities = conf_bool(&corp.conf, "XMLENTITIES");
+    let mut attrs: Vec<Box<dyn Attr + Sync + Send>> = Vec::new();
+    for name in corp.conf.attrnames_in_order() {
+        let block = corp.conf.attribute(name).ok_or("attribute not found")?;
+        if block.value("DYNAMIC").is_some() { continue; }
+        attrs.push(corp.open_attribute(name)?);
+    }
+    let size = attrs.first().map_or(0, |a| a.text().size() as u64);
+
+    let mut structs = Vec::new();
+    for sname in corp.conf.structnames_in_order() {
+        let sblock = corp.conf.structure(sname).ok_or("structure not found")?;
+        let mut sattrs = Vec::new();
+        for aname in sblock.attrnames_in_order() {
+            let ablock = sblock.attribute(aname).ok_or("structure attribute not found")?;
+            if ablock.value("DYNAMIC").is_some() { continue; }
+            let lex = MapLex::open(&format!("{}{}.{}", corp.path, sname, aname))?;
+            let text = corp.open_structtext(sname, aname)?;
+            sattrs.push((aname.to_string(), lex, text));
+        }
+        structs.push(StructOut {
+            name: sname.to_string(),
+            s: corp.open_struct(sname)?,
+            attrs: sattrs,
+            next: 0,
+        });
+    }
+
+    for &(beg, end) in ranges {
+        let end = end.min(size);
+        if beg > end { continue; }
+        for so in structs.iter_mut() {
+            so.next = first_at_or_after(so.s.as_ref(), beg);
+        }
+        let mut iters: Vec<_> = attrs.iter().map(|a| a.iter_ids(beg)).collect();
+        let mut stack: Vec<OpenItem> = Vec::new();
+        let mut fields: Vec<&str> = Vec::with_capacity(attrs.len());
+        for pos in beg..=end {
+            if pos == end && end != size && beg != end {
+                while let Some(it) = stack.pop() {
+                    write_close(out, &structs[it.structno].name)?;
+                }
+                break;
+            }
+            let mut opening = Vec::new();
+            let mut empty = Vec::new();
+            for (k, so) in structs.iter_mut().enumerate() {
+                while so.next < so.s.len() as u64 {
+                    let (b, e) = (so.s.beg_at(so.next), so.s.end_at(so.next));
+                    if b > pos { break; }
+                    if b == e {
+                        empty.push((k, so.next));
+                    } else {
+                        opening.push((e, k, so.next));
+                    }
+                    so.next += 1;
+                }
+            }
+            // structures earlier in the configuration are taken as the outer
+            // ones: an empty structure goes inside the outer structures
+            // opening here, or else inside those closing here
+            let outer_opening = opening.iter().map(|o| o.1).min().unwrap_or(usize::MAX);
+            while stack.last().is_some_and(|it| it.end <= pos) {
+                let it = stack.pop().unwrap();
+                let (inside, rest): (Vec<_>, Vec<_>) = empty.into_iter()
+                    .partition(|&(k, _)| k > it.structno && k <= outer_opening);
+                for (k, num) in inside {
+                    write_tag(out, &structs[k], num, true, entities)?;
+                }
+                empty = rest;
+                write_close(out, &structs[it.structno].name)?;
+            }
+            if pos != end {
+                opening.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+                for (e, k, num) in opening {
+                    write_tag(out, &structs[k], num, false, entities)?;
+                    stack.push(OpenItem { structno: k, end: e });
+                }
+            }
+            for (k, num) in empty {
+                write_tag(out, &structs[k], num, true, entities)?;
+            }
+            if pos == end {
+                break;
+            }
+
+            fields.clear();
+            for (a, it) in attrs.iter().zip(iters.iter_mut()) {
+                let id = it.next().ok_or("text underflow")?;
+                fields.push(a.id2str(id));
+            }
+            for (i, f) in fields.iter().enumerate() {
+                if i > 0 { out.write_all(b"\t")?; }
+                if entities {
+                    out.write_all(vert::escape(f).as_bytes())?;
+                } else {
+                    out.write_all(f.as_bytes())?;
+                }
+            }
+            out.write_all(b"\n")?;
+        }
+        while let Some(it) = stack.pop() {
+            write_close(out, &structs[it.structno].name)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Number of the first structure not lying entirely before `pos`;
+/// an empty structure at `pos` counts as not before it.
+fn first_at_or_after(s: &dyn Struct, pos: u64) -> u64 {
+    let (mut lo, mut hi) = (0u64, s.len() as u64);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let (b, e) = (s.beg_at(mid), s.end_at(mid));
+        if e < pos || (e == pos && b < pos) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+fn write_tag<W: Write>(out: &mut W, so: &StructOut, num: u64, empty: bool,
+                       entities: bool) -> Result<(), Error> {
+    write!(out, "<{}", so.name)?;
+    for (name, lex, text) in &so.attrs {
+        let val = lex.id2str(text.get(num));
+        if entities {
+            write!(out, " {}=\"{}\"", name, vert::escape_attr(val))?;
+        } else if val.contains('"') {
+            write!(out, " {}='{}'", name, val)?;
+        } else {
+            write!(out, " {}=\"{}\"", name, val)?;
+        }
+    }
+    out.write_all(if empty { b"/>\n" } else { b">\n" })?;
+    Ok(())
+}
+
+fn write_close<W: Write>(out: &mut W, name: &str) -> Result<(), Error> {
+    writeln!(out, "</{}>", name)?;
+    Ok(())
+}
+
+/// Number of positions in the corpus, taken from its default attribute.
+pub fn corpus_size(corp: &Corpus) -> Result<u64, Error> {
+    let attr = corp.open_attribute(&corp.get_conf("DEFAULTATTR").ok_or("no attributes")?)?;
+    Ok(attr.text().size() as u64)
+}
+
+/// Ranges of the given structure numbers, sorted by position.
+pub fn struct_ranges(s: &dyn Struct, nums: &[u64]) -> Result<Vec<(u64, u64)>, Error> {
+    let mut out = Vec::with_capacity(nums.len());
+    for &n in nums {
+        if n >= s.len() as u64 {
+            return Err(format!("structure number {} out of range", n).into());
+        }
+        out.push((s.beg_at(n), s.end_at(n)));
+    }
+    out.sort_unstable();
+    out.dedup();
+    Ok(out)
+}
+
+/// Numbers of the structures whose attribute `attrname` has one of the
+/// given values, e.g. `doc` numbers for a list of `doc.id` values.
+pub fn struct_nums_by_value(corp: &Corpus, structname: &str, attrname: &str,
+                            values: &[&str]) -> Result<Vec<u64>, Error> {
+    let lex = MapLex::open(&format!("{}{}.{}", corp.path, structname, attrname))?;
+    let text = corp.open_structtext(structname, attrname)?;
+    let ids: HashSet<u32> = values.iter().filter_map(|v| lex.str2id(v)).collect();
+    Ok((0..text.size() as u64).filter(|&n| ids.contains(&text.get(n))).collect())
+}
+
+/// Numbers of the structures whose `NUMERIC` or `DATE` attribute satisfies
+/// `cond`, e.g. `year>=1990` or `date=2004..2005-06`; ascending.
+pub fn struct_nums_where(corp: &Corpus, structname: &str, cond: &str) -> Result<Vec<u64>, Error> {
+    let attrname = cond.split(['<', '>', '=']).next().unwrap_or_default().trim();
+    let name = format!("{}.{}", structname, attrname);
+    let attrconf = corp.conf.structure(structname).and_then(|s| s.attribute(attrname))
+        .ok_or_else(|| format!("attribute {} not found", name))?;
+    if DateParser::from_conf(attrconf).is_some() {
+        return corp.open_dates(&name)?.select(cond);
+    }
+    let (_, range) = numeric::parse_condition(cond)?;
+    Ok(corp.open_numeric(&name)?.range(range))
+}
+
+/// Reads a subcorpus file consisting of 32-bit (begin, end) pairs.
+pub fn read_subc(path: &str) -> Result<Vec<(u64, u64)>, Error> {
+    let data = fs::read(path)?;
+    Ok(data.chunks_exact(8)
+        .map(|c| (u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as u64,
+                  u32::from_le_bytes([c[4], c[5], c[6], c[7]]) as u64))
+        .collect())
+}
@@ -0,0 +1,1102 @@
+//! Compilation of vertical files into corpus files.
+//!
+//! An [`Encoder`] either creates a new corpus or appends to an existing
+//! one. When appending, the extended files are written to a staging
+//! directory inside the corpus. [`Encoder::finish`] then writes a commit
+//! marker and renames them into place; `Corpus::open` finishes a commit
+//! it finds in progress (see [`complete_append`]), so a corpus opened once
+//! the commit has started never sees only part of it. Rev files present in
+//! the corpus are merged with the new postings; frequency files (`.frq`,
+//! `.frq64`) are removed as they would be stale. Dynamic attributes have to
+//! be regenerated after appending.
+//!
+//! In strict mode, input problems that are otherwise warned about or
+//! silently repaired are reported as JSON lines and make
+//! [`Encoder::finish`] fail.
+//!
+//! Tags of structures missing in the config are handled according to
+//! `UNKNOWNSTRUCT`; structures declared on the fly are written like the
+//! configured ones and listed in [`AUTO_STRUCT_CONF`] for inclusion in the
+//! config. Tags whose names are not XML names, such as `<3>`, are read
+//! as tokens in every mode. Glue tags `<g/>` are empty tags of `GLUESTRUCT`
+//! (`g` by default); like other tags they are recorded only if the
+//! structure is declared, in the config or on the fly.
+//!
+//! Values of `MULTIVALUE` attributes are stored whole; their components
+//! (split on `MULTISEP`, `,` by default) are added to the lexicon as well
+//! and the rev lists each position under the value and its components.
+//! Structure attributes with `NUMERIC` set also get a numeric index (see
+//! [`crate::numeric`]), those with `DATE` a date index (see
+//! [`crate::dates`]).
+//!
+//! In parallel mode each positional attribute is written by its own thread
+//! fed with batches of tokens over a bounded channel; the output is the
+//! same as in the serial mode.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+use chrono::Utc;
+
+use corpconf::Block;
+use crate::columns::ColumnMap;
+use crate::dates::{self, DateParser};
+use crate::corp::{conf_bool, conf_multisep};
+use crate::normalize::{normalize, NormForm};
+use crate::numeric;
+use crate::util::{add_suffix, json_string};
+use crate::vert::{self, Event};
+use crate::wrlex::{self, LexIndexes, LexWriter};
+use crate::wrrev;
+use crate::wrtext::{self, IntTextWriter, TextType, TextWriter};
+
+const STATUS_EVERY_LINES: u64 = 10_000_000;
+const ENC_ERR_MAX: i64 = 100;
+const WARN_VERBOSE: bool = false;
+const APPEND_DIR: &str = ".append.tmp";
+/// Commit marker of an append, see [`complete_append`].
+pub const APPEND_COMMIT: &str = ".append.commit";
+const BATCH_TOKENS: usize = 4096;
+const BATCH_QUEUE: usize = 4;
+/// longest value in bytes accepted in strict mode, unless `MAXVALUELEN`
+/// says otherwise
+const DEFAULT_MAX_VALUE_LEN: usize = 4096;
+/// config snippet declaring the structures declared on the fly
+pub const AUTO_STRUCT_CONF: &str = "autostructs.conf";
+
+/// Handling of tags of structures missing in the config, `UNKNOWNSTRUCT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownStruct {
+    /// read the tag line as a token, as encodevert always did
+    Token,
+    /// drop the tag
+    Ignore,
+    /// drop the tag with a warning
+    Warn,
+    /// declare the structure and its attributes as they appear
+    Declare,
+}
+
+impl UnknownStruct {
+    pub fn from_conf(conf: &Block) -> Result<UnknownStruct, Box<dyn std::error::Error>> {
+        match conf.value("UNKNOWNSTRUCT").map(|v| v.to_ascii_lowercase()).as_deref() {
+            None | Some("token") => Ok(UnknownStruct::Token),
+            Some("ignore") => Ok(UnknownStruct::Ignore),
+            Some("warn") => Ok(UnknownStruct::Warn),
+            Some("declare") => Ok(UnknownStruct::Declare),
+            Some(v) => Err(format!("bad UNKNOWNSTRUCT: {}", v).into()),
+        }
+    }
+}
+
+/// Options of an attribute from the config; structure attributes
+/// declared on the fly get the corpus-level ones.
+#[derive(Clone)]
+struct AttrOptions {
+    lex_mem_limit: Option<usize>,
+    indexes: LexIndexes,
+    norm: Option<NormForm>,
+    /// component separator of a `MULTIVALUE` attribute
+    multisep: Option<String>,
+    /// `NUMERIC`: write the numeric index of a structure attribute
+    numeric: bool,
+    /// `DATE`: write the date index of a structure attribute
+    dates: Option<DateParser>,
+}
+
+impl AttrOptions {
+    /// Options of `attr`, with corpus-level defaults from `conf`.
+    fn from_conf(conf: &Block, attr: &Block) -> Result<AttrOptions, Box<dyn std::error::Error>> {
+        Ok(AttrOptions {
+            lex_mem_limit: lex_mem_limit(conf, attr)?,
+            indexes: LexIndexes::from_conf(conf, attr),
+            norm: NormForm::from_conf(conf, attr)?,
+            multisep: conf_multisep(attr),
+            numeric: conf_bool(attr, "NUMERIC"),
+            dates: DateParser::from_conf(attr),
+        })
+    }
+
+    /// Id of `value`, normalized as configured; components of multivalues
+    /// are added to the lexicon too, for the rev.
+    fn id_for(&self, lex: &mut LexWriter, value: &str) -> Result<u32, Box<dyn std::error::Error>> {
+        let value = match self.norm {
+            Some(form) => normalize(value, form),
+            None => Cow::Borrowed(value),
+        };
+        let id = lex.id_for(&value)?;
+        if let Some(sep) = &self.multisep {
+            for comp in wrrev::split_multivalue(&value, sep) {
+                lex.id_for(comp)?;
+            }
+        }
+        Ok(id)
+    }
+}
+
+struct EncErr {
+    name: &'static str,
+    count: i64,
+}
+
+impl EncErr {
+    fn new(name: &'static str) -> EncErr {
+        EncErr { name, count: 0 }
+    }
+
+    fn emit(&mut self, line: u64, msg: &str) {
+        if self.count < ENC_ERR_MAX || ENC_ERR_MAX == -1 {
+            eprintln!("line {}: warning: {}", line, msg);
+        }
+        if self.count == ENC_ERR_MAX - 1 && ENC_ERR_MAX != -1 {
+            eprintln!("There were already {} similar errors in the input", ENC_ERR_MAX);
+            eprintln!("further errors will be suppressed and a summary will be");
+            eprintln!("provided at the end of the compilation.");
+            eprintln!("Use -v to emit all occurrences.");
+        }
+        self.count += 1;
+    }
+
+    fn summary(&self) {
+        if WARN_VERBOSE || self.count > 0 {
+            eprintln!("{} times: warning type '{}'", self.count, self.name);
+        }
+    }
+}
+
+/// Violations found in strict mode, written as JSON lines with the line
+/// number, the kind of the violation and the offending text.
+struct Report {
+    out: Box<dyn Write + Send>,
+    max_value_len: usize,
+    count: u64,
+}
+
+impl Report {
+    fn emit(&mut self, line: u64, kind: &str, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.count += 1;
+        writeln!(self.out, "{{\"line\":{},\"kind\":\"{}\",\"text\":{}}}",
+                 line, kind, json_string(text))?;
+        Ok(())
+    }
+
+    fn check_value(&mut self, line: u64, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if value.len() > self.max_value_len {
+            let head: String = value.chars().take(64).collect();
+            self.emit(line, "overlong_value", &format!("{}... ({} bytes)", head, value.len()))?;
+        }
+        Ok(())
+    }
+}
+
+struct AttrWriter {
+    name: String,
+    lex: LexWriter,
+    text: Box<dyn TextWriter>,
+    default_value: String,
+    opts: AttrOptions,
+}
+
+impl AttrWriter {
+    fn push_value(&mut self, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let id = self.opts.id_for(&mut self.lex, value)?;
+        self.text.push(id)?;
+        Ok(())
+    }
+
+    fn push_field(&mut self, fields: &[String], i: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if i < fields.len() {
+            self.push_value(&fields[i])
+        } else {
+            let dv = self.default_value.clone();
+            self.push_value(&dv)
+        }
+    }
+
+    fn finalize(self, rev: &RevJob) -> Result<(), Box<dyn std::error::Error>> {
+        self.lex.finalize()?;
+        self.opts.indexes.write(&rev.base)?;
+        self.text.finalize()?;
+        rev.run()
+    }
+
+}
+
+struct StructAttrWriter {
+    name: String,
+    lex: LexWriter,
+    text: IntTextWriter,
+    default_value: String,
+    opts: AttrOptions,
+}
+
+impl StructAttrWriter {
+    fn new(
+        name: &str,
+        base: &Path,
+        from: Option<&Path>,
+        default_value: String,
+        opts: AttrOptions,
+    ) -> Result<StructAttrWriter, Box<dyn std::error::Error>> {
+        let lex = LexWriter::open(base, from, opts.lex_mem_limit)?;
+        let text = match from {
+            Some(from) => IntTextWriter::append(from, base)?,
+            None => IntTextWriter::new(base)?,
+        };
+        Ok(StructAttrWriter {
+            name: name.to_string(),
+            lex,
+            text,
+            default_value,
+            opts,
+        })
+    }
+
+    fn id_for(&mut self, value: &str) -> Result<u32, Box<dyn std::error::Error>> {
+        self.opts.id_for(&mut self.lex, value)
+    }
+
+    fn push_value(&mut self, id: u32, _struct_pos: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.text.push(id)?;
+        Ok(())
+    }
+
+}
+
+struct StructWriter {
+    type64: bool,
+    rng: BufWriter<File>,
+    count: u32,
+    /// number of structures in the corpus being appended to
+    start_count: u32,
+    attrs: Vec<StructAttrWriter>,
+    last_start_pos: Option<u64>,
+    pending_empty_pos: Option<u64>,
+    pending_empty_vals: Option<Vec<String>>,
+}
+
+/// Rev to write for an attribute once its text is complete.
+struct RevJob {
+    base: PathBuf,
+    multisep: Option<String>,
+    /// existing attribute whose rev is extended, when appending
+    from: Option<PathBuf>,
+    old_size: u64,
+    build: bool,
+}
+
+impl RevJob {
+    fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(from) = &self.from {
+            wrrev::append_rev(from, &self.base, self.old_size, self.multisep.as_deref())
+        } else if self.build {
+            wrrev::build_rev(&self.base, self.multisep.as_deref())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Attribute writer running in its own thread.
+struct Worker {
+    name: String,
+    tx: SyncSender<Arc<Vec<Vec<String>>>>,
+    handle: JoinHandle<Result<(), String>>,
+}
+
+struct OpenStruct {
+    name: String,
+    line: u64,
+    start: u64,
+    attr_values: Vec<String>,
+}
+
+pub struct Encoder {
+    out_path: PathBuf,
+    /// directory the files are written to, the staging directory when
+    /// appending
+    work_path: PathBuf,
+    appending: bool,
+    entities: bool,
+    /// attribute values from the token columns, when `COLUMN` is used
+    columns: Option<ColumnMap>,
+    /// number of non-dynamic positional attributes
+    nattrs: usize,
+    attrs: Vec<AttrWriter>,
+    parallel: bool,
+    build_revs: bool,
+    workers: Vec<Worker>,
+    /// tokens not yet sent to the workers
+    batch: Vec<Vec<String>>,
+    structs: HashMap<String, StructWriter>,
+    start_pos: u32,
+    pos: u32,
+    open_structs: Vec<OpenStruct>,
+    lineno: u64,
+    err_open_same_str: EncErr,
+    err_closing_str: EncErr,
+    err_mismatch_str: EncErr,
+    err_unterminated: EncErr,
+    strict: Option<Report>,
+    max_value_len: usize,
+    unknown_struct: UnknownStruct,
+    auto_attr: AttrOptions,
+    /// structures declared on the fly with their attributes, in order
+    auto_structs: Vec<(String, Vec<String>)>,
+    err_unknown_str: EncErr,
+    /// structure recording glue tags, `GLUESTRUCT`
+    glue_struct: String,
+}
+
+/// Memory limit of an attribute's lexicon writer, `LEXMEMLIMIT` of the
+/// attribute or the corpus, in bytes with an optional K, M or G suffix;
+/// 0 disables spilling to disk.
+pub fn lex_mem_limit(conf: &Block, attr: &Block) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    let Some(val) = attr.value("LEXMEMLIMIT").or(conf.value("LEXMEMLIMIT")) else {
+        return Ok(Some(wrlex::DEFAULT_MEM_LIMIT));
+    };
+    let val = val.trim();
+    let (num, mult) = match val.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&val[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&val[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&val[..i], 1 << 30),
+        _ => (val, 1),
+    };
+    let n: usize = num.trim().parse().map_err(|_| format!("bad LEXMEMLIMIT: {}", val))?;
+    Ok(if n == 0 { None } else { Some(n * mult) })
+}
+
+fn timestamp_iso_utc() -> String {
+    Utc::now().to_rfc3339()
+}
+
+impl Encoder {
+    /// Starts a new corpus in `out_path`, replacing any files there.
+    pub fn new(conf: &Block, out_path: &Path) -> Result<Encoder, Box<dyn std::error::Error>> {
+        fs::create_dir_all(out_path)?;
+        Encoder::open(conf, out_path, out_path.to_path_buf(), false)
+    }
+
+    /// Opens the corpus compiled in `out_path` for appending; the new
+    /// positions follow the existing ones.
+    pub fn append(conf: &Block, out_path: &Path) -> Result<Encoder, Box<dyn std::error::Error>> {
+        complete_append(out_path)?;
+        let work_path = out_path.join(APPEND_DIR);
+        if work_path.exists() {
+            fs::remove_dir_all(&work_path)?;
+        }
+        fs::create_dir_all(&work_path)?;
+        Encoder::open(conf, out_path, work_path, true)
+    }
+
+    fn open(conf: &Block, out_path: &Path, work_path: PathBuf, appending: bool)
+            -> Result<Encoder, Box<dyn std::error::Error>> {
+        let segment_size = conf
+            .value("SEGMENTSIZE")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(wrtext::DEFAULT_SEGMENT_SIZE);
+        let existing = |name: &str| -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+            if !appending {
+                return Ok(None);
+            }
+            let from = out_path.join(name);
+            if !add_suffix(&from, ".lex").exists() {
+                return Err(format!("{} not found in the corpus appended to", name).into());
+            }
+            Ok(Some(from))
+        };
+
+        let mut attrs: Vec<AttrWriter> = Vec::new();
+        for name in conf.attrnames_in_order() {
+            let block = conf.attribute(name).ok_or("attribute not found")?;
+            if block.value("DYNAMIC").is_some() {
+                continue;
+            }
+            let tt = TextType::from_conf(block);
+            let default_value = block
+                .value("DEFAULTVALUE")
+                .unwrap_or("===NONE===")
+                .to_string();
+            let base = work_path.join(name);
+            let from = existing(name)?;
+            let opts = AttrOptions::from_conf(conf, block)?;
+            let lex = LexWriter::open(&base, from.as_deref(), opts.lex_mem_limit)?;
+            let text = match from {
+                Some(from) => wrtext::append_text_writer(&from, &base, tt)?,
+                None => wrtext::new_text_writer(&base, tt, segment_size)?,
+            };
+            attrs.push(AttrWriter { name: name.to_string(), lex, text, default_value, opts });
+        }
+        let start_pos = attrs.first().map_or(0, |a| a.text.count());
+        if attrs.iter().any(|a| a.text.count() != start_pos) {
+            return Err("attribute texts differ in length".into());
+        }
+        let start_pos = u32::try_from(start_pos).map_err(|_| "corpus too large")?;
+
+        let mut structs: HashMap<String, StructWriter> = HashMap::new();
+        for sname in conf.structnames_in_order() {
+            let sblock = conf.structure(sname).ok_or("structure not found")?;
+            let type64 = matches!(sblock.value("TYPE"), Some("file64") | Some("map64"));
+            let mut sattrs = Vec::new();
+            for aname in sblock.attrnames_in_order() {
+                let sa = format!("{}.{}", sname, aname);
+                let base = work_path.join(&sa);
+                let ablock = sblock.attribute(aname).ok_or("structure attribute not found")?;
+                let default_value = ablock
+                    .value("DEFAULTVALUE")
+                    .unwrap_or("===NONE===")
+                    .to_string();
+                sattrs.push(StructAttrWriter::new(aname, &base, existing(&sa)?.as_deref(),
+                                                  default_value, AttrOptions::from_conf(conf, ablock)?)?);
+            }
+            let rng_path = add_suffix(&work_path.join(sname), ".rng");
+            let (rng, count) = if appending {
+                fs::copy(add_suffix(&out_path.join(sname), ".rng"), &rng_path)?;
+                let f = OpenOptions::new().append(true).open(&rng_path)?;
+                let count = f.metadata()?.len() / if type64 { 16 } else { 8 };
+                (f, u32::try_from(count).map_err(|_| "structure count overflow")?)
+            } else {
+                (File::create(&rng_path)?, 0)
+            };
+            structs.insert(
+                sname.to_string(),
+                StructWriter {
+                    type64,
+                    rng: BufWriter::new(rng),
+                    count,
+                    start_count: count,
+                    attrs: sattrs,
+                    last_start_pos: None,
+                    pending_empty_pos: None,
+                    pending_empty_vals: None,
+                },
+            );
+        }
+
+        Ok(Encoder {
+            out_path: out_path.to_path_buf(),
+            work_path,
+            appending,
+            entities: conf_bool(conf, "XMLENTITIES"),
+            columns: ColumnMap::from_conf(conf)?,
+            nattrs: attrs.len(),
+            attrs,
+            parallel: false,
+            build_revs: false,
+            workers: Vec::new(),
+            batch: Vec::new(),
+            structs,
+            start_pos,
+            pos: start_pos,
+            open_structs: Vec::new(),
+            lineno: 0,
+            err_open_same_str: EncErr::new("structure opened multiple times on same position"),
+            err_closing_str: EncErr::new("closing non opened structure"),
+            err_mismatch_str: EncErr::new("mismatched closing structure"),
+            err_unterminated: EncErr::new("unterminated structure tags"),
+            strict: None,
+            max_value_len: match conf.value("MAXVALUELEN") {
+                Some(v) => v.parse().map_err(|_| format!("bad MAXVALUELEN: {}", v))?,
+                None => DEFAULT_MAX_VALUE_LEN,
+            },
+            unknown_struct: UnknownStruct::from_conf(conf)?,
+            auto_attr: AttrOptions {
+                multisep: None, numeric: false, dates: None, ..AttrOptions::from_conf(conf, conf)? },
+            auto_structs: Vec::new(),
+            err_unknown_str: EncErr::new("undeclared structure"),
+            glue_struct: conf.value("GLUESTRUCT").unwrap_or("g").to_string(),
+        })
+    }
+
+    /// Writes each positional attribute in a separate thread.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    /// Builds the rev files of positional and structure attributes as soon
+    /// as their text is complete. Existing revs are always updated when
+    /// appending.
+    pub fn set_build_revs(&mut self, build_revs: bool) {
+        self.build_revs = build_revs;
+    }
+
+    /// Checks the input strictly, writing violations to `report`:
+    /// unreadable lines are skipped instead of being read leniently, and
+    /// [`Encoder::finish`] fails if any violation was found.
+    pub fn set_strict(&mut self, report: Box<dyn Write + Send>) {
+        self.strict = Some(Report { out: report, max_value_len: self.max_value_len, count: 0 });
+    }
+
+    /// Number of strict mode violations found so far.
+    pub fn violations(&self) -> u64 {
+        self.strict.as_ref().map_or(0, |r| r.count)
+    }
+
+    fn rev_job(&self, name: &str, old_size: u64, multisep: &Option<String>)
+            -> RevJob {
+        let from = self.out_path.join(name);
+        RevJob {
+            base: self.work_path.join(name),
+            multisep: multisep.clone(),
+            from: (self.appending && add_suffix(&from, ".rev").exists()).then_some(from),
+            old_size,
+            build: self.build_revs,
+        }
+    }
+
+    fn spawn_workers(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let start_pos = self.start_pos;
+        for (i, mut attr) in std::mem::take(&mut self.attrs).into_iter().enumerate() {
+            let rev = self.rev_job(&attr.name, start_pos as u64, &attr.opts.multisep);
+            let name = attr.name.clone();
+            let (tx, rx) = sync_channel::<Arc<Vec<Vec<String>>>>(BATCH_QUEUE);
+            let handle = thread::Builder::new()
+                .name(format!("encode-{}", name))
+                .spawn(move || -> Result<(), String> {
+                    for batch in rx {
+                        for fields in batch.iter() {
+                            attr.push_field(fields, i).map_err(|e| e.to_string())?;
+                        }
+                    }
+                    attr.finalize(&rev).map_err(|e| e.to_string())
+                })?;
+            self.workers.push(Worker { name, tx, handle });
+        }
+        Ok(())
+    }
+
+    fn send_batch(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = Arc::new(std::mem::take(&mut self.batch));
+        for i in 0..self.workers.len() {
+            if self.workers[i].tx.send(batch.clone()).is_err() {
+                // the worker stopped on an error, which joining it returns
+                join_worker(self.workers.remove(i))?;
+                return Err("attribute writer stopped".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of corpus positions encoded so far.
+    pub fn size(&self) -> u64 {
+        self.pos as u64
+    }
+
+    /// Encodes a vertical file.
+    pub fn encode<R: BufRead>(&mut self, reader: R) -> Result<(), Box<dyn std::error::Error>> {
+        if self.parallel && self.workers.is_empty() {
+            self.spawn_workers()?;
+        }
+        if let Some(columns) = &mut self.columns {
+            columns.reset();
+        }
+        let parser = vert::Parser::with_options(reader, vert::Options {
+            entities: self.entities,
+            malformed: if self.strict.is_some() {
+                vert::Malformed::Error
+            } else {
+                vert::Malformed::Lenient
+            },
+            structures: match self.unknown_struct {
+                UnknownStruct::Token => Some(self.structs.keys().cloned().collect()),
+                _ => None,
+            },
+            // the rest would be read as tokens in the token mode too
+            xml_names: true,
+            glue: self.glue_struct.clone(),
+        });
+        for line in parser {
+            let line = match (line, self.strict.as_mut()) {
+                (Ok(line), _) => line,
+                (Err(e), Some(report)) if e.kind != vert::ErrorKind::Io => {
+                    report.emit(e.line, e.kind.as_str(), &e.text)?;
+                    continue;
+                }
+                (Err(e), _) => return Err(e.into()),
+            };
+            self.lineno = line.num;
+            if self.lineno.is_multiple_of(STATUS_EVERY_LINES) {
+                let ts = timestamp_iso_utc();
+                eprintln!(
+                    "encodevert: status [{}] line {}, position {}",
+                    ts, self.lineno, self.pos
+                );
+            }
+            self.event(line.event)?;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: Event) -> Result<(), Box<dyn std::error::Error>> {
+        let pos = self.pos;
+        let lineno = self.lineno;
+        let (name, tag_attrs, self_close) = match event {
+            Event::Token(fields) => {
+                if self.strict.is_some() {
+                    self.check_token(&fields, lineno)?;
+                }
+                let fields = match &self.columns {
+                    Some(columns) => columns.apply(&fields)
+                        .map_err(|e| format!("line {}: {}", lineno, e))?,
+                    None => fields,
+                };
+                // lines are trimmed, so only a mapped word can be empty
+                if let Some(report) = self.strict.as_mut()
+                        && fields.first().is_none_or(|f| f.is_empty()) {
+                    report.emit(lineno, "empty_token", &fields.join("\t"))?;
+                }
+                if self.workers.is_empty() {
+                    push_token(&mut self.attrs, &fields)?;
+                } else {
+                    self.batch.push(fields);
+                    if self.batch.len() >= BATCH_TOKENS {
+                        self.send_batch()?;
+                    }
+                }
+                flush_all_pending_at_pos(&mut self.structs, pos as u64)?;
+                self.pos = pos.checked_add(1).ok_or("corpus too large")?;
+                return Ok(());
+            }
+            Event::Comment(text) => {
+                if let Some(columns) = &mut self.columns && columns.needs_header() {
+                    columns.header(&text);
+                }
+                return Ok(());
+            }
+            Event::Glue => (self.glue_struct.clone(), Vec::new(), true),
+            Event::Empty { name, attrs } => (name, attrs, true),
+            Event::Open { name, attrs } => (name, attrs, false),
+            Event::Close { name } => {
+                if !self.known_struct(&name, &[], lineno)? {
+                    return Ok(());
+                }
+                if let Some(sb) = self.structs.get_mut(&name)
+                    && let Some(pend_pos) = sb.pending_empty_pos
+                    && pend_pos != pos as u64 {
+                    flush_pending_empty(sb)?;
+                }
+                let open = match self.open_structs.pop() {
+                    Some(v) => v,
+                    None => {
+                        if let Some(report) = self.strict.as_mut() {
+                            report.emit(lineno, "unopened_tag", &format!("</{}>", name))?;
+                        }
+                        self.err_closing_str.emit(
+                            lineno,
+                            &format!("closing non opened structure ({})", name),
+                        );
+                        // keep stack as-is
+                        // and ignore this end tag
+                        return Ok(());
+                    }
+                };
+                if open.name != name {
+                    if let Some(report) = self.strict.as_mut() {
+                        report.emit(lineno, "mismatched_tag",
+                                    &format!("</{}> closing <{}>", name, open.name))?;
+                    }
+                    self.err_mismatch_str.emit(
+                        lineno,
+                        &format!("mismatched closing structure ({})", name),
+                    );
+                    self.open_structs.push(open);
+                    return Ok(());
+                }
+                let sb = self.structs.get_mut(&name).ok_or("structure not found")?;
+                let beg = open.start;
+                let end = pos as u64;
+                if sb.type64 {
+                    sb.rng.write_all(&beg.to_le_bytes())?;
+                    sb.rng.write_all(&end.to_le_bytes())?;
+                } else {
+                    sb.rng.write_all(&(beg as u32).to_le_bytes())?;
+                    sb.rng.write_all(&(end as u32).to_le_bytes())?;
+                }
+                let struct_pos = sb.count;
+                sb.count = sb.count.checked_add(1).ok_or("structure count overflow")?;
+                push_struct_values(&mut sb.attrs, &open.attr_values, struct_pos)?;
+                return Ok(());
+            }
+        };
+        if !self.known_struct(&name, &tag_attrs, lineno)? {
+            return Ok(());
+        }
+
+        let sb = self.structs.get_mut(&name).ok_or("structure not found")?;
+        if let Some(pend_pos) = sb.pending_empty_pos
+            && pend_pos != pos as u64 {
+            flush_pending_empty(sb)?;
+        }
+        if sb.last_start_pos == Some(pos as u64) {
+            self.err_open_same_str.emit(
+                lineno,
+                &format!(
+                    "opening structure ({}) on the same position, ignoring the previous empty one",
+                    name
+                ),
+            );
+        }
+        if let Some(report) = self.strict.as_mut() {
+            for (k, v) in &tag_attrs {
+                if !sb.attrs.iter().any(|a| a.name == *k) {
+                    report.emit(lineno, "undeclared_attribute", &format!("{}.{}", name, k))?;
+                }
+                report.check_value(lineno, v)?;
+            }
+        }
+        let mut attr_values = Vec::new();
+        for attr in &mut sb.attrs {
+            let val = tag_attrs
+                .iter()
+                .rev()
+                .find(|(k, _)| *k == attr.name)
+                .map(|(_, v)| v.as_str())
+                .unwrap_or(&attr.default_value);
+            attr_values.push(val.to_string());
+        }
+        sb.last_start_pos = Some(pos as u64);
+        if self_close {
+            sb.pending_empty_pos = Some(pos as u64);
+            sb.pending_empty_vals = Some(attr_values);
+        } else {
+            if sb.pending_empty_pos == Some(pos as u64) {
+                sb.pending_empty_pos = None;
+                sb.pending_empty_vals = None;
+            }
+            self.open_structs.push(OpenStruct { name, line: lineno, start: pos as u64, attr_values });
+        }
+        Ok(())
+    }
+
+    /// Whether tags of structure `name` are encoded, declaring the
+    /// structure and its attributes `tag_attrs` if configured so.
+    fn known_struct(&mut self, name: &str, tag_attrs: &[(String, String)], line: u64)
+            -> Result<bool, Box<dyn std::error::Error>> {
+        let auto = self.auto_structs.iter().position(|(s, _)| s == name);
+        if self.structs.contains_key(name) && auto.is_none() {
+            return Ok(true);
+        }
+        if self.unknown_struct != UnknownStruct::Declare {
+            if let Some(report) = self.strict.as_mut() {
+                report.emit(line, "undeclared_structure", name)?;
+            }
+            if self.unknown_struct == UnknownStruct::Warn {
+                self.err_unknown_str.emit(line, &format!("undeclared structure ({}) ignored", name));
+            }
+            return Ok(false);
+        }
+        let auto = match auto {
+            Some(i) => i,
+            None => {
+                self.declare_struct(name)?;
+                self.auto_structs.len() - 1
+            }
+        };
+        for (aname, _) in tag_attrs {
+            if !self.auto_structs[auto].1.contains(aname) {
+                self.declare_struct_attr(auto, aname)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn declare_struct(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if name.is_empty() || name.contains(['/', '.']) {
+            return Err(format!("cannot declare structure named {:?}", name).into());
+        }
+        if self.appending && add_suffix(&self.out_path.join(name), ".rng").exists() {
+            return Err(format!("structure {} exists in the corpus but not in the config, \
+                                see {}", name, AUTO_STRUCT_CONF).into());
+        }
+        let rng = File::create(add_suffix(&self.work_path.join(name), ".rng"))?;
+        self.structs.insert(name.to_string(), StructWriter {
+            type64: false,
+            rng: BufWriter::new(rng),
+            count: 0,
+            start_count: 0,
+            attrs: Vec::new(),
+            last_start_pos: None,
+            pending_empty_pos: None,
+            pending_empty_vals: None,
+        });
+        self.auto_structs.push((name.to_string(), Vec::new()));
+        Ok(())
+    }
+
+    /// Adds an attribute to a structure declared on the fly; structures
+    /// written before get the default value.
+    fn declare_struct_attr(&mut self, auto: usize, aname: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let sname = self.auto_structs[auto].0.clone();
+        if aname.is_empty() || aname.contains(['/', '.']) {
+            return Err(format!("cannot declare attribute {}.{:?}", sname, aname).into());
+        }
+        let base = self.work_path.join(format!("{}.{}", sname, aname));
+        let mut attr = StructAttrWriter::new(aname, &base, None, "===NONE===".to_string(),
+                                             self.auto_attr.clone())?;
+        let sb = self.structs.get_mut(&sname).ok_or("structure not found")?;
+        if sb.count > 0 {
+            let id = attr.id_for("===NONE===")?;
+            for num in 0..sb.count {
+                attr.push_value(id, num)?;
+            }
+        }
+        sb.attrs.push(attr);
+        self.auto_structs[auto].1.push(aname.to_string());
+        Ok(())
+    }
+
+    /// Writes the config snippet for the structures declared on the fly.
+    fn write_auto_conf(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = String::new();
+        for (sname, attrs) in &self.auto_structs {
+            if attrs.is_empty() {
+                out.push_str(&format!("STRUCTURE {}\n", sname));
+                continue;
+            }
+            out.push_str(&format!("STRUCTURE {} {{\n", sname));
+            for aname in attrs {
+                out.push_str(&format!("    ATTRIBUTE {}\n", aname));
+            }
+            out.push_str("}\n");
+        }
+        fs::write(self.work_path.join(AUTO_STRUCT_CONF), out)?;
+        Ok(())
+    }
+
+    /// Reports a token line whose fields do not fit the attributes.
+    fn check_token(&mut self, fields: &[String], line: u64) -> Result<(), Box<dyn std::error::Error>> {
+        // with COLUMN, extra columns are skipped rather than wrong
+        let (width, exact) = match &self.columns {
+            Some(columns) => (columns.width(), false),
+            None => (Some(self.nattrs), true),
+        };
+        let Some(report) = self.strict.as_mut() else { return Ok(()) };
+        // tags of undeclared structures are read as tokens
+        if let [f] = fields && f.starts_with('<') && f.ends_with('>') {
+            return report.emit(line, "undeclared_structure", f);
+        }
+        if let Some(width) = width
+                && (fields.len() < width || (exact && fields.len() > width)) {
+            report.emit(line, "field_count",
+                        &format!("{} fields, expected {}: {}", fields.len(), width, fields.join("\t")))?;
+        }
+        for f in fields {
+            report.check_value(line, f)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining data and, when appending, merges the revs and
+    /// moves the new files into the corpus directory.
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(report) = self.strict.as_mut() {
+            for open in &self.open_structs {
+                report.emit(open.line, "unclosed_tag", &format!("<{}>", open.name))?;
+            }
+        }
+        if !self.open_structs.is_empty() {
+            self.err_unterminated.emit(
+                self.lineno,
+                &format!("{} unterminated structure tags ignored", self.open_structs.len()),
+            );
+        }
+        flush_all_pending_at_pos(&mut self.structs, self.pos as u64)?;
+
+        let mut names = Vec::new();
+        self.send_batch()?;
+        for w in std::mem::take(&mut self.workers) {
+            names.push(join_worker(w)?);
+        }
+        for attr in std::mem::take(&mut self.attrs) {
+            let rev = self.rev_job(&attr.name, self.start_pos as u64, &attr.opts.multisep);
+            names.push(attr.name.clone());
+            attr.finalize(&rev)?;
+        }
+
+        let mut structs = std::mem::take(&mut self.structs);
+        for (sname, sb) in structs.iter_mut() {
+            sb.rng.flush()?;
+            for attr in sb.attrs.drain(..) {
+                let name = format!("{}.{}", sname, attr.name);
+                attr.lex.finalize()?;
+                attr.opts.indexes.write(&self.work_path.join(&name))?;
+                Box::new(attr.text).finalize()?;
+                if attr.opts.numeric {
+                    numeric::write_index(&self.work_path.join(&name))?;
+                }
+                if let Some(parser) = &attr.opts.dates {
+                    dates::write_index(&self.work_path.join(&name), parser)?;
+                }
+                self.rev_job(&name, sb.start_count as u64, &attr.opts.multisep).run()?;
+                names.push(name);
+            }
+        }
+        self.err_open_same_str.summary();
+        self.err_closing_str.summary();
+        self.err_mismatch_str.summary();
+        self.err_unterminated.summary();
+        self.err_unknown_str.summary();
+        if !self.auto_structs.is_empty() {
+            self.write_auto_conf()?;
+        }
+
+        if let Some(report) = self.strict.as_mut() {
+            report.out.flush()?;
+            if report.count > 0 {
+                if self.appending {
+                    fs::remove_dir_all(&self.work_path)?;
+                    return Err(format!("{} strict mode violations, nothing appended",
+                                       report.count).into());
+                }
+                return Err(format!("{} strict mode violations", report.count).into());
+            }
+        }
+        if self.appending {
+            commit_append(&self.work_path, &self.out_path, &names)?;
+        }
+        Ok(())
+    }
+}
+
+/// Waits for the worker to write its attribute; returns the attribute name.
+fn join_worker(w: Worker) -> Result<String, Box<dyn std::error::Error>> {
+    drop(w.tx);
+    w.handle.join().map_err(|_| format!("attribute {}: writer panicked", w.name))?
+        .map_err(|e| format!("attribute {}: {}", w.name, e))?;
+    Ok(w.name)
+}
+
+/// Moves the staged files into the corpus. The list of files made stale
+/// by the append is written to [`APPEND_COMMIT`] first; once it exists the
+/// append counts as done and [`complete_append`] finishes it, also when
+/// the process dies half way.
+fn commit_append(work_path: &Path, out_path: &Path, names: &[String])
+        -> Result<(), Box<dyn std::error::Error>> {
+    let mut stale = Vec::new();
+    for name in names {
+        // optional lexicon indexes are only kept while configured
+        for suffix in [".lex.lsrt", ".lex.fld", ".nidx", ".didx"] {
+            let old = add_suffix(&out_path.join(name), suffix);
+            if old.exists() && !add_suffix(&work_path.join(name), suffix).exists() {
+                stale.push(format!("{}{}", name, suffix));
+            }
+        }
+        for suffix in [".frq", ".frq64"] {
+            if add_suffix(&out_path.join(name), suffix).exists() {
+                stale.push(format!("{}{}", name, suffix));
+            }
+        }
+    }
+    let marker = out_path.join(APPEND_COMMIT);
+    let tmp = add_suffix(&marker, ".tmp");
+    let mut f = File::create(&tmp)?;
+    for name in &stale {
+        writeln!(f, "{}", name)?;
+    }
+    f.sync_all()?;
+    fs::rename(tmp, marker)?;
+    complete_append(out_path)?;
+    Ok(())
+}
+
+/// Finishes an append to the corpus in `out_path` whose commit marker is
+/// present: renames the staged files over the corpus files, lexicons first
+/// since ids in the other files refer to them, and removes the stale ones.
+/// Every step tolerates having been done already, so readers may call this
+/// concurrently with the encoder. Returns whether there was anything to do.
+pub fn complete_append(out_path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let marker = out_path.join(APPEND_COMMIT);
+    let stale = match fs::read_to_string(&marker) {
+        Ok(s) => s,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let work_path = out_path.join(APPEND_DIR);
+    let mut files: Vec<_> = match fs::read_dir(&work_path) {
+        Ok(dir) => dir.map(|e| e.map(|e| e.file_name())).collect::<Result<_, _>>()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    files.sort_by_key(|f| !f.to_string_lossy().contains(".lex"));
+    for f in files {
+        done_if_missing(fs::rename(work_path.join(&f), out_path.join(&f)))?;
+    }
+    for name in stale.lines().filter(|l| !l.is_empty()) {
+        done_if_missing(fs::remove_file(out_path.join(name)))?;
+    }
+    done_if_missing(fs::remove_dir(&work_path))?;
+    done_if_missing(fs::remove_file(&marker))?;
+    Ok(true)
+}
+
+fn done_if_missing(res: std::io::Result<()>) -> std::io::Result<()> {
+    match res {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+fn push_token(attrs: &mut [AttrWriter], fields: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    for (i, attr) in attrs.iter_mut().enumerate() {
+        attr.push_field(fields, i)?;
+    }
+    Ok(())
+}
+
+fn flush_pending_empty(sb: &mut StructWriter) -> Result<(), Box<dyn std::error::Error>> {
+    let pos = match sb.pending_empty_pos.take() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let vals = match sb.pending_empty_vals.take() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    if sb.type64 {
+        sb.rng.write_all(&pos.to_le_bytes())?;
+        sb.rng.write_all(&pos.to_le_bytes())?;
+    } else {
+        sb.rng.write_all(&(pos as u32).to_le_bytes())?;
+        sb.rng.write_all(&(pos as u32).to_le_bytes())?;
+    }
+    let struct_pos = sb.count;
+    sb.count = sb.count.checked_add(1).ok_or("structure count overflow")?;
+    push_struct_values(&mut sb.attrs, &vals, struct_pos)
+}
+
+/// Adds the attribute values of structure `num`; attributes declared after
+/// the values were collected get their default value.
+fn push_struct_values(attrs: &mut [StructAttrWriter], vals: &[String], num: u32)
+        -> Result<(), Box<dyn std::error::Error>> {
+    for (i, attr) in attrs.iter_mut().enumerate() {
+        let id = match vals.get(i) {
+            Some(val) => attr.id_for(val)?,
+            None => {
+                let dv = attr.default_value.clone();
+                attr.id_for(&dv)?
+            }
+        };
+        attr.push_value(id, num)?;
+    }
+    Ok(())
+}
+
+fn flush_all_pending_at_pos(
+    structs: &mut HashMap<String, StructWriter>,
+    pos: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for sb in structs.values_mut() {
+        if sb.pending_empty_pos == Some(pos) {
+            flush_pending_empty(sb)?;
+        }
+    }
+    Ok(())
+}
@@ -5,6 +5,8 @@ use std::cmp::Ordering;
 
 use memmap::MmapOptions;
 
+use crate::collate::{self, Collator, Strength};
+
 #[inline]
 pub fn read<T: Sized>(mmap: &memmap::Mmap, idx: usize) -> T {
     let x = mmap.as_ptr() as *const T;
@@ -17,6 +19,8 @@ pub struct MapLex {
     lex: memmap::Mmap,
     srt: memmap::Mmap,
     idx: memmap::Mmap,
+    lsrt: Option<memmap::Mmap>,
+    fld: Option<memmap::Mmap>,
 }
 
 impl MapLex {
@@ -26,11 +30,18 @@ impl MapLex {
             unsafe { MmapOptions::new().map(f.file()) }
         };
 
+        let open_opt = |name| match std::fs::metadata(base.to_string() + name) {
+            Ok(m) if m.len() > 0 => open_map(name).map(Some),
+            _ => Ok(None),
+        };
+
         Ok(MapLex{
             name: base.to_string(),
             lex: open_map(".lex")?,
             srt: open_map(".lex.srt")?,
             idx: open_map(".lex.idx")?,
+            lsrt: open_opt(".lex.lsrt")?,
+            fld: open_opt(".lex.fld")?,
         })
     }
 
@@ -48,14 +59,14 @@ impl MapLex {
 
     pub fn str2id(&self, s: &str) -> Option<u32> {
         let mut bot = 0;
-        let mut top = (self.srt.len() / 4) as u32 - 1;
-        while bot <= top {
-            let cur_id = (top + bot) / 2;
+        let mut top = (self.srt.len() / 4) as u32;
+        while bot < top {
+            let cur_id = bot + (top - bot) / 2;
             let sort_id: u32 = read(&self.srt, cur_id as usize);
             let q = self.id2str(sort_id);
             match q.cmp(s) {
                 Ordering::Less => bot = cur_id + 1,
-                Ordering::Greater => top = cur_id - 1,
+                Ordering::Greater => top = cur_id,
                 Ordering::Equal => return Some(sort_id),
             }
         }
@@ -65,4 +76,64 @@ impl MapLex {
     pub fn id_range(&self) -> u32 {
         (self.srt.len() / 4) as u32
     }
+
+    /// Ids in the locale order of `.lex.lsrt`, if the lexicon has one.
+    pub fn iter_collated(&self) -> Option<impl Iterator<Item = u32> + '_> {
+        let lsrt = self.lsrt.as_ref()?;
+        Some((0..lsrt.len() / 4).map(move |i| read::<u32>(lsrt, i)))
+    }
+
+    /// Ids of the strings equal to `s` at the given strength, e.g. all
+    /// case and diacritic variants for `Strength::Primary`. `coll` has to
+    /// be the collator `.lex.lsrt` was built with; empty without the file.
+    pub fn find_collated(&self, coll: &Collator, s: &str, strength: Strength) -> Vec<u32> {
+        let Some(lsrt) = self.lsrt.as_ref() else { return Vec::new() };
+        let key = coll.sort_key(s);
+        let at = |i: usize| read::<u32>(lsrt, i);
+        let cmp = |i: usize| coll.sort_key(self.id2str(at(i))).cmp_at(&key, strength);
+        let (mut bot, mut top) = (0, lsrt.len() / 4);
+        while bot < top {
+            let mid = bot + (top - bot) / 2;
+            if cmp(mid) == Ordering::Less { bot = mid + 1 } else { top = mid }
+        }
+        (bot..lsrt.len() / 4)
+            .take_while(|&i| cmp(i) == Ordering::Equal)
+            .map(at)
+            .collect()
+    }
+
+    /// Ids of the strings equal to `s` ignoring case and diacritics.
+    pub fn str2ids_folded(&self, s: &str) -> Vec<u32> {
+        self.str2ids(s, "cd")
+    }
+
+    /// Ids matching `s` under query flags: `c` ignores case, `d` ignores
+    /// diacritics, other flags are ignored. Uses `.lex.fld` if present and
+    /// scans the whole lexicon otherwise.
+    pub fn str2ids(&self, s: &str, flags: &str) -> Vec<u32> {
+        let (icase, idiac) = (flags.contains('c'), flags.contains('d'));
+        if !icase && !idiac {
+            return self.str2id(s).into_iter().collect();
+        }
+        let want = collate::fold(s, icase, idiac);
+        let matches = |id: &u32| collate::fold(self.id2str(*id), icase, idiac) == want;
+        let Some(fld) = self.fld.as_ref() else {
+            return (0..self.id_range()).filter(matches).collect();
+        };
+        let key = collate::fold(s, true, true);
+        let at = |i: usize| read::<u32>(fld, i);
+        let folded = |i: usize| collate::fold(self.id2str(at(i)), true, true);
+        let (mut bot, mut top) = (0, fld.len() / 4);
+        while bot < top {
+            let mid = bot + (top - bot) / 2;
+            if folded(mid) < key { bot = mid + 1 } else { top = mid }
+        }
+        let mut ids: Vec<u32> = (bot..fld.len() / 4)
+            .take_while(|&i| folded(i) == key)
+            .map(at)
+            .filter(matches)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
 }
@@ -6,4 +6,19 @@ pub mod text;
 pub mod bits;
 pub mod wsketch;
 pub mod wrbits;
+pub mod wrlex;
+pub mod wrtext;
+pub mod wrrev;
+pub mod vert;
+pub mod decode;
+pub mod columns;
+pub mod encode;
+pub mod merge;
+pub mod tombstone;
+pub mod collate;
+pub mod normalize;
+pub mod numeric;
+pub mod dates;
+pub mod trends;
+pub mod mem;
 mod util;
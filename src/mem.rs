@@ -0,0 +1,511 @@
+//! In-memory corpora.
+//!
+//! [`MemBuilder`] collects tokens and structure spans (or reads them from
+//! a vertical file) and builds a [`Corpus`] whose attributes and
+//! structures live in memory, with no files and no registry entry. Texts
+//! and revs are delta-coded the way the encoder writes them, so the
+//! attributes implement [`Text`] and [`Rev`] like the ones on disk. Lexicon
+//! ids are assigned in the order of first occurrence, structures are
+//! numbered in the order in which they end and missing values are
+//! `===NONE===`, as with `encodevert`. Dynamic attributes are not
+//! available; numeric and date indexes of the structure attributes set up
+//! with [`MemBuilder::option`] are built when opened.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::sync::Arc;
+
+use crate::bits;
+use crate::collate;
+use crate::corp::{Attr, Corpus, Frequency};
+use crate::rev::{Rev, RevIter};
+use crate::structure::Struct;
+use crate::text::{DeltaIter, IntIter, Text};
+use crate::vert::{self, Event};
+use crate::wrbits::BitsWriter;
+
+type Error = Box<dyn std::error::Error>;
+
+const DEFAULT_VALUE: &str = "===NONE===";
+
+/// Bytes written by a [`BitsWriter`] as the atoms of a [`bits::Reader`].
+fn atoms(bytes: Vec<u8>) -> Vec<u64> {
+    bytes.chunks(8).map(|c| {
+        let mut atom = [0u8; 8];
+        atom[..c.len()].copy_from_slice(c);
+        u64::from_le_bytes(atom)
+    }).collect()
+}
+
+#[derive(Debug, Default)]
+struct MemLex {
+    strs: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl MemLex {
+    fn add(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.ids.get(s) {
+            return id;
+        }
+        let id = self.strs.len() as u32;
+        self.strs.push(s.to_string());
+        self.ids.insert(s.to_string(), id);
+        id
+    }
+}
+
+/// Delta-coded ids with the bit offset of every 64th position.
+pub struct MemText {
+    bits: Vec<u64>,
+    offsets: Vec<usize>,
+    positions: usize,
+}
+
+impl MemText {
+    fn new(ids: &[u32]) -> Result<MemText, Error> {
+        let mut bw = BitsWriter::new(Vec::new());
+        let mut offsets = Vec::with_capacity(ids.len() / 64 + 1);
+        for (pos, &id) in ids.iter().enumerate() {
+            if pos % 64 == 0 {
+                offsets.push(bw.bits_written() as usize);
+            }
+            bw.delta(id as u64 + 1);
+        }
+        Ok(MemText { bits: atoms(bw.finish()?), offsets, positions: ids.len() })
+    }
+
+    pub fn at(&self, pos: u64) -> DeltaIter<'_> {
+        let pos = pos.min(self.positions as u64);
+        let seek = self.offsets.get(pos as usize / 64).copied().unwrap_or(0);
+        let mut rb = bits::Reader::open(&self.bits, seek);
+        for _ in 0..pos % 64 {
+            rb.delta();
+        }
+        DeltaIter { remaining: self.positions as u64 - pos, rb }
+    }
+}
+
+impl fmt::Debug for MemText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemText").field("positions", &self.positions).finish()
+    }
+}
+
+impl Text for MemText {
+    fn posat(&self, pos: u64) -> Option<DeltaIter<'_>> { Some(self.at(pos)) }
+    fn structat(&self, _pos: u64) -> Option<IntIter<'_>> { None }
+    fn size(&self) -> usize { self.positions }
+    fn get(&self, pos: u64) -> u32 { self.at(pos).next().unwrap() }
+}
+
+/// Delta-coded posting lists with the bit offset and the length of each.
+pub struct MemRev {
+    bits: Vec<u64>,
+    offsets: Vec<usize>,
+    counts: Vec<u64>,
+}
+
+impl MemRev {
+    fn new(ids: &[u32], nids: usize) -> Result<MemRev, Error> {
+        let mut poss = vec![Vec::new(); nids];
+        for (pos, &id) in ids.iter().enumerate() {
+            poss[id as usize].push(pos as u64);
+        }
+        let mut bw = BitsWriter::new(Vec::new());
+        let mut offsets = Vec::with_capacity(nids);
+        for list in &poss {
+            offsets.push(bw.bits_written() as usize);
+            let mut last = -1i64;
+            for &pos in list {
+                bw.delta((pos as i64 - last) as u64);
+                last = pos as i64;
+            }
+        }
+        Ok(MemRev {
+            bits: atoms(bw.finish()?),
+            offsets,
+            counts: poss.iter().map(|l| l.len() as u64).collect(),
+        })
+    }
+}
+
+impl fmt::Debug for MemRev {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemRev").field("ids", &self.counts.len()).finish()
+    }
+}
+
+impl Rev for MemRev {
+    fn count(&self, id: u32) -> u64 {
+        self.counts.get(id as usize).copied().unwrap_or(0)
+    }
+    fn id2poss(&self, id: u32) -> RevIter<'_> {
+        let seek = self.offsets.get(id as usize).copied().unwrap_or(0);
+        RevIter::new(DeltaIter {
+            remaining: self.count(id),
+            rb: bits::Reader::open(&self.bits, seek),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct AttrData {
+    name: String,
+    lex: MemLex,
+    text: MemText,
+    rev: MemRev,
+}
+
+#[derive(Debug)]
+struct StructData {
+    name: String,
+    ranges: Vec<(u64, u64)>,
+}
+
+/// Attributes and structures of an in-memory corpus; structure
+/// attributes are named `struct.attr`.
+pub struct MemStore {
+    attrs: Vec<AttrData>,
+    structs: Vec<StructData>,
+}
+
+impl MemStore {
+    pub(crate) fn attribute(self: &Arc<Self>, name: &str) -> Option<MemAttr> {
+        let idx = self.attrs.iter().position(|a| a.name == name)?;
+        Some(MemAttr { store: self.clone(), idx })
+    }
+
+    pub(crate) fn structure(self: &Arc<Self>, name: &str) -> Option<MemStruct> {
+        let idx = self.structs.iter().position(|s| s.name == name)?;
+        Some(MemStruct { store: self.clone(), idx })
+    }
+}
+
+impl fmt::Debug for MemStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemStore")
+            .field("attrs", &self.attrs.iter().map(|a| &a.name).collect::<Vec<_>>())
+            .field("structs", &self.structs.iter().map(|s| &s.name).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Attribute of an in-memory corpus.
+#[derive(Clone, Debug)]
+pub struct MemAttr {
+    store: Arc<MemStore>,
+    idx: usize,
+}
+
+impl MemAttr {
+    fn data(&self) -> &AttrData { &self.store.attrs[self.idx] }
+}
+
+impl Attr for MemAttr {
+    fn iter_ids(&self, frompos: u64) -> Box<dyn Iterator<Item=u32> + '_> {
+        Box::new(self.data().text.at(frompos))
+    }
+    fn id2str(&self, id: u32) -> &str { &self.data().lex.strs[id as usize] }
+    fn str2id(&self, s: &str) -> Option<u32> { self.data().lex.ids.get(s).copied() }
+    fn str2ids(&self, s: &str, flags: &str) -> Vec<u32> {
+        let (icase, idiac) = (flags.contains('c'), flags.contains('d'));
+        if !icase && !idiac {
+            return self.str2id(s).into_iter().collect();
+        }
+        let want = collate::fold(s, icase, idiac);
+        (0..self.id_range())
+            .filter(|&id| collate::fold(self.id2str(id), icase, idiac) == want)
+            .collect()
+    }
+    fn revidx(&self) -> &dyn Rev { &self.data().rev }
+    fn text(&self) -> &dyn Text { &self.data().text }
+    fn id_range(&self) -> u32 { self.data().lex.strs.len() as u32 }
+    fn get_freq(&self, t: &str) -> Result<Box<dyn Frequency + '_>, Error> {
+        match t {
+            "frq" => Ok(Box::new(self.clone())),
+            _ => Err(format!("bad frequency type: {}", t).into()),
+        }
+    }
+}
+
+impl Frequency for MemAttr {
+    fn frq(&self, id: u32) -> u64 { self.data().rev.count(id) }
+}
+
+/// Structure of an in-memory corpus.
+#[derive(Clone, Debug)]
+pub struct MemStruct {
+    store: Arc<MemStore>,
+    idx: usize,
+}
+
+impl MemStruct {
+    fn ranges(&self) -> &[(u64, u64)] { &self.store.structs[self.idx].ranges }
+}
+
+impl Struct for MemStruct {
+    fn beg_at(&self, pos: u64) -> u64 { self.ranges()[pos as usize].0 }
+    fn end_at(&self, pos: u64) -> u64 { self.ranges()[pos as usize].1 }
+    fn len(&self) -> usize { self.ranges().len() }
+}
+
+#[derive(Debug)]
+struct AttrBuilder {
+    name: String,
+    lex: MemLex,
+    ids: Vec<u32>,
+}
+
+impl AttrBuilder {
+    /// An attribute whose first `len` values are missing.
+    fn new(name: &str, len: usize) -> AttrBuilder {
+        let mut lex = MemLex::default();
+        let ids = if len > 0 { vec![lex.add(DEFAULT_VALUE); len] } else { Vec::new() };
+        AttrBuilder { name: name.to_string(), lex, ids }
+    }
+
+    fn push(&mut self, value: &str) {
+        let id = self.lex.add(value);
+        self.ids.push(id);
+    }
+
+    fn build(self, prefix: &str) -> Result<AttrData, Error> {
+        Ok(AttrData {
+            name: format!("{}{}", prefix, self.name),
+            text: MemText::new(&self.ids)?,
+            rev: MemRev::new(&self.ids, self.lex.strs.len())?,
+            lex: self.lex,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct StructBuilder {
+    name: String,
+    ranges: Vec<(u64, u64)>,
+    attrs: Vec<AttrBuilder>,
+}
+
+impl StructBuilder {
+    /// Renumbers the spans in the order of their ends and begins, which
+    /// the binary searches over the ranges rely on.
+    fn sort(&mut self) {
+        if self.ranges.is_sorted_by_key(|r| (r.1, r.0)) {
+            return;
+        }
+        let mut order: Vec<usize> = (0..self.ranges.len()).collect();
+        order.sort_by_key(|&i| (self.ranges[i].1, self.ranges[i].0));
+        self.ranges = order.iter().map(|&i| self.ranges[i]).collect();
+        for a in &mut self.attrs {
+            let mut sorted = AttrBuilder::new(&a.name, 0);
+            for &i in &order {
+                sorted.push(&a.lex.strs[a.ids[i] as usize]);
+            }
+            *a = sorted;
+        }
+    }
+}
+
+/// Structure opened by [`MemBuilder::open`].
+#[derive(Debug)]
+struct OpenSpan {
+    idx: usize,
+    beg: u64,
+    attrs: Vec<(String, String)>,
+}
+
+/// Collects the tokens and structures of an in-memory corpus.
+#[derive(Debug)]
+pub struct MemBuilder {
+    attrs: Vec<AttrBuilder>,
+    structs: Vec<StructBuilder>,
+    open: Vec<OpenSpan>,
+    positions: u64,
+    /// config options of structure attributes: attribute, key, value
+    options: Vec<(String, String, String)>,
+}
+
+impl MemBuilder {
+    /// A builder for a corpus with the positional attributes `attrs`.
+    pub fn new(attrs: &[&str]) -> MemBuilder {
+        MemBuilder {
+            attrs: attrs.iter().map(|a| AttrBuilder::new(a, 0)).collect(),
+            structs: Vec::new(),
+            open: Vec::new(),
+            positions: 0,
+            options: Vec::new(),
+        }
+    }
+
+    /// Number of tokens so far.
+    pub fn len(&self) -> u64 {
+        self.positions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions == 0
+    }
+
+    /// Appends a token with the values of the attributes in order and
+    /// returns its position; missing values are `===NONE===`, extra ones
+    /// are ignored.
+    pub fn token<S: AsRef<str>>(&mut self, values: &[S]) -> u64 {
+        for (i, a) in self.attrs.iter_mut().enumerate() {
+            a.push(values.get(i).map_or(DEFAULT_VALUE, |v| v.as_ref()));
+        }
+        self.positions += 1;
+        self.positions - 1
+    }
+
+    fn struct_idx(&mut self, name: &str) -> usize {
+        match self.structs.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                self.structs.push(StructBuilder {
+                    name: name.to_string(),
+                    ranges: Vec::new(),
+                    attrs: Vec::new(),
+                });
+                self.structs.len() - 1
+            }
+        }
+    }
+
+    /// Adds a structure `name` spanning the positions `beg..end` with the
+    /// attribute values `attrs`. Spans of a structure are numbered in the
+    /// order in which they end, those ending at the same position in the
+    /// order in which they begin; attributes missing from a span are
+    /// `===NONE===`. [`MemBuilder::build`] fails unless
+    /// `beg <= end <= len()` and the spans of a structure do not overlap.
+    pub fn span(&mut self, name: &str, beg: u64, end: u64, attrs: &[(&str, &str)]) {
+        let idx = self.struct_idx(name);
+        let s = &mut self.structs[idx];
+        let num = s.ranges.len();
+        for &(k, _) in attrs {
+            if !s.attrs.iter().any(|a| a.name == k) {
+                s.attrs.push(AttrBuilder::new(k, num));
+            }
+        }
+        for a in &mut s.attrs {
+            let value = attrs.iter().find(|&&(k, _)| k == a.name).map_or(DEFAULT_VALUE, |&(_, v)| v);
+            a.push(value);
+        }
+        s.ranges.push((beg, end));
+    }
+
+    /// Sets the config option `key` of the structure attribute `name`
+    /// (e.g. `doc.date`), such as `NUMERIC` or `DATE`.
+    pub fn option(&mut self, name: &str, key: &str, value: &str) {
+        self.options.push((name.to_string(), key.to_string(), value.to_string()));
+    }
+
+    /// Opens a structure at the current position.
+    pub fn open(&mut self, name: &str, attrs: &[(&str, &str)]) {
+        let idx = self.struct_idx(name);
+        let attrs = attrs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        self.open.push(OpenSpan { idx, beg: self.positions, attrs });
+    }
+
+    /// Closes the innermost open structure, which has to be `name`.
+    pub fn close(&mut self, name: &str) -> Result<(), Error> {
+        let open = match self.open.pop() {
+            Some(open) if self.structs[open.idx].name == name => open,
+            Some(open) => {
+                let other = &self.structs[open.idx].name;
+                let err = format!("closing {} while {} is open", name, other);
+                self.open.push(open);
+                return Err(err.into());
+            }
+            None => return Err(format!("closing non opened structure ({})", name).into()),
+        };
+        let attrs: Vec<(&str, &str)> = open.attrs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        self.span(name, open.beg, self.positions, &attrs);
+        Ok(())
+    }
+
+    /// Reads tokens and tags from a vertical file; glue tags become empty
+    /// structures `g`.
+    pub fn read_vert(&mut self, reader: impl BufRead) -> Result<(), Error> {
+        for line in vert::Parser::new(reader) {
+            let line = line?;
+            let (name, attrs, empty) = match line.event {
+                Event::Token(fields) => {
+                    self.token(&fields);
+                    continue;
+                }
+                Event::Open { name, attrs } => (name, attrs, false),
+                Event::Empty { name, attrs } => (name, attrs, true),
+                Event::Glue => ("g".to_string(), Vec::new(), true),
+                Event::Close { name } => {
+                    self.close(&name).map_err(|e| format!("line {}: {}", line.num, e))?;
+                    continue;
+                }
+                Event::Comment(_) => continue,
+            };
+            let attrs: Vec<(&str, &str)> = attrs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            if empty {
+                self.span(&name, self.positions, self.positions, &attrs);
+            } else {
+                self.open(&name, &attrs);
+            }
+        }
+        Ok(())
+    }
+
+    /// The corpus; all structures have to be closed.
+    pub fn build(mut self) -> Result<Corpus, Error> {
+        if let Some(open) = self.open.last() {
+            return Err(format!("unclosed structure ({})", self.structs[open.idx].name).into());
+        }
+        for s in &mut self.structs {
+            if let Some(&(beg, end)) = s.ranges.iter().find(|&&(b, e)| b > e || e > self.positions) {
+                return Err(format!("span {}..{} of {} outside the {} positions",
+                                   beg, end, s.name, self.positions).into());
+            }
+            s.sort();
+            if let Some(w) = s.ranges.windows(2).find(|w| w[1].0 < w[0].1) {
+                return Err(format!("overlapping spans {}..{} and {}..{} of {}",
+                                   w[0].0, w[0].1, w[1].0, w[1].1, s.name).into());
+            }
+        }
+        let mut conf = String::new();
+        if let Some(first) = self.attrs.first() {
+            conf += &format!("DEFAULTATTR \"{}\"\n", first.name);
+        }
+        for a in &self.attrs {
+            conf += &format!("ATTRIBUTE {}\n", a.name);
+        }
+        for s in &self.structs {
+            conf += &format!("STRUCTURE {} {{\n", s.name);
+            for a in &s.attrs {
+                let name = format!("{}.{}", s.name, a.name);
+                let options: Vec<_> = self.options.iter().filter(|o| o.0 == name).collect();
+                if options.is_empty() {
+                    conf += &format!("    ATTRIBUTE {}\n", a.name);
+                    continue;
+                }
+                conf += &format!("    ATTRIBUTE {} {{\n", a.name);
+                for (_, key, value) in options {
+                    conf += &format!("        {} \"{}\"\n", key, value);
+                }
+                conf += "    }\n";
+            }
+            conf += "}\n";
+        }
+        let conf = corpconf::parse_conf_opt(&conf)?;
+        let mut store = MemStore { attrs: Vec::new(), structs: Vec::new() };
+        for a in self.attrs {
+            store.attrs.push(a.build("")?);
+        }
+        for s in self.structs {
+            let prefix = format!("{}.", s.name);
+            for a in s.attrs {
+                store.attrs.push(a.build(&prefix)?);
+            }
+            store.structs.push(StructData { name: s.name, ranges: s.ranges });
+        }
+        Ok(Corpus::in_memory(conf, Arc::new(store)))
+    }
+}
@@ -0,0 +1,220 @@
+//! Merging of compiled corpora into one.
+//!
+//! The inputs are concatenated in the order given. Lexicons are unified
+//! by assigning new ids in order of first occurrence, so the result is
+//! the same as compiling the concatenated vertical files. Texts, structure
+//! ranges and rev posting lists are copied through id remapping tables
+//! with positions shifted by the sizes of the preceding corpora. Rev
+//! files are written only where all inputs have them, `.lex.lsrt` and
+//! `.lex.fld` where configured. Inputs with deleted structures are refused.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use corpconf::Block;
+use crate::corp::{conf_bool, Corpus};
+use crate::lex::MapLex;
+use crate::dates::{self, DateParser};
+use crate::encode::lex_mem_limit;
+use crate::numeric;
+use crate::rev;
+use crate::tombstone;
+use crate::util::add_suffix;
+use crate::wrlex::{LexIndexes, LexWriter};
+use crate::wrrev::{self, USE_DELTA_DENSE_REV};
+use crate::wrtext::{self, IntTextWriter, TextType, TextWriter, DEFAULT_SEGMENT_SIZE};
+
+type Error = Box<dyn std::error::Error>;
+
+/// Maps ids of one input lexicon to ids of the merged lexicon.
+pub struct IdMap {
+    pub to_new: Vec<u32>,
+}
+
+impl IdMap {
+    /// Inverse mapping for a merged lexicon of `nids` ids.
+    pub fn to_old(&self, nids: usize) -> Vec<Option<u32>> {
+        let mut inv = vec![None; nids];
+        for (old, &new) in self.to_new.iter().enumerate() {
+            inv[new as usize] = Some(old as u32);
+        }
+        inv
+    }
+}
+
+/// Writes the union of `lexes` to `base`, in input order, and returns the
+/// remapping table of each input; `mem_limit` is that of [`LexWriter::open`].
+pub fn merge_lexicons(lexes: &[MapLex], base: &Path, mem_limit: Option<usize>) -> Result<Vec<IdMap>, Error> {
+    let mut lw = LexWriter::open(base, None, mem_limit)?;
+    let mut maps = Vec::with_capacity(lexes.len());
+    for lex in lexes {
+        let to_new = (0..lex.id_range())
+            .map(|id| lw.id_for(lex.id2str(id)))
+            .collect::<Result<_, _>>()?;
+        maps.push(IdMap { to_new });
+    }
+    lw.finalize()?;
+    Ok(maps)
+}
+
+fn attr_base(corp: &Corpus, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", corp.path, name))
+}
+
+/// Merges the attribute `name` (a positional attribute or `struct.attr`)
+/// of all inputs; `offsets[i]` is the first position (or structure number)
+/// of input `i` in the result.
+fn merge_attr(inputs: &[Corpus], name: &str, out: &Path, text: Box<dyn TextWriter>,
+              offsets: &[u64], indexes: LexIndexes, mem_limit: Option<usize>) -> Result<(), Error> {
+    let bases: Vec<PathBuf> = inputs.iter().map(|c| attr_base(c, name)).collect();
+    let lexes = bases.iter()
+        .map(|b| MapLex::open(b.to_str().ok_or("bad path")?).map_err(Error::from))
+        .collect::<Result<Vec<_>, _>>()?;
+    let maps = merge_lexicons(&lexes, out, mem_limit)?;
+    indexes.write(out)?;
+
+    let mut text = text;
+    for (base, map) in bases.iter().zip(&maps) {
+        let src = wrrev::open_text(base)?;
+        let size = src.size() as u64;
+        let ids: Box<dyn Iterator<Item = u32>> = if size == 0 {
+            Box::new(std::iter::empty())
+        } else if let Some(it) = src.posat(0) {
+            Box::new(it)
+        } else if let Some(it) = src.structat(0) {
+            Box::new(it)
+        } else {
+            return Err("text type not supported".into());
+        };
+        for id in ids.take(size as usize) {
+            text.push(map.to_new[id as usize])?;
+        }
+    }
+    text.finalize()?;
+
+    if bases.iter().all(|b| add_suffix(b, ".rev").exists()) {
+        let revs = bases.iter()
+            .map(|b| rev::open(b.to_str().ok_or("bad path")?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let nids = fs::metadata(add_suffix(out, ".lex.idx"))?.len() as usize / 4;
+        let inv: Vec<Vec<Option<u32>>> = maps.iter().map(|m| m.to_old(nids)).collect();
+        let (revs, inv) = (&revs, &inv);
+        wrrev::write_rev(out, USE_DELTA_DENSE_REV, nids, |id| {
+            revs.iter().zip(inv).zip(offsets).flat_map(move |((r, inv), &off)| {
+                inv[id as usize].into_iter()
+                    .flat_map(move |old| r.id2poss(old).map(move |p| p + off))
+            })
+        })?;
+    }
+    Ok(())
+}
+
+fn check_compatible(conf: &Block, corp: &Corpus) -> Result<(), Error> {
+    for name in conf.attrnames_in_order() {
+        if conf.attribute(name).is_some_and(|a| a.value("DYNAMIC").is_some()) { continue; }
+        if corp.conf.attribute(name).is_none() {
+            return Err(format!("attribute {} missing in {}", name, corp.name).into());
+        }
+    }
+    for sname in conf.structnames_in_order() {
+        let sblock = conf.structure(sname).ok_or("structure not found")?;
+        let other = corp.conf.structure(sname)
+            .ok_or(format!("structure {} missing in {}", sname, corp.name))?;
+        for aname in sblock.attrnames_in_order() {
+            if sblock.attribute(aname).is_some_and(|a| a.value("DYNAMIC").is_some()) { continue; }
+            if other.attribute(aname).is_none() {
+                return Err(format!("attribute {}.{} missing in {}", sname, aname, corp.name).into());
+            }
+        }
+    }
+    // texts and revs are copied whole, deleted positions included
+    if !tombstone::load(corp)?.is_empty() {
+        return Err(format!("{} has deleted structures, compact it before merging", corp.name).into());
+    }
+    Ok(())
+}
+
+/// Merges `inputs` into a new corpus described by `conf` in `out_path`.
+/// Every attribute and structure of `conf` has to be present in all inputs,
+/// which must not have tombstones (see [`crate::tombstone::compact`]).
+pub fn merge_corpora(inputs: &[Corpus], conf: &Block, out_path: &Path) -> Result<(), Error> {
+    for corp in inputs {
+        check_compatible(conf, corp)?;
+    }
+    fs::create_dir_all(out_path)?;
+    let segment_size = conf
+        .value("SEGMENTSIZE")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_SEGMENT_SIZE);
+
+    let mut sizes = Vec::with_capacity(inputs.len());
+    for corp in inputs {
+        sizes.push(crate::decode::corpus_size(corp)?);
+    }
+    let offsets = prefix_sums(&sizes);
+    if *offsets.last().unwrap_or(&0) > u32::MAX as u64 {
+        return Err("merged corpus too large".into());
+    }
+
+    for name in conf.attrnames_in_order() {
+        let block = conf.attribute(name).ok_or("attribute not found")?;
+        if block.value("DYNAMIC").is_some() { continue; }
+        let out = out_path.join(name);
+        let text = wrtext::new_text_writer(&out, TextType::from_conf(block), segment_size)?;
+        merge_attr(inputs, name, &out, text, &offsets, LexIndexes::from_conf(conf, block),
+                   lex_mem_limit(conf, block)?)?;
+    }
+
+    for sname in conf.structnames_in_order() {
+        let sblock = conf.structure(sname).ok_or("structure not found")?;
+        let type64 = matches!(sblock.value("TYPE"), Some("file64") | Some("map64"));
+        let mut counts = Vec::with_capacity(inputs.len());
+        let mut rng = BufWriter::new(File::create(add_suffix(&out_path.join(sname), ".rng"))?);
+        for (corp, &off) in inputs.iter().zip(&offsets) {
+            let s = corp.open_struct(sname)?;
+            for n in 0..s.len() as u64 {
+                let (beg, end) = (s.beg_at(n) + off, s.end_at(n) + off);
+                if type64 {
+                    rng.write_all(&beg.to_le_bytes())?;
+                    rng.write_all(&end.to_le_bytes())?;
+                } else {
+                    rng.write_all(&(beg as u32).to_le_bytes())?;
+                    rng.write_all(&(end as u32).to_le_bytes())?;
+                }
+            }
+            counts.push(s.len() as u64);
+        }
+        rng.flush()?;
+
+        let soffsets = prefix_sums(&counts);
+        for aname in sblock.attrnames_in_order() {
+            let ablock = sblock.attribute(aname).ok_or("structure attribute not found")?;
+            if ablock.value("DYNAMIC").is_some() { continue; }
+            let name = format!("{}.{}", sname, aname);
+            let out = out_path.join(&name);
+            let text = Box::new(IntTextWriter::new(&out)?);
+            merge_attr(inputs, &name, &out, text, &soffsets, LexIndexes::from_conf(conf, ablock),
+                       lex_mem_limit(conf, ablock)?)?;
+            if conf_bool(ablock, "NUMERIC") {
+                numeric::write_index(&out)?;
+            }
+            if let Some(parser) = DateParser::from_conf(ablock) {
+                dates::write_index(&out, &parser)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Offsets of consecutive parts of the given sizes, with the total last.
+fn prefix_sums(sizes: &[u64]) -> Vec<u64> {
+    let mut out = Vec::with_capacity(sizes.len() + 1);
+    let mut acc = 0;
+    out.push(acc);
+    for &s in sizes {
+        acc += s;
+        out.push(acc);
+    }
+    out
+}
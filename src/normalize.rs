@@ -0,0 +1,55 @@
+//! Unicode normalization of attribute values.
+//!
+//! Values are normalized to NFC or NFKC before they enter a lexicon, so
+//! that precomposed and decomposed spellings get one id. The forms are
+//! those of the `unicode-normalization` crate, i.e. of the full Unicode
+//! data, for every script.
+
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfc, is_nfkc, UnicodeNormalization};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormForm {
+    Nfc,
+    Nfkc,
+}
+
+impl NormForm {
+    pub fn parse(s: &str) -> Result<NormForm, Box<dyn std::error::Error>> {
+        match s.to_ascii_uppercase().as_str() {
+            "NFC" => Ok(NormForm::Nfc),
+            "NFKC" => Ok(NormForm::Nfkc),
+            _ => Err(format!("unsupported normalization form: {}", s).into()),
+        }
+    }
+
+    /// `NORMALIZE` of the attribute or of the corpus; `none` disables it.
+    pub fn from_conf(conf: &corpconf::Block, attr: &corpconf::Block)
+            -> Result<Option<NormForm>, Box<dyn std::error::Error>> {
+        match attr.value("NORMALIZE").or(conf.value("NORMALIZE")) {
+            None => Ok(None),
+            Some(v) if v.eq_ignore_ascii_case("none") || v.is_empty() => Ok(None),
+            Some(v) => NormForm::parse(v).map(Some),
+        }
+    }
+}
+
+/// `s` in the normalization form `form`; borrowed if already normalized.
+pub fn normalize(s: &str, form: NormForm) -> Cow<'_, str> {
+    if is_normalized(s, form) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(match form {
+        NormForm::Nfc => s.nfc().collect(),
+        NormForm::Nfkc => s.nfkc().collect(),
+    })
+}
+
+/// Whether `s` is in the form `form`.
+pub fn is_normalized(s: &str, form: NormForm) -> bool {
+    s.is_ascii() || match form {
+        NormForm::Nfc => is_nfc(s),
+        NormForm::Nfkc => is_nfkc(s),
+    }
+}
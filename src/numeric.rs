@@ -0,0 +1,229 @@
+//! Numeric index of structure attributes.
+//!
+//! For an attribute with `NUMERIC` set, `.nidx` lists (value, structure
+//! number) records, 16 bytes each (an `f64` and a `u64`, little endian),
+//! sorted by value and then by number. Values that do not parse as
+//! finite numbers are left out. Range queries are two binary searches.
+//! Indexes of in-memory corpora are built from the values when opened.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use memmap::MmapOptions;
+
+use crate::corp::Attr;
+use crate::lex::{read, MapLex};
+use crate::text;
+use crate::util::add_suffix;
+
+type Error = Box<dyn std::error::Error>;
+
+/// Range of values, usable with [`NumIndex::range`].
+pub type NumRange = (Bound<f64>, Bound<f64>);
+
+/// The number in an attribute value, ignoring surrounding whitespace.
+pub fn parse_number(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+/// Writes `.nidx` for the structure attribute at `base` from its lexicon
+/// and text.
+pub fn write_index(base: &Path) -> Result<(), Error> {
+    write_index_by(base, ".nidx", parse_number)
+}
+
+/// Writes an index in the `.nidx` format with the suffix `suffix`, taking
+/// the values from `parse`.
+pub fn write_index_by(base: &Path, suffix: &str, parse: impl Fn(&str) -> Option<f64>)
+        -> Result<(), Error> {
+    let mut f = BufWriter::new(File::create(add_suffix(base, suffix))?);
+    // empty files cannot be mapped
+    if std::fs::metadata(add_suffix(base, ".text"))?.len() == 0
+            || std::fs::metadata(add_suffix(base, ".lex.idx"))?.len() == 0 {
+        return Ok(());
+    }
+    let path = base.to_str().ok_or("bad path")?;
+    let lex = MapLex::open(path)?;
+    let txt = text::Int::open(path)?;
+    let values: Vec<Option<f64>> = (0..lex.id_range())
+        .map(|id| parse(lex.id2str(id)))
+        .collect();
+    for (v, n) in sorted_entries(&values, (0..txt.size() as u64).map(|n| txt.get(n))) {
+        f.write_all(&v.to_le_bytes())?;
+        f.write_all(&n.to_le_bytes())?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+/// (value, structure number) records of the structures with the ids
+/// `ids` given the values of the ids, sorted.
+fn sorted_entries(values: &[Option<f64>], ids: impl Iterator<Item = u32>) -> Vec<(f64, u64)> {
+    let mut entries: Vec<(f64, u64)> = ids.zip(0..)
+        .filter_map(|(id, n)| values[id as usize].map(|v| (v, n)))
+        .collect();
+    entries.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    entries
+}
+
+/// Splits a condition such as `year>=1990`, `year<2005`, `year=2000` or
+/// `year=1990..2005` (both ends included) into the attribute name and
+/// the range of values.
+pub fn parse_condition(cond: &str) -> Result<(&str, NumRange), Error> {
+    parse_condition_by(cond, |s| parse_number(s).map(|v| (v, v)))
+}
+
+/// [`parse_condition`] with the values read by `parse` as the lowest and
+/// the highest number they stand for; `<` and `>=` compare with the
+/// lowest, `<=` and `>` with the highest, and `=` takes the whole span.
+pub fn parse_condition_by(cond: &str, parse: impl Fn(&str) -> Option<(f64, f64)>)
+        -> Result<(&str, NumRange), Error> {
+    let op_at = cond.find(['<', '>', '='])
+        .ok_or_else(|| format!("no comparison in condition {}", cond))?;
+    let name = cond[..op_at].trim();
+    let rest = &cond[op_at..];
+    let op_len = if rest[1..].starts_with('=') { 2 } else { 1 };
+    let (op, value) = rest.split_at(op_len);
+    let num = |s: &str| parse(s.trim())
+        .ok_or_else(|| format!("bad value in condition {}: {}", cond, s.trim()));
+    let range = match op {
+        "<" => (Bound::Unbounded, Bound::Excluded(num(value)?.0)),
+        "<=" => (Bound::Unbounded, Bound::Included(num(value)?.1)),
+        ">" => (Bound::Excluded(num(value)?.1), Bound::Unbounded),
+        ">=" => (Bound::Included(num(value)?.0), Bound::Unbounded),
+        "=" | "==" => match value.split_once("..") {
+            Some((lo, hi)) => (Bound::Included(num(lo)?.0), Bound::Included(num(hi)?.1)),
+            None => {
+                let (lo, hi) = num(value)?;
+                (Bound::Included(lo), Bound::Included(hi))
+            }
+        },
+        _ => return Err(format!("unknown comparison in condition {}", cond).into()),
+    };
+    if name.is_empty() {
+        return Err(format!("no attribute in condition {}", cond).into());
+    }
+    Ok((name, range))
+}
+
+#[derive(Debug)]
+enum Records {
+    Mapped(memmap::Mmap),
+    Memory(Vec<(f64, u64)>),
+}
+
+/// Sorted numeric values of a structure attribute, read from `.nidx`.
+#[derive(Debug)]
+pub struct NumIndex {
+    pub name: String,
+    records: Records,
+    /// sorted ranges of structure numbers to leave out
+    deleted: Vec<(u64, u64)>,
+}
+
+impl NumIndex {
+    pub fn open(base: &str) -> Result<NumIndex, std::io::Error> {
+        NumIndex::open_as(base, ".nidx")
+    }
+
+    /// Opens an index written by [`write_index_by`] with `suffix`.
+    pub fn open_as(base: &str, suffix: &str) -> Result<NumIndex, std::io::Error> {
+        let path = base.to_string() + suffix;
+        let records = if std::fs::metadata(&path)?.len() > 0 {
+            let f = fs_err::File::open(&path)?;
+            Records::Mapped(unsafe { MmapOptions::new().map(f.file())? })
+        } else {
+            Records::Memory(Vec::new())
+        };
+        Ok(NumIndex { name: base.to_string(), records, deleted: Vec::new() })
+    }
+
+    /// An index built in memory from the structure attribute `attr`
+    /// named `name`, with the values read by `parse`.
+    pub fn from_attr(name: &str, attr: &dyn Attr, parse: impl Fn(&str) -> Option<f64>) -> NumIndex {
+        let values: Vec<Option<f64>> = (0..attr.id_range())
+            .map(|id| parse(attr.id2str(id)))
+            .collect();
+        let entries = sorted_entries(&values, attr.iter_ids(0).take(attr.text().size()));
+        NumIndex { name: name.to_string(), records: Records::Memory(entries), deleted: Vec::new() }
+    }
+
+    /// The index without the structures in the sorted `deleted` ranges.
+    pub fn without(self, deleted: Vec<(u64, u64)>) -> NumIndex {
+        NumIndex { deleted, ..self }
+    }
+
+    /// Number of structures with a numeric value, deleted ones included.
+    pub fn len(&self) -> usize {
+        match &self.records {
+            Records::Mapped(m) => m.len() / 16,
+            Records::Memory(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `i`-th (value, structure number) record.
+    pub fn entry(&self, i: usize) -> (f64, u64) {
+        match &self.records {
+            Records::Mapped(m) => (f64::from_bits(read::<u64>(m, 2 * i)), read::<u64>(m, 2 * i + 1)),
+            Records::Memory(v) => v[i],
+        }
+    }
+
+    /// First record whose value does not satisfy `below`, which has to
+    /// hold for a prefix of the records.
+    fn partition(&self, below: impl Fn(f64) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if below(self.entry(mid).0) { lo = mid + 1 } else { hi = mid }
+        }
+        lo
+    }
+
+    /// Numbers of the structures whose value lies in `range`, ascending;
+    /// e.g. `1990.0..2005.0`, `..=2000.0` or a pair of [`Bound`]s.
+    pub fn range(&self, range: impl RangeBounds<f64>) -> Vec<u64> {
+        let from = match range.start_bound() {
+            Bound::Included(&v) => self.partition(|x| x < v),
+            Bound::Excluded(&v) => self.partition(|x| x <= v),
+            Bound::Unbounded => 0,
+        };
+        let to = match range.end_bound() {
+            Bound::Included(&v) => self.partition(|x| x <= v),
+            Bound::Excluded(&v) => self.partition(|x| x < v),
+            Bound::Unbounded => self.len(),
+        };
+        let mut nums: Vec<u64> = (from..to.max(from))
+            .map(|i| self.entry(i).1)
+            .filter(|&n| !self.is_deleted(n))
+            .collect();
+        nums.sort_unstable();
+        nums
+    }
+
+    /// (value, structure number) records in the order of values, without
+    /// the deleted structures.
+    pub fn iter(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        (0..self.len()).map(|i| self.entry(i)).filter(|&(_, n)| !self.is_deleted(n))
+    }
+
+    /// Structures with a value below `v`.
+    pub fn lt(&self, v: f64) -> Vec<u64> { self.range(..v) }
+    /// Structures with a value of at most `v`.
+    pub fn le(&self, v: f64) -> Vec<u64> { self.range(..=v) }
+    /// Structures with a value above `v`.
+    pub fn gt(&self, v: f64) -> Vec<u64> { self.range((Bound::Excluded(v), Bound::Unbounded)) }
+    /// Structures with a value of at least `v`.
+    pub fn ge(&self, v: f64) -> Vec<u64> { self.range(v..) }
+
+    fn is_deleted(&self, n: u64) -> bool {
+        let i = self.deleted.partition_point(|&(_, end)| end <= n);
+        self.deleted.get(i).is_some_and(|&(beg, _)| beg <= n)
+    }
+}
@@ -1,5 +1,6 @@
 use memmap::MmapOptions;
 use fs_err::File;
+use std::collections::HashMap;
 use std::fmt;
 
 use crate::text::DeltaIter;
@@ -15,15 +16,33 @@ struct BadRevHeader {
 pub struct RevIter<'a> {
     di: DeltaIter<'a>,
     last: i64,
+    /// sorted half-open ranges whose positions are skipped
+    deleted: &'a [(u64, u64)],
+}
+
+impl<'a> RevIter<'a> {
+    /// Positions from the gaps in `di`, each one less than the distance
+    /// from the previous position (or from -1).
+    pub fn new(di: DeltaIter<'a>) -> RevIter<'a> {
+        RevIter { di, last: -1, deleted: &[] }
+    }
 }
 
 impl Iterator for RevIter<'_> {
     type Item = u64;
     fn next(&mut self) -> Option<u64> {
-        if let Some(v) = self.di.next() {
+        for v in self.di.by_ref() {
             self.last += (v + 1) as i64; 
-            Some(self.last as u64)
-        } else { None }
+            let pos = self.last as u64;
+            while let Some(&(_, end)) = self.deleted.first() && end <= pos {
+                self.deleted = &self.deleted[1..];
+            }
+            if let Some(&(beg, _)) = self.deleted.first() && beg <= pos {
+                continue;
+            }
+            return Some(pos);
+        }
+        None
     }
 }
 
@@ -61,6 +80,35 @@ impl Rev for DeltaDense {
     fn id2poss(&self, id: u32) -> RevIter<'_> { self.id2poss(id) }
 }
 
+/// Rev hiding the positions in deleted ranges, see `tombstone`. Counts
+/// are those of the inner rev less the postings in the deleted ranges,
+/// counted up front from the text.
+#[derive(Debug)]
+pub struct Filtered {
+    inner: Box<dyn Rev + Sync + Send>,
+    deleted: Vec<(u64, u64)>,
+    removed: HashMap<u32, u64>,
+}
+
+impl Filtered {
+    /// `removed` is the number of postings of each id within `deleted`.
+    pub fn new(inner: Box<dyn Rev + Sync + Send>, deleted: Vec<(u64, u64)>,
+               removed: HashMap<u32, u64>) -> Filtered {
+        Filtered { inner, deleted, removed }
+    }
+}
+
+impl Rev for Filtered {
+    fn count(&self, id: u32) -> u64 {
+        self.inner.count(id) - self.removed.get(&id).copied().unwrap_or(0)
+    }
+    fn id2poss(&self, id: u32) -> RevIter<'_> {
+        let mut it = self.inner.id2poss(id);
+        it.deleted = &self.deleted;
+        it
+    }
+}
+
 #[derive(Debug)]
 pub struct Delta {
     crevf: memmap::Mmap,
@@ -113,7 +161,7 @@ impl Delta {
         let rb = bits::Reader::open(as_slice_ref(&self.crevf), seek*8);
         // while rest != 0 { rb.delta(); rest -= 1; };
         //DeltaIter { remaining: cnt as u64, rb }
-        RevIter { di: DeltaIter { remaining: cnt as u64, rb }, last: -1 }
+        RevIter { di: DeltaIter { remaining: cnt as u64, rb }, last: -1, deleted: &[] }
     }
 }
 
@@ -158,6 +206,6 @@ impl DeltaDense {
     pub fn id2poss(&self, id: u32) -> RevIter<'_> {
         let (seek, cnt) = self.locate(id);
         let rb = bits::Reader::open(as_slice_ref(&self.crevf), seek as usize*8);
-        RevIter {di: DeltaIter { remaining: cnt, rb }, last: -1 }
+        RevIter {di: DeltaIter { remaining: cnt, rb }, last: -1, deleted: &[] }
     }
 }
@@ -1,6 +1,7 @@
 use std::io::*;
 use fs_err::File;
 use std::str;
+use std::sync::OnceLock;
 //use std::cmp::Ordering;
 
 use memmap::MmapOptions;
@@ -16,12 +17,72 @@ pub fn read<T: Sized>(mmap: &memmap::Mmap, idx: usize) -> T {
 pub struct MapStructure32 {
     pub name: String,
     rng: memmap::Mmap,
+    samples: OnceLock<SampleIndex>,
 }
 
 #[derive(Debug)]
 pub struct MapStructure64 {
     pub name: String,
     rng: memmap::Mmap,
+    samples: OnceLock<SampleIndex>,
+}
+
+/// Structure counts sampled every `1 << shift` corpus positions: sample
+/// `i` holds how many structures begin (`begs`) and end (`ends`) at or
+/// before position `i << shift`. A search for a position then only has
+/// to look between two neighbouring samples. About one sample is kept
+/// per 8 structures.
+#[derive(Debug)]
+pub struct SampleIndex {
+    shift: u32,
+    begs: Vec<u64>,
+    ends: Vec<u64>,
+}
+
+impl SampleIndex {
+    pub fn build(s: &(impl Struct + ?Sized)) -> SampleIndex {
+        let len = s.len() as u64;
+        let size = if len == 0 { 0 } else { s.end_at(len - 1) + 1 };
+        let per = (size / (len / 8).max(1)).max(1);
+        let shift = per.next_power_of_two().trailing_zeros();
+        let (mut begs, mut ends) = (Vec::new(), Vec::new());
+        let (mut b, mut e) = (0, 0);
+        for i in 0..=(size >> shift) {
+            let pos = i << shift;
+            while b < len && s.beg_at(b) <= pos { b += 1; }
+            while e < len && s.end_at(e) <= pos { e += 1; }
+            begs.push(b);
+            ends.push(e);
+        }
+        SampleIndex { shift, begs, ends }
+    }
+
+    /// Bounds of the number of structures whose `at` (beginning or end)
+    /// is at most `pos`, from the samples `counts`.
+    fn window(&self, counts: &[u64], pos: u64, len: u64) -> (u64, u64) {
+        let i = (pos >> self.shift) as usize;
+        match (counts.get(i), counts.get(i + 1)) {
+            (Some(&lo), Some(&hi)) => (lo, hi),
+            (Some(&lo), None) => (lo, len),
+            _ => (counts.last().copied().unwrap_or(0), len),
+        }
+    }
+}
+
+/// Number of structures `n` with `at(n) <= pos`, where `at` is
+/// non-decreasing (the beginnings or the ends of non-nested structures).
+fn count_le(s: &(impl Struct + ?Sized), pos: u64, ends: bool) -> u64 {
+    let len = s.len() as u64;
+    let at = |n| if ends { s.end_at(n) } else { s.beg_at(n) };
+    let (mut lo, mut hi) = match s.samples() {
+        Some(smp) => smp.window(if ends { &smp.ends } else { &smp.begs }, pos, len),
+        None => (0, len),
+    };
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if at(mid) <= pos { lo = mid + 1 } else { hi = mid }
+    }
+    lo
 }
 
 impl MapStructure64 {
@@ -34,6 +95,7 @@ impl MapStructure64 {
         Ok(MapStructure64{
             name: base.to_string(),
             rng: open_map(".rng")?,
+            samples: OnceLock::new(),
         })
     }
     
@@ -55,6 +117,7 @@ impl MapStructure32 {
         Ok(MapStructure32{
             name: base.to_string(),
             rng: open_map(".rng")?,
+            samples: OnceLock::new(),
         })
     }
     pub fn beg_at(&self, pos: u64) -> u64 {
@@ -71,43 +134,32 @@ pub fn open(base: &str, type64: bool) -> std::result::Result<Box<dyn Struct + Sy
     else { Box::new(MapStructure32::open(base)?) })
 }
 
+/// Ranges of the structures of one name, in the order of their
+/// beginnings. The searches assume, as encodevert writes them, that both
+/// the beginnings and the ends are non-decreasing: structures do not
+/// overlap, and empty structures (`beg == end`) lie between or at the
+/// edges of the others.
 pub trait Struct: std::fmt::Debug {
     fn beg_at(&self, pos: u64) -> u64;
     fn end_at(&self, pos: u64) -> u64;
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool { self.len() == 0 }
-    /*
-    fn find_beg(&self, pos: u64, start_at_struct_pos: u64) -> Option<u64> {
-        let mut incr = 1u64;
-        let mut curr = 0u64;
-        let len = self.len() as u64;
-
-        while (curr + incr) < len && self.beg_at(curr + incr) <= pos {
-            curr += incr;
-            incr *= 2;
-        }
-        while incr > 0 {
-            if (curr + incr) < len && self.beg_at(curr + incr) <= pos {
-                curr += incr;
-            }
-            incr /= 2;
-        }
-        //if self.beg_at(curr) < pos {
-        //    curr += 1;
-        //}
-        if pos >= self.beg_at(curr) && pos < self.end_at(curr) {
-            Some(curr)
-        } else {
-            None
-        }
-    }
-    */
+    /// Sampled index speeding up the searches, if the structure has one.
+    fn samples(&self) -> Option<&SampleIndex> { None }
+    /// Number and beginning of the first structure ending at or after
+    /// `pos`, which includes an empty structure at `pos`; `u64::MAX` twice
+    /// if there is none.
     fn find_end(&self, pos: u64) -> (u64, u64) {
         find_end_i(self, pos)
     }
+    /// Beginning of the first structure beginning at or after `pos`;
+    /// `u64::MAX` if there is none.
     fn find_beg(&self, pos: u64) -> u64 {
         find_beg_i(self, pos)
     }
+    /// Number of the structure containing `pos` (`beg <= pos < end`) or,
+    /// if there is none, of the last empty structure at `pos` (a tag
+    /// such as `<doc/>` right before the token at `pos`).
     fn num_at_pos(&self, pos: u64) -> Option<u64> {
         num_at_pos_i(self, pos)
     }
@@ -118,102 +170,81 @@ impl Struct for MapStructure32 {
     fn beg_at(&self, pos: u64) -> u64 { self.beg_at(pos) }
     fn end_at(&self, pos: u64) -> u64 { self.end_at(pos) }
     fn len(&self) -> usize { self.rng.len() / 8 }
+    fn samples(&self) -> Option<&SampleIndex> {
+        Some(self.samples.get_or_init(|| SampleIndex::build(self)))
+    }
 }
 
 impl Struct for MapStructure64 {
     fn beg_at(&self, pos: u64) -> u64 { self.beg_at(pos) }
     fn end_at(&self, pos: u64) -> u64 { self.end_at(pos) }
     fn len(&self) -> usize { self.rng.len() / 16 }
+    fn samples(&self) -> Option<&SampleIndex> {
+        Some(self.samples.get_or_init(|| SampleIndex::build(self)))
+    }
 }
 
-fn find_end_i(s: &(impl Struct + ?Sized), pos: u64) -> (u64, u64) {
-    let mut curr = 0u64;
-    let last = s.len() as u64 - 1;
-    let finval = u64::MAX;
-    if !(curr < last) { return (finval, finval); }
-    let mut incr = 1u64;
-    while (curr + incr) < last &&
-        /* abs( */ s.end_at(curr + incr) /* ) */ <= pos
-            {
-        curr += incr;
-        incr *= 2;
-    }
-    while incr > 0 {
-        if (curr + incr) < last &&
-            /* abs( */ s.end_at(curr + incr) /* ) */ <= pos
-                {
-            curr += incr;
-        }
-        incr /= 2;
-    }
-    /*
-    // go back out of nested ranges (end < 0)
-    while (prev < curr && s.end_at(curr) < 0) {
-        curr -= 1;
-    }
-    */
-    while curr < last && s.end_at(curr) /*.abs()*/ < pos {
-        curr += 1;
-    }
-    if curr < last { (curr, s.beg_at(curr)) }
-    else { (finval, finval) }
+/// Looks up the structures at a sequence of positions. For positions in
+/// ascending order, each lookup takes amortized constant time; a position
+/// before the previous one starts the walk anew from the sampled index.
+pub struct StructCursor<'a, S: Struct + ?Sized> {
+    s: &'a S,
+    /// first structure ending after the previous position
+    next: u64,
+    pos: u64,
 }
 
-fn find_beg_i(s: &(impl Struct + ?Sized), pos: u64) -> u64 {
-    let mut curr = 0u64;
-    let prev = curr;
-    let last = s.len() as u64 - 1;
-    let finval = u64::MAX;
-    if !(curr < last) { return finval; }
-    let mut incr = 1u64;
-    while (curr + incr) < last &&
-        /* abs( */ s.beg_at(curr + incr) /* ) */ <= pos {
-        curr += incr;
-        incr *= 2;
-    }
-    while incr > 0 {
-        if (curr + incr) < last &&
-            /* abs( */ s.beg_at(curr + incr) /* ) */ <= pos {
-            curr += incr;
+impl<'a, S: Struct + ?Sized> StructCursor<'a, S> {
+    pub fn new(s: &'a S) -> StructCursor<'a, S> {
+        StructCursor { s, next: 0, pos: 0 }
+    }
+
+    /// Number of the structure at `pos`, as [`Struct::num_at_pos`].
+    pub fn num_at(&mut self, pos: u64) -> Option<u64> {
+        let len = self.s.len() as u64;
+        if pos < self.pos {
+            self.next = 0;
+        }
+        self.pos = pos;
+        if let Some(smp) = self.s.samples() {
+            self.next = self.next.max(smp.window(&smp.ends, pos, len).0);
         }
-        incr /= 2;
-    }
-    if s.beg_at(curr) < pos {
-        curr += 1;
-    } else if prev < curr {
-        let mut curr_1 = curr;
-        curr_1 -= 1;
-        while prev < curr && s.beg_at(curr_1) == pos {
-            curr -= 1;
-            curr_1 -= 1;
+        while self.next < len && self.s.end_at(self.next) <= pos {
+            self.next += 1;
         }
+        at_or_before(self.s, self.next, pos)
     }
-    if curr < last { s.beg_at(curr) }
-    else { finval }
+}
+
+/// Number of structures ending (or, with `ends` false, beginning)
+/// before `pos`.
+fn count_lt(s: &(impl Struct + ?Sized), pos: u64, ends: bool) -> u64 {
+    pos.checked_sub(1).map_or(0, |p| count_le(s, p, ends))
+}
+
+fn find_end_i(s: &(impl Struct + ?Sized), pos: u64) -> (u64, u64) {
+    let n = count_lt(s, pos, true);
+    if n < s.len() as u64 { (n, s.beg_at(n)) } else { (u64::MAX, u64::MAX) }
+}
+
+fn find_beg_i(s: &(impl Struct + ?Sized), pos: u64) -> u64 {
+    let n = count_lt(s, pos, false);
+    if n < s.len() as u64 { s.beg_at(n) } else { u64::MAX }
 }
 
 fn num_at_pos_i(s: &(impl Struct + ?Sized), pos: u64) -> Option<u64> {
-    let (mut structpos, corppos) = s.find_end(pos + 1);
-    if structpos == u64::MAX {
-        return None;
-    }
-    if corppos <= pos {
-        // let rngsize = s.end_at(b) - s.beg_at(b);
+    let n = count_le(s, pos, true);
+    at_or_before(s, n, pos)
+}
 
-        // try to find smaller range in nested ones
-        // TODO
-        return Some(structpos);
-    }
-    // handling of empty structures around pos
-    if s.end_at(structpos) == s.beg_at(structpos) && s.beg_at(structpos) == pos + 1 {
-        // empty struct following pos
-        return Some(structpos);
-    }
-    if structpos > 0 {
-        structpos -= 1;
-    }
-    if s.beg_at(structpos) == s.beg_at(structpos) && s.beg_at(structpos) == pos {
-        return Some(structpos)
+/// `num_at_pos` given `n`, the first structure ending after `pos`: `n` if
+/// it contains `pos`, or else the empty structure at `pos` right before.
+fn at_or_before(s: &(impl Struct + ?Sized), n: u64, pos: u64) -> Option<u64> {
+    if n < s.len() as u64 && s.beg_at(n) <= pos {
+        Some(n)
+    } else if n > 0 && s.beg_at(n - 1) == pos && s.end_at(n - 1) == pos {
+        Some(n - 1)
+    } else {
+        None
     }
-    return None
 }
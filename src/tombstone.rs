@@ -0,0 +1,309 @@
+//! Deletion of structures (typically documents) without recompilation.
+//!
+//! Deleted structure instances are listed in the `tombstones` file in the
+//! corpus directory, one `struct<TAB>number` per line. Attributes opened
+//! through `Corpus::open_attribute` then hide the deleted positions from
+//! rev iteration and frequencies. [`compact`] writes a copy of the corpus
+//! with the deleted positions physically removed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::corp::{conf_bool, conf_multisep, Corpus};
+use crate::lex::MapLex;
+use crate::dates::{self, DateParser};
+use crate::encode::lex_mem_limit;
+use crate::numeric;
+use crate::structure::Struct;
+use crate::util::add_suffix;
+use crate::wrlex::{LexIndexes, LexWriter};
+use crate::wrrev;
+use crate::wrtext::{self, IntTextWriter, TextType, TextWriter, DEFAULT_SEGMENT_SIZE};
+
+type Error = Box<dyn std::error::Error>;
+
+pub const TOMBSTONE_FILE: &str = "tombstones";
+
+fn tombstone_path(corp: &Corpus) -> PathBuf {
+    PathBuf::from(format!("{}{}", corp.path, TOMBSTONE_FILE))
+}
+
+/// Deleted structure numbers by structure name.
+pub fn load(corp: &Corpus) -> Result<HashMap<String, HashSet<u64>>, Error> {
+    let mut out: HashMap<String, HashSet<u64>> = HashMap::new();
+    let path = tombstone_path(corp);
+    if !path.exists() {
+        return Ok(out);
+    }
+    for (i, line) in fs::read_to_string(&path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let (sname, num) = line.split_once('\t')
+            .ok_or(format!("{}:{}: expected structure and number", path.display(), i + 1))?;
+        out.entry(sname.to_string()).or_default().insert(num.trim().parse()?);
+    }
+    Ok(out)
+}
+
+/// Marks the given structure numbers as deleted.
+pub fn add(corp: &Corpus, structname: &str, nums: &[u64]) -> Result<(), Error> {
+    let s = corp.open_struct(structname)?;
+    if let Some(&n) = nums.iter().find(|&&n| n >= s.len() as u64) {
+        return Err(format!("structure number {} out of range", n).into());
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(tombstone_path(corp))?;
+    for n in nums {
+        writeln!(f, "{}\t{}", structname, n)?;
+    }
+    Ok(())
+}
+
+/// Sorted, non-overlapping corpus position ranges of deleted structures.
+pub fn deleted_ranges(corp: &Corpus) -> Result<Vec<(u64, u64)>, Error> {
+    ranges_of(corp, &load(corp)?)
+}
+
+fn ranges_of(corp: &Corpus, tombstoned: &HashMap<String, HashSet<u64>>)
+        -> Result<Vec<(u64, u64)>, Error> {
+    let mut ranges = Vec::new();
+    for (sname, nums) in tombstoned {
+        let s = corp.open_struct(sname)?;
+        for &n in nums {
+            if n < s.len() as u64 && s.beg_at(n) < s.end_at(n) {
+                ranges.push((s.beg_at(n), s.end_at(n)));
+            }
+        }
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (b, e) in ranges {
+        match merged.last_mut() {
+            Some(last) if b <= last.1 => last.1 = last.1.max(e),
+            _ => merged.push((b, e)),
+        }
+    }
+    Ok(merged)
+}
+
+/// The tombstones of a corpus with the deleted ranges derived from them,
+/// read once and kept by the `Corpus`; tombstones added later take effect
+/// when the corpus is opened again.
+#[derive(Debug, Default)]
+pub struct Deleted {
+    /// see [`deleted_ranges`]
+    pub ranges: Vec<(u64, u64)>,
+    tombstoned: HashMap<String, HashSet<u64>>,
+    struct_nums: Mutex<HashMap<String, Vec<(u64, u64)>>>,
+}
+
+impl Deleted {
+    pub fn load(corp: &Corpus) -> Result<Deleted, Error> {
+        let tombstoned = load(corp)?;
+        Ok(Deleted {
+            ranges: ranges_of(corp, &tombstoned)?,
+            tombstoned,
+            struct_nums: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Ranges of deleted structure numbers of `structname`, see
+    /// [`deleted_struct_nums`].
+    pub fn struct_nums(&self, corp: &Corpus, structname: &str) -> Result<Vec<(u64, u64)>, Error> {
+        if let Some(nums) = self.struct_nums.lock().unwrap().get(structname) {
+            return Ok(nums.clone());
+        }
+        let nums = struct_nums_of(corp, structname, &self.tombstoned, &self.ranges)?;
+        self.struct_nums.lock().unwrap().insert(structname.to_string(), nums.clone());
+        Ok(nums)
+    }
+}
+
+/// Whether structure `num` of `s` disappears with the deleted positions:
+/// it is tombstoned itself, or it lies within a deleted range (empty
+/// structures at the range boundaries survive).
+fn struct_deleted(s: &dyn Struct, num: u64, tombstoned: Option<&HashSet<u64>>,
+                  deleted: &[(u64, u64)]) -> bool {
+    if tombstoned.is_some_and(|t| t.contains(&num)) {
+        return true;
+    }
+    let (b, e) = (s.beg_at(num), s.end_at(num));
+    let i = deleted.partition_point(|r| r.0 <= b);
+    if i == 0 {
+        return false;
+    }
+    let (db, de) = deleted[i - 1];
+    if b < e { e <= de } else { db < b && b < de }
+}
+
+/// Ranges of deleted structure numbers of the structure `structname`.
+pub fn deleted_struct_nums(corp: &Corpus, structname: &str, deleted: &[(u64, u64)])
+        -> Result<Vec<(u64, u64)>, Error> {
+    struct_nums_of(corp, structname, &load(corp)?, deleted)
+}
+
+fn struct_nums_of(corp: &Corpus, structname: &str, tombstoned: &HashMap<String, HashSet<u64>>,
+                  deleted: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+    let s = corp.open_struct(structname)?;
+    let mut out: Vec<(u64, u64)> = Vec::new();
+    for n in 0..s.len() as u64 {
+        if struct_deleted(s.as_ref(), n, tombstoned.get(structname), deleted) {
+            match out.last_mut() {
+                Some(last) if last.1 == n => last.1 = n + 1,
+                _ => out.push((n, n + 1)),
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Number of deleted positions before `pos`.
+fn shift(deleted: &[(u64, u64)], before: &[u64], pos: u64) -> u64 {
+    let i = deleted.partition_point(|r| r.0 < pos);
+    if i == 0 {
+        return 0;
+    }
+    let (db, de) = deleted[i - 1];
+    before[i - 1] + de.min(pos) - db
+}
+
+/// Copies `ids` (from the lexicon `lex`) through a new lexicon at `out`,
+/// assigning ids in order of first occurrence; components of multivalues
+/// follow their value.
+fn rewrite_ids<I>(lex: &MapLex, ids: I, out: &Path, text: &mut dyn TextWriter,
+                  multisep: Option<&str>, mem_limit: Option<usize>) -> Result<(), Error>
+where
+    I: Iterator<Item = u32>,
+{
+    let mut lw = LexWriter::open(out, None, mem_limit)?;
+    let mut map: Vec<Option<u32>> = vec![None; lex.id_range() as usize];
+    for id in ids {
+        let new = match map[id as usize] {
+            Some(n) => n,
+            None => {
+                let n = lw.id_for(lex.id2str(id))?;
+                if let Some(sep) = multisep {
+                    for comp in wrrev::split_multivalue(lex.id2str(id), sep) {
+                        lw.id_for(comp)?;
+                    }
+                }
+                map[id as usize] = Some(n);
+                n
+            }
+        };
+        text.push(new)?;
+    }
+    lw.finalize()
+}
+
+/// Writes the corpus without its deleted positions to `out_path`; the
+/// result is what compiling the vertical file without the deleted
+/// structures would give. Rev files are built where the corpus has them.
+pub fn compact(corp: &Corpus, out_path: &Path) -> Result<(), Error> {
+    let conf = &corp.conf;
+    let deleted = deleted_ranges(corp)?;
+    let tombstoned = load(corp)?;
+    let size = crate::decode::corpus_size(corp)?;
+    let kept = complement(&deleted, size);
+    let mut before = Vec::with_capacity(deleted.len());
+    let mut acc = 0;
+    for &(b, e) in &deleted {
+        before.push(acc);
+        acc += e - b;
+    }
+    fs::create_dir_all(out_path)?;
+    let segment_size = conf
+        .value("SEGMENTSIZE")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_SEGMENT_SIZE);
+
+    for name in conf.attrnames_in_order() {
+        let block = conf.attribute(name).ok_or("attribute not found")?;
+        if block.value("DYNAMIC").is_some() { continue; }
+        let src = PathBuf::from(format!("{}{}", corp.path, name));
+        let lex = MapLex::open(src.to_str().ok_or("bad path")?)?;
+        let text = wrrev::open_text(&src)?;
+        let out = out_path.join(name);
+        let mut tw = wrtext::new_text_writer(&out, TextType::from_conf(block), segment_size)?;
+        let ids = kept.iter().flat_map(|&(b, e)| -> Box<dyn Iterator<Item = u32>> {
+            match (text.posat(b), text.structat(b)) {
+                (Some(it), _) => Box::new(it.take((e - b) as usize)),
+                (None, Some(it)) => Box::new(it.take((e - b) as usize)),
+                _ => Box::new(std::iter::empty()),
+            }
+        });
+        let multisep = conf_multisep(block);
+        rewrite_ids(&lex, ids, &out, tw.as_mut(), multisep.as_deref(), lex_mem_limit(conf, block)?)?;
+        tw.finalize()?;
+        LexIndexes::from_conf(conf, block).write(&out)?;
+        if add_suffix(&src, ".rev").exists() {
+            wrrev::build_rev(&out, multisep.as_deref())?;
+        }
+    }
+
+    for sname in conf.structnames_in_order() {
+        let sblock = conf.structure(sname).ok_or("structure not found")?;
+        let type64 = matches!(sblock.value("TYPE"), Some("file64") | Some("map64"));
+        let s = corp.open_struct(sname)?;
+        let nums: Vec<u64> = (0..s.len() as u64)
+            .filter(|&n| !struct_deleted(s.as_ref(), n, tombstoned.get(sname), &deleted))
+            .collect();
+        let mut rng = Vec::with_capacity(nums.len() * 16);
+        for &n in &nums {
+            let b = s.beg_at(n) - shift(&deleted, &before, s.beg_at(n));
+            let e = s.end_at(n) - shift(&deleted, &before, s.end_at(n));
+            if type64 {
+                rng.extend_from_slice(&b.to_le_bytes());
+                rng.extend_from_slice(&e.to_le_bytes());
+            } else {
+                rng.extend_from_slice(&(b as u32).to_le_bytes());
+                rng.extend_from_slice(&(e as u32).to_le_bytes());
+            }
+        }
+        fs::write(add_suffix(&out_path.join(sname), ".rng"), rng)?;
+
+        for aname in sblock.attrnames_in_order() {
+            let ablock = sblock.attribute(aname).ok_or("structure attribute not found")?;
+            if ablock.value("DYNAMIC").is_some() { continue; }
+            let name = format!("{}.{}", sname, aname);
+            let src = PathBuf::from(format!("{}{}", corp.path, name));
+            let lex = MapLex::open(src.to_str().ok_or("bad path")?)?;
+            let text = corp.open_structtext(sname, aname)?;
+            let out = out_path.join(&name);
+            let mut tw = IntTextWriter::new(&out)?;
+            let multisep = conf_multisep(ablock);
+            let ids = nums.iter().map(|&n| text.get(n));
+            rewrite_ids(&lex, ids, &out, &mut tw, multisep.as_deref(), lex_mem_limit(conf, ablock)?)?;
+            Box::new(tw).finalize()?;
+            LexIndexes::from_conf(conf, ablock).write(&out)?;
+            if conf_bool(ablock, "NUMERIC") {
+                numeric::write_index(&out)?;
+            }
+            if let Some(parser) = DateParser::from_conf(ablock) {
+                dates::write_index(&out, &parser)?;
+            }
+            if add_suffix(&src, ".rev").exists() {
+                wrrev::build_rev(&out, multisep.as_deref())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ranges of `0..size` not covered by the sorted `ranges`.
+fn complement(ranges: &[(u64, u64)], size: u64) -> Vec<(u64, u64)> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    for &(b, e) in ranges {
+        if b > pos {
+            out.push((pos, b.min(size)));
+        }
+        pos = pos.max(e);
+    }
+    if pos < size {
+        out.push((pos, size));
+    }
+    out
+}
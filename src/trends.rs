@@ -0,0 +1,296 @@
+//! Items rising and falling over time.
+//!
+//! [`trends`] counts the occurrences of each id of a positional attribute
+//! in the time slices of the dated structures (see [`crate::dates`]),
+//! walking the rev of the id through the structure ranges, and turns the
+//! counts into frequencies per million positions of each slice. Two
+//! trends are fitted to the frequencies: the least-squares line, whose
+//! slope is tested with Student's t test, and the Mann-Kendall test, a
+//! rank test that does not assume the change to be linear. Slices without
+//! dated text are left out of both.
+
+use chrono::NaiveDate;
+
+use crate::corp::Corpus;
+use crate::dates::{self, Period};
+
+type Error = Box<dyn std::error::Error>;
+
+/// Trend test used to rank the items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// slope of the regression line relative to the mean frequency
+    Regression,
+    /// the Mann-Kendall statistic Z
+    MannKendall,
+}
+
+impl Method {
+    pub fn parse(s: &str) -> Result<Method, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "regression" | "linear" => Ok(Method::Regression),
+            "mk" | "mann-kendall" | "mannkendall" => Ok(Method::MannKendall),
+            _ => Err(format!("unknown trend method: {}", s).into()),
+        }
+    }
+}
+
+/// Trend of one id.
+#[derive(Clone, Debug)]
+pub struct Trend {
+    pub id: u32,
+    /// occurrences in the dated structures
+    pub freq: u64,
+    /// frequency per million positions in each slice with dated text
+    pub series: Vec<f64>,
+    /// slope of the regression line, per million positions per slice
+    pub slope: f64,
+    /// slope divided by the mean frequency
+    pub rel_slope: f64,
+    /// two-sided p-value of the slope
+    pub p_regression: f64,
+    /// Mann-Kendall statistic Z, positive for rising items
+    pub mk_z: f64,
+    /// two-sided p-value of the Mann-Kendall test
+    pub p_mann_kendall: f64,
+}
+
+impl Trend {
+    /// Score of the trend by `method`, positive for rising items.
+    pub fn score(&self, method: Method) -> f64 {
+        match method {
+            Method::Regression => self.rel_slope,
+            Method::MannKendall => self.mk_z,
+        }
+    }
+
+    pub fn p_value(&self, method: Method) -> f64 {
+        match method {
+            Method::Regression => self.p_regression,
+            Method::MannKendall => self.p_mann_kendall,
+        }
+    }
+}
+
+/// Trends of all ids of an attribute.
+#[derive(Clone, Debug)]
+pub struct Trends {
+    /// first days of the time slices
+    pub starts: Vec<NaiveDate>,
+    /// positions in each time slice
+    pub sizes: Vec<u64>,
+    /// ids occurring at least `min_freq` times in dated structures
+    pub items: Vec<Trend>,
+}
+
+impl Trends {
+    /// First days of the slices in [`Trend::series`].
+    pub fn series_starts(&self) -> Vec<NaiveDate> {
+        self.starts.iter().zip(&self.sizes)
+            .filter(|&(_, &size)| size > 0)
+            .map(|(&s, _)| s)
+            .collect()
+    }
+
+    /// The `n` most rising and the `n` most falling items by `method`,
+    /// among those with a p-value of at most `max_p`.
+    pub fn top(&self, method: Method, max_p: f64, n: usize) -> (Vec<&Trend>, Vec<&Trend>) {
+        let mut rising: Vec<&Trend> = self.items.iter()
+            .filter(|t| t.score(method) > 0.0 && t.p_value(method) <= max_p)
+            .collect();
+        let mut falling: Vec<&Trend> = self.items.iter()
+            .filter(|t| t.score(method) < 0.0 && t.p_value(method) <= max_p)
+            .collect();
+        rising.sort_by(|a, b| b.score(method).total_cmp(&a.score(method)));
+        falling.sort_by(|a, b| a.score(method).total_cmp(&b.score(method)));
+        rising.truncate(n);
+        falling.truncate(n);
+        (rising, falling)
+    }
+}
+
+/// Trends of the ids of the positional attribute `attr` over the slices
+/// of length `period` of the structures dated by `dateattr` (e.g.
+/// `doc.date`), which must not nest; ids occurring fewer than `min_freq`
+/// times in dated structures are left out.
+pub fn trends(corp: &Corpus, attr: &str, dateattr: &str, period: Period, min_freq: u64)
+        -> Result<Trends, Error> {
+    let (sname, _) = dateattr.split_once('.')
+        .ok_or_else(|| format!("{} is not a structure attribute", dateattr))?;
+    let s = corp.open_struct(sname)?;
+    let index = corp.open_dates(dateattr)?;
+    let (starts, of_struct) = dates::slices(&index, s.len(), period);
+    let sizes = dates::slice_sizes(s.as_ref(), &of_struct, starts.len());
+    let begs: Vec<u64> = (0..s.len() as u64).map(|n| s.beg_at(n)).collect();
+    let ends: Vec<u64> = (0..s.len() as u64).map(|n| s.end_at(n)).collect();
+    let xs: Vec<f64> = (0..sizes.len()).filter(|&i| sizes[i] > 0).map(|i| i as f64).collect();
+
+    let a = corp.open_attribute(attr)?;
+    let rev = a.revidx();
+    let mut items = Vec::new();
+    for id in 0..a.id_range() {
+        if rev.count(id) < min_freq {
+            continue;
+        }
+        let mut hits = vec![0u64; sizes.len()];
+        // structures starting at or before the current position
+        let mut n = 0;
+        for pos in rev.id2poss(id) {
+            n += begs[n..].partition_point(|&b| b <= pos);
+            if n > 0 && pos < ends[n - 1] && let Some(p) = of_struct[n - 1] {
+                hits[p] += 1;
+            }
+        }
+        let freq: u64 = hits.iter().sum();
+        if freq < min_freq.max(1) {
+            continue;
+        }
+        let series: Vec<f64> = hits.iter().zip(&sizes)
+            .filter(|&(_, &size)| size > 0)
+            .map(|(&h, &size)| h as f64 * 1e6 / size as f64)
+            .collect();
+        let (slope, p_regression) = regression(&xs, &series);
+        let mean = series.iter().sum::<f64>() / series.len() as f64;
+        let (mk_z, p_mann_kendall) = mann_kendall(&series);
+        items.push(Trend {
+            id, freq, slope,
+            rel_slope: if mean > 0.0 { slope / mean } else { 0.0 },
+            p_regression, mk_z, p_mann_kendall, series,
+        });
+    }
+    Ok(Trends { starts, sizes, items })
+}
+
+/// Least-squares slope of `ys` over `xs` and its two-sided p-value.
+pub fn regression(xs: &[f64], ys: &[f64]) -> (f64, f64) {
+    let k = xs.len() as f64;
+    if xs.len() < 2 {
+        return (0.0, 1.0);
+    }
+    let mx = xs.iter().sum::<f64>() / k;
+    let my = ys.iter().sum::<f64>() / k;
+    let sxx: f64 = xs.iter().map(|x| (x - mx) * (x - mx)).sum();
+    let sxy: f64 = xs.iter().zip(ys).map(|(x, y)| (x - mx) * (y - my)).sum();
+    let slope = sxy / sxx;
+    if xs.len() < 3 {
+        return (slope, 1.0);
+    }
+    let sse: f64 = xs.iter().zip(ys).map(|(x, y)| {
+        let r = y - my - slope * (x - mx);
+        r * r
+    }).sum();
+    let df = k - 2.0;
+    let se = (sse / df / sxx).sqrt();
+    let p = if se > 0.0 {
+        let t = slope / se;
+        inc_beta(df / 2.0, 0.5, df / (df + t * t))
+    } else if slope != 0.0 {
+        0.0
+    } else {
+        1.0
+    };
+    (slope, p)
+}
+
+/// Mann-Kendall statistic Z of `ys` (with the tie correction and the
+/// continuity correction) and its two-sided p-value.
+pub fn mann_kendall(ys: &[f64]) -> (f64, f64) {
+    let n = ys.len();
+    let mut s = 0i64;
+    for i in 0..n {
+        for j in i + 1..n {
+            s += match ys[j].total_cmp(&ys[i]) {
+                std::cmp::Ordering::Greater => 1,
+                std::cmp::Ordering::Less => -1,
+                std::cmp::Ordering::Equal => 0,
+            };
+        }
+    }
+    let mut sorted = ys.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mut ties = 0.0;
+    for run in sorted.chunk_by(|a, b| a == b) {
+        let t = run.len() as f64;
+        ties += t * (t - 1.0) * (2.0 * t + 5.0);
+    }
+    let nf = n as f64;
+    let var = (nf * (nf - 1.0) * (2.0 * nf + 5.0) - ties) / 18.0;
+    if var <= 0.0 || s == 0 {
+        return (0.0, 1.0);
+    }
+    let z = (s - s.signum()) as f64 / var.sqrt();
+    (z, erfc(z.abs() / std::f64::consts::SQRT_2))
+}
+
+/// Logarithm of the gamma function (Lanczos approximation), for x > 0.
+pub fn ln_gamma(x: f64) -> f64 {
+    const G: [f64; 9] = [
+        0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8,
+        771.323_428_777_653_1, -176.615_029_162_140_6, 12.507_343_278_686_905,
+        -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6, 1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = G[1..].iter().enumerate()
+        .fold(G[0], |acc, (i, g)| acc + g / (x + i as f64 + 1.0));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Regularized incomplete beta function I_x(a, b).
+pub fn inc_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b)
+                 + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_cf(a, b, x) / a
+    } else {
+        1.0 - front * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function (modified Lentz).
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY { d = TINY; }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for num in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + num * d;
+            if d.abs() < TINY { d = TINY; }
+            c = 1.0 + num / c;
+            if c.abs() < TINY { c = TINY; }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// Complementary error function, with a relative error below 1.2e-7.
+pub fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96
+        + t * (0.096_784_18 + t * (-0.186_288_06 + t * (0.278_868_07 + t * (-1.135_203_98
+        + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
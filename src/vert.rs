@@ -0,0 +1,399 @@
+//! Streaming parser for the vertical (one token per line) corpus format.
+//!
+//! Each input line becomes one `Event`: a token with its tab-separated
+//! fields, an opening, closing or empty (self-closing) structure tag,
+//! a glue tag (`<g/>` by default), or a comment (`#` lines, `<!-- -->`, `<? ?>`).
+//! Blank lines are skipped. Line numbers are 1-based.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::BufRead;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Token(Vec<String>),
+    Open { name: String, attrs: Vec<(String, String)> },
+    Close { name: String },
+    Empty { name: String, attrs: Vec<(String, String)> },
+    Glue,
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub num: u64,
+    pub event: Event,
+}
+
+/// What to do with lines that look like tags but cannot be parsed
+/// and with lines that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformed {
+    /// keep going the way encodevert always did: a broken tag is read
+    /// as a token, a tag with broken attributes keeps the attributes
+    /// parsed so far and invalid UTF-8 is replaced by U+FFFD
+    Lenient,
+    /// drop the line
+    Skip,
+    /// report the line as an `Err` item
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// decode XML entities in token fields and attribute values
+    pub entities: bool,
+    pub malformed: Malformed,
+    /// when set, tags with other names are read as tokens
+    pub structures: Option<HashSet<String>>,
+    /// read tags whose names are not XML names (`<3>`) as tokens
+    pub xml_names: bool,
+    /// name of the empty tag read as [`Event::Glue`]
+    pub glue: String,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            entities: true,
+            malformed: Malformed::Lenient,
+            structures: None,
+            xml_names: false,
+            glue: "g".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    MalformedTag,
+    MalformedAttrs,
+    InvalidUtf8,
+    Io,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::MalformedTag => "malformed_tag",
+            ErrorKind::MalformedAttrs => "malformed_attrs",
+            ErrorKind::InvalidUtf8 => "invalid_utf8",
+            ErrorKind::Io => "io",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VertError {
+    pub line: u64,
+    pub kind: ErrorKind,
+    pub text: String,
+}
+
+impl fmt::Display for VertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.kind.as_str(), self.text)
+    }
+}
+
+impl std::error::Error for VertError {}
+
+pub struct Parser<R> {
+    reader: R,
+    opts: Options,
+    lineno: u64,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> Parser<R> {
+    pub fn new(reader: R) -> Parser<R> {
+        Parser::with_options(reader, Options::default())
+    }
+
+    pub fn with_options(reader: R, opts: Options) -> Parser<R> {
+        Parser { reader, opts, lineno: 0, buf: Vec::new() }
+    }
+
+    /// number of the last line read
+    pub fn line(&self) -> u64 { self.lineno }
+
+    fn error(&self, kind: ErrorKind, text: &str) -> VertError {
+        VertError { line: self.lineno, kind, text: text.to_string() }
+    }
+
+    fn decode<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        if self.opts.entities { unescape(s) } else { Cow::Borrowed(s) }
+    }
+
+    fn decode_attrs(&self, attrs: Vec<(&str, &str)>) -> Vec<(String, String)> {
+        attrs.into_iter()
+            .map(|(k, v)| (k.to_string(), self.decode(v).into_owned()))
+            .collect()
+    }
+
+    fn token(&self, line: &str) -> Event {
+        Event::Token(line.split('\t').map(|f| self.decode(f).into_owned()).collect())
+    }
+
+    fn declared(&self, name: &str) -> bool {
+        match &self.opts.structures {
+            Some(s) => s.contains(name),
+            None => !self.opts.xml_names || is_xml_name(name),
+        }
+    }
+
+    /// Parses one non-empty, trimmed line; `Ok(None)` means skip it.
+    fn parse_line(&self, line: &str) -> Result<Option<Event>, VertError> {
+        if let Some(comment) = line.strip_prefix('#') {
+            return Ok(Some(Event::Comment(comment.to_string())));
+        }
+        let (tag, attr_err) = match parse_tag(line) {
+            TagParse::NotTag => return Ok(Some(self.token(line))),
+            TagParse::Comment(c) => return Ok(Some(Event::Comment(c.to_string()))),
+            TagParse::Bad(msg) => return match self.opts.malformed {
+                Malformed::Lenient => Ok(Some(self.token(line))),
+                Malformed::Skip => Ok(None),
+                Malformed::Error => Err(self.error(ErrorKind::MalformedTag,
+                                                   &format!("{}: {}", msg, line))),
+            },
+            TagParse::Tag(tag, attr_err) => (tag, attr_err),
+        };
+        if !self.declared(tag.name) {
+            return Ok(Some(self.token(line)));
+        }
+        if let Some(msg) = attr_err {
+            match self.opts.malformed {
+                Malformed::Lenient => {},
+                Malformed::Skip => return Ok(None),
+                Malformed::Error => return Err(self.error(
+                    ErrorKind::MalformedAttrs, &format!("{}: {}", msg, line))),
+            }
+        }
+        Ok(Some(match tag.kind {
+            TagKind::Close => Event::Close { name: tag.name.to_string() },
+            TagKind::Empty if tag.name == self.opts.glue => Event::Glue,
+            TagKind::Empty => Event::Empty {
+                name: tag.name.to_string(), attrs: self.decode_attrs(tag.attrs) },
+            TagKind::Open => Event::Open {
+                name: tag.name.to_string(), attrs: self.decode_attrs(tag.attrs) },
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for Parser<R> {
+    type Item = Result<Line, VertError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_until(b'\n', &mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {},
+                Err(e) => return Some(Err(self.error(ErrorKind::Io, &e.to_string()))),
+            }
+            self.lineno += 1;
+            let buf = std::mem::take(&mut self.buf);
+            let text = match std::str::from_utf8(&buf) {
+                Ok(s) => Cow::Borrowed(s),
+                Err(_) => match self.opts.malformed {
+                    Malformed::Lenient => String::from_utf8_lossy(&buf),
+                    Malformed::Skip => { self.buf = buf; continue; },
+                    Malformed::Error => {
+                        let lossy = String::from_utf8_lossy(&buf);
+                        let err = self.error(ErrorKind::InvalidUtf8, lossy.trim());
+                        self.buf = buf;
+                        return Some(Err(err));
+                    },
+                },
+            };
+            let line = text.trim();
+            let res = if line.is_empty() { Ok(None) } else { self.parse_line(line) };
+            drop(text);
+            self.buf = buf;
+            match res {
+                Ok(Some(event)) => return Some(Ok(Line { num: self.lineno, event })),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Whether `name` is an XML name: a letter, `_` or `:` followed by
+/// letters, digits, `_`, `:`, `-`, `.` or `·`.
+pub fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.' | '\u{b7}'))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagKind { Open, Close, Empty }
+
+struct Tag<'a> {
+    name: &'a str,
+    kind: TagKind,
+    attrs: Vec<(&'a str, &'a str)>,
+}
+
+enum TagParse<'a> {
+    NotTag,
+    Comment(&'a str),
+    Bad(&'static str),
+    /// a tag and the reason its attributes could not be fully parsed
+    Tag(Tag<'a>, Option<&'static str>),
+}
+
+fn parse_tag(s: &str) -> TagParse<'_> {
+    if !(s.starts_with('<') && s.ends_with('>')) || s.len() < 2 {
+        return TagParse::NotTag;
+    }
+    let inner = &s[1..s.len() - 1];
+    if let Some(c) = inner.strip_prefix("!--") {
+        return TagParse::Comment(c.strip_suffix("--").unwrap_or(c).trim());
+    }
+    if inner.starts_with('?') || inner.starts_with('!') {
+        return TagParse::Comment(inner);
+    }
+    if let Some(name) = inner.strip_prefix('/') {
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return TagParse::Bad("bad closing tag");
+        }
+        return TagParse::Tag(Tag { name, kind: TagKind::Close, attrs: Vec::new() }, None);
+    }
+    let (content, kind) = match inner.strip_suffix('/') {
+        Some(c) => (c.trim(), TagKind::Empty),
+        None => (inner.trim(), TagKind::Open),
+    };
+    let name_end = content.find(char::is_whitespace).unwrap_or(content.len());
+    let name = &content[..name_end];
+    if name.is_empty() || name.contains(['<', '>', '=', '"', '\'']) {
+        return TagParse::Bad("bad tag name");
+    }
+    let (attrs, err) = parse_attrs(&content[name_end..]);
+    TagParse::Tag(Tag { name, kind, attrs }, err)
+}
+
+/// Parses `key="value" key='value' key=value ...`; on error returns the
+/// attributes read so far together with the reason.
+fn parse_attrs(mut s: &str) -> (Vec<(&str, &str)>, Option<&'static str>) {
+    let mut out = Vec::new();
+    loop {
+        s = s.trim_start();
+        if s.is_empty() {
+            return (out, None);
+        }
+        let key_end = match s.find(|c: char| c == '=' || c.is_whitespace()) {
+            Some(v) if v > 0 => v,
+            _ => return (out, Some("attribute without value")),
+        };
+        let key = &s[..key_end];
+        s = s[key_end..].trim_start();
+        s = match s.strip_prefix('=') {
+            Some(rest) => rest.trim_start(),
+            None => return (out, Some("attribute without value")),
+        };
+        if s.starts_with('"') || s.starts_with('\'') {
+            let quote = s.chars().next().unwrap();
+            s = &s[1..];
+            match s.find(quote) {
+                Some(end) => {
+                    out.push((key, &s[..end]));
+                    s = &s[end + 1..];
+                },
+                None => return (out, Some("unterminated attribute value")),
+            }
+        } else {
+            let end = s.find(char::is_whitespace).unwrap_or(s.len());
+            out.push((key, &s[..end]));
+            s = &s[end..];
+        }
+    }
+}
+
+/// Longest entity name between `&` and `;`, e.g. `#x0010FFFF`.
+const MAX_ENTITY_LEN: usize = 10;
+
+/// Decodes XML entities: the predefined named ones (`&amp;`, `&lt;`,
+/// `&gt;`, `&quot;`, `&apos;`) and decimal (`&#233;`) or hexadecimal
+/// (`&#xE9;`) character references. Anything else, including references
+/// to invalid code points, is left as is.
+pub fn unescape(s: &str) -> Cow<'_, str> {
+    if !s.contains('&') {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.bytes().take(MAX_ENTITY_LEN + 2).position(|b| b == b';')
+            .and_then(|semi| Some((decode_entity(&rest[1..semi])?, semi + 1)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let num = name.strip_prefix('#')?;
+            let (digits, radix) = match num.strip_prefix(['x', 'X']) {
+                Some(hex) => (hex, 16),
+                None => (num, 10),
+            };
+            // from_str_radix would also take a leading sign
+            if !digits.chars().all(|c| c.is_digit(radix)) {
+                return None;
+            }
+            let code = u32::from_str_radix(digits, radix).ok()?;
+            char::from_u32(code).filter(|&c| c != '\0')
+        }
+    }
+}
+
+/// Escapes `&`, `<` and `>` so that the text can be written as a token
+/// field; the inverse of `unescape`.
+pub fn escape(s: &str) -> Cow<'_, str> {
+    escape_chars(s, false)
+}
+
+/// Like `escape`, but also escapes `"` for use in a double-quoted
+/// attribute value.
+pub fn escape_attr(s: &str) -> Cow<'_, str> {
+    escape_chars(s, true)
+}
+
+fn escape_chars(s: &str, quot: bool) -> Cow<'_, str> {
+    let special = |c: char| matches!(c, '&' | '<' | '>') || (quot && c == '"');
+    if !s.contains(special) {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if quot => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
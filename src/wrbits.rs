@@ -4,15 +4,15 @@ use std::io::{BufWriter, Write};
 
 type Atom = usize;
 
-pub struct BitsWriter {
+pub struct BitsWriter<W: Write = BufWriter<File>> {
     part: Atom,
     freebits: usize,
-    target: BufWriter<File>,
+    target: W,
     total_bits: u64,
 }
 
-impl BitsWriter {
-    pub fn new(target: BufWriter<File>) -> BitsWriter {
+impl<W: Write> BitsWriter<W> {
+    pub fn new(target: W) -> BitsWriter<W> {
         BitsWriter {
             part: 0,
             freebits: Atom::BITS as usize,
@@ -109,7 +109,7 @@ impl BitsWriter {
         self.total_bits += Atom::BITS as u64;
     }
 
-    pub fn finish(mut self) -> Result<BufWriter<File>, Box<dyn std::error::Error>> {
+    pub fn finish(mut self) -> Result<W, Box<dyn std::error::Error>> {
         if self.usedbits() > 0 {
             let num_bytes = (self.usedbits() + 7) / 8;
             self.target.write_all(&self.part.to_le_bytes()[0..num_bytes])?;
@@ -0,0 +1,338 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::collate::{self, Collator};
+use crate::corp::conf_bool;
+use crate::lex::MapLex;
+use crate::util::add_suffix;
+
+/// Memory used by the in-memory part of a lexicon before it is spilled
+/// to disk, unless configured otherwise.
+pub const DEFAULT_MEM_LIMIT: usize = 1 << 30;
+/// approximate per-entry overhead of the hash map
+const ENTRY_OVERHEAD: usize = 48;
+/// number of runs of the same tier merged into one run of the next tier
+const TIER_RUNS: usize = 8;
+
+/// Builds a lexicon (`.lex`, `.lex.idx`, `.lex.srt`), assigning ids in
+/// order of first occurrence.
+///
+/// Values are looked up in a hash map. When the map grows over the memory
+/// limit, it is written to disk as a sorted run and cleared; lookups then
+/// also binary-search the runs, and `.lex.srt` is produced by merging
+/// them. Runs are merged in tiers: [`TIER_RUNS`] runs of one tier make a
+/// run of the next, so each value is rewritten a logarithmic number of
+/// times. The result does not depend on the limit.
+pub struct LexWriter {
+    base: PathBuf,
+    lex: BufWriter<File>,
+    idx: BufWriter<File>,
+    map: HashMap<String, u32>,
+    map_bytes: usize,
+    mem_limit: Option<usize>,
+    runs: Vec<Run>,
+    next_run: usize,
+    count: u32,
+    bytes: u32,
+}
+
+impl LexWriter {
+    pub fn new(base: &Path) -> Result<LexWriter, Box<dyn std::error::Error>> {
+        LexWriter::open(base, None, Some(DEFAULT_MEM_LIMIT))
+    }
+
+    /// Continues the lexicon at `from`, writing the extended lexicon to
+    /// `base`; existing ids are kept, new values get ids after them.
+    pub fn append(from: &Path, base: &Path) -> Result<LexWriter, Box<dyn std::error::Error>> {
+        LexWriter::open(base, Some(from), Some(DEFAULT_MEM_LIMIT))
+    }
+
+    /// Starts a lexicon at `base`, continuing `from` if given; `mem_limit`
+    /// of `None` keeps everything in memory.
+    pub fn open(base: &Path, from: Option<&Path>, mem_limit: Option<usize>)
+            -> Result<LexWriter, Box<dyn std::error::Error>> {
+        let lex_path = add_suffix(base, ".lex");
+        let idx_path = add_suffix(base, ".lex.idx");
+        let (lex, idx) = match from {
+            Some(from) => {
+                fs::copy(add_suffix(from, ".lex"), &lex_path)?;
+                fs::copy(add_suffix(from, ".lex.idx"), &idx_path)?;
+                (OpenOptions::new().append(true).open(&lex_path)?,
+                 OpenOptions::new().append(true).open(&idx_path)?)
+            }
+            None => (File::create(&lex_path)?, File::create(&idx_path)?),
+        };
+        let bytes = u32::try_from(lex.metadata()?.len()).map_err(|_| "lexicon offset overflow")?;
+        let mut lw = LexWriter {
+            base: base.to_path_buf(),
+            lex: BufWriter::new(lex),
+            idx: BufWriter::new(idx),
+            map: HashMap::new(),
+            map_bytes: 0,
+            mem_limit,
+            runs: Vec::new(),
+            next_run: 0,
+            count: 0,
+            bytes,
+        };
+        // empty files cannot be mapped
+        if let Some(from) = from && lw.bytes > 0 {
+            let lex = map_file(&add_suffix(from, ".lex"))?;
+            let idx = map_file(&add_suffix(from, ".lex.idx"))?;
+            for id in 0..idx.len() / 4 {
+                lw.insert(std::str::from_utf8(lex_entry(&lex, &idx, id))?.to_string(), id as u32)?;
+            }
+            lw.count = (idx.len() / 4) as u32;
+        }
+        Ok(lw)
+    }
+
+    pub fn id_for(&mut self, value: &str) -> Result<u32, Box<dyn std::error::Error>> {
+        if let Some(&id) = self.map.get(value) {
+            return Ok(id);
+        }
+        if let Some(id) = self.runs.iter().find_map(|r| r.find(value.as_bytes())) {
+            return Ok(id);
+        }
+        let id = self.count;
+        self.count = self.count.checked_add(1).ok_or("lexicon id overflow")?;
+        self.insert(value.to_string(), id)?;
+        self.idx.write_all(&self.bytes.to_le_bytes())?;
+        self.lex.write_all(value.as_bytes())?;
+        self.lex.write_all(&[0])?;
+        self.bytes = self
+            .bytes
+            .checked_add(value.len() as u32 + 1)
+            .ok_or("lexicon offset overflow")?;
+        Ok(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn insert(&mut self, value: String, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.map_bytes += value.len() + ENTRY_OVERHEAD;
+        self.map.insert(value, id);
+        if self.mem_limit.is_some_and(|l| l > 0 && self.map_bytes > l) {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the in-memory map to a sorted run on disk.
+    fn spill(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut entries: Vec<(String, u32)> = self.map.drain().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        self.map_bytes = 0;
+        let path = self.run_path();
+        self.runs.push(Run::write(&path, entries.iter().map(|(s, id)| (s.as_bytes(), *id)), 0)?);
+        // tiers do not increase along `runs`, the last ones are the lowest
+        while let Some(tier) = self.runs.last().map(|r| r.tier) {
+            let n = self.runs.iter().rev().take_while(|r| r.tier == tier).count();
+            if n < TIER_RUNS {
+                break;
+            }
+            let path = self.run_path();
+            let start = self.runs.len() - n;
+            let merged = {
+                let runs: Vec<&Run> = self.runs[start..].iter().collect();
+                Run::write(&path, merge_runs(&runs), tier + 1)?
+            };
+            self.runs.truncate(start);
+            self.runs.push(merged);
+        }
+        Ok(())
+    }
+
+    fn run_path(&mut self) -> PathBuf {
+        self.next_run += 1;
+        add_suffix(&self.base, &format!(".lex.run{}", self.next_run))
+    }
+
+    pub fn finalize(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.lex.flush()?;
+        self.idx.flush()?;
+
+        let mut srt = BufWriter::new(File::create(add_suffix(&self.base, ".lex.srt"))?);
+        if self.runs.is_empty() {
+            let mut pairs: Vec<(&String, &u32)> = self.map.iter().collect();
+            pairs.sort_by(|a, b| a.0.cmp(b.0));
+            for (_, id) in pairs {
+                srt.write_all(&id.to_le_bytes())?;
+            }
+        } else {
+            if !self.map.is_empty() {
+                self.spill()?;
+            }
+            let runs: Vec<&Run> = self.runs.iter().collect();
+            for (_, id) in merge_runs(&runs) {
+                srt.write_all(&id.to_le_bytes())?;
+            }
+        }
+        srt.flush()?;
+        Ok(())
+    }
+}
+
+/// Optional secondary indexes of a lexicon, as configured for an attribute.
+#[derive(Clone, Debug, Default)]
+pub struct LexIndexes {
+    /// `.lex.lsrt` in the collation order of `LOCALE`
+    pub collator: Option<Collator>,
+    /// `.lex.fld` for case- and diacritics-insensitive lookups (`FOLDEDLEX`)
+    pub folded: bool,
+}
+
+impl LexIndexes {
+    /// Indexes configured for `attr`, with corpus-level defaults.
+    pub fn from_conf(conf: &corpconf::Block, attr: &corpconf::Block) -> LexIndexes {
+        let folded = if attr.value("FOLDEDLEX").is_some() {
+            conf_bool(attr, "FOLDEDLEX")
+        } else {
+            conf_bool(conf, "FOLDEDLEX")
+        };
+        LexIndexes { collator: collate::from_conf(conf, attr), folded }
+    }
+
+    /// Builds the indexes for the finalized lexicon at `base`.
+    pub fn write(&self, base: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(coll) = &self.collator {
+            collate::write_lsrt(base, coll)?;
+        }
+        if self.folded {
+            write_folded(base)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `.lex.fld`: ids of the lexicon at `base` sorted by their values
+/// with case and diacritics folded (see [`collate::fold`]).
+pub fn write_folded(base: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".lex.fld"))?);
+    // empty files cannot be mapped
+    if fs::metadata(add_suffix(base, ".lex.idx"))?.len() == 0 {
+        return Ok(());
+    }
+    let lex = MapLex::open(base.to_str().ok_or("bad path")?)?;
+    let mut keys: Vec<(String, u32)> = (0..lex.id_range())
+        .map(|id| (collate::fold(lex.id2str(id), true, true), id))
+        .collect();
+    keys.sort_unstable();
+    for (_, id) in keys {
+        f.write_all(&id.to_le_bytes())?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+/// Sorted (string, id) pairs spilled by a `LexWriter`: the strings
+/// concatenated in `path`, and an index of (offset u64, length u32, id u32)
+/// records in `path.idx`. The files are removed when the run is dropped.
+struct Run {
+    path: PathBuf,
+    /// number of merges the entries went through
+    tier: u32,
+    data: memmap::Mmap,
+    index: memmap::Mmap,
+}
+
+impl Run {
+    fn write<'a, I>(path: &Path, entries: I, tier: u32) -> Result<Run, Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = (&'a [u8], u32)>,
+    {
+        let idx_path = add_suffix(path, ".idx");
+        let mut data = BufWriter::new(File::create(path)?);
+        let mut index = BufWriter::new(File::create(&idx_path)?);
+        let mut off = 0u64;
+        for (s, id) in entries {
+            data.write_all(s)?;
+            index.write_all(&off.to_le_bytes())?;
+            index.write_all(&(s.len() as u32).to_le_bytes())?;
+            index.write_all(&id.to_le_bytes())?;
+            off += s.len() as u64;
+        }
+        // keeps the file mappable when all strings are empty
+        data.write_all(&[0])?;
+        data.flush()?;
+        index.flush()?;
+        Ok(Run {
+            path: path.to_path_buf(),
+            tier,
+            data: map_file(path)?,
+            index: map_file(&idx_path)?,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.index.len() / 16
+    }
+
+    fn get(&self, i: usize) -> (&[u8], u32) {
+        let rec = &self.index[i * 16..i * 16 + 16];
+        let off = u64::from_le_bytes(rec[0..8].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(rec[8..12].try_into().unwrap()) as usize;
+        let id = u32::from_le_bytes(rec[12..16].try_into().unwrap());
+        (&self.data[off..off + len], id)
+    }
+
+    fn find(&self, s: &[u8]) -> Option<u32> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (v, id) = self.get(mid);
+            match v.cmp(s) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(id),
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(add_suffix(&self.path, ".idx"));
+    }
+}
+
+/// K-way merge of sorted runs with distinct strings.
+fn merge_runs<'a>(runs: &[&'a Run]) -> impl Iterator<Item = (&'a [u8], u32)> + use<'a> {
+    let mut heap = BinaryHeap::new();
+    for (r, run) in runs.iter().enumerate() {
+        if run.len() > 0 {
+            heap.push(Reverse((run.get(0).0, r, 0usize)));
+        }
+    }
+    let runs = runs.to_vec();
+    std::iter::from_fn(move || {
+        let Reverse((s, r, i)) = heap.pop()?;
+        let id = runs[r].get(i).1;
+        if i + 1 < runs[r].len() {
+            heap.push(Reverse((runs[r].get(i + 1).0, r, i + 1)));
+        }
+        Some((s, id))
+    })
+}
+
+fn map_file(path: &Path) -> Result<memmap::Mmap, Box<dyn std::error::Error>> {
+    let f = File::open(path)?;
+    Ok(unsafe { memmap::MmapOptions::new().map(&f)? })
+}
+
+fn lex_entry<'a>(lex: &'a [u8], idx: &[u8], id: usize) -> &'a [u8] {
+    let ofs = u32::from_le_bytes(idx[id * 4..id * 4 + 4].try_into().unwrap()) as usize;
+    let end = lex[ofs..].iter().position(|&b| b == 0).map_or(lex.len(), |p| ofs + p);
+    &lex[ofs..end]
+}
@@ -0,0 +1,268 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::lex::MapLex;
+use crate::rev;
+use crate::text::{self, Text};
+use crate::util::add_suffix;
+use crate::wrbits::BitsWriter;
+
+pub const REV_MAGIC: [u8; 6] = [0xa3, b'f', b'i', b'n', b'D', b'R'];
+pub const REV_DENSE_MAGIC: [u8; 6] = [0xa8, b'f', b'i', b'n', b'D', b'R'];
+pub const USE_DELTA_DENSE_REV: bool = true;
+
+/// Writes the rev files for ids `0..nids`; `poss` yields the ascending
+/// positions of an id.
+pub fn write_rev<F, I>(base: &Path, dense: bool, nids: usize, poss: F)
+        -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(u32) -> I,
+    I: IntoIterator<Item = u64>,
+{
+    if dense {
+        write_rev_dense(base, nids, poss)
+    } else {
+        write_rev_delta(base, nids, poss)
+    }
+}
+
+fn write_poslist<I>(bw: &mut BitsWriter, poslist: I) -> Result<u32, Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = u64>,
+{
+    let mut cnt: u32 = 0;
+    let mut last: i64 = -1;
+    for p in poslist {
+        if p as i64 <= last {
+            return Err("rev positions not ascending".into());
+        }
+        bw.delta((p as i64 - last) as u64);
+        last = p as i64;
+        cnt = cnt.checked_add(1).ok_or("rev count overflow")?;
+    }
+    Ok(cnt)
+}
+
+fn write_rev_delta<F, I>(base: &Path, nids: usize, mut poss: F)
+        -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(u32) -> I,
+    I: IntoIterator<Item = u64>,
+{
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".rev"))?);
+    f.write_all(&REV_MAGIC)?;
+    f.flush()?;
+
+    let mut hbw = BitsWriter::new(f);
+    hbw.delta(2);
+    let mut f = hbw.finish()?;
+    let header_end = f.stream_position()?;
+    f.seek(SeekFrom::Start(header_end))?;
+    let mut bw = BitsWriter::new(f);
+
+    let mut idx = Vec::with_capacity(nids);
+    let mut cnts = Vec::with_capacity(nids);
+    for id in 0..nids as u32 {
+        bw.byte_align();
+        let bitpos = bw.bits_written();
+        let byte_off = header_end + (bitpos / 8);
+        if byte_off > u32::MAX as u64 {
+            return Err("rev offset overflow".into());
+        }
+        idx.push(byte_off as u32);
+        cnts.push(write_poslist(&mut bw, poss(id))?);
+    }
+    let _f = bw.finish()?;
+
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".rev.idx"))?);
+    for off in idx {
+        f.write_all(&off.to_le_bytes())?;
+    }
+    f.flush()?;
+
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".rev.cnt"))?);
+    for cnt in cnts {
+        f.write_all(&cnt.to_le_bytes())?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+fn write_rev_dense<F, I>(base: &Path, nids: usize, mut poss: F)
+        -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(u32) -> I,
+    I: IntoIterator<Item = u64>,
+{
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".rev"))?);
+    f.write_all(&REV_DENSE_MAGIC)?;
+    f.flush()?;
+    let data_start = f.stream_position()?;
+    let mut bw = BitsWriter::new(f);
+
+    let mut byte_offsets: Vec<u32> = Vec::with_capacity(nids);
+    let mut cnts: Vec<u32> = Vec::with_capacity(nids);
+    for id in 0..nids as u32 {
+        bw.byte_align();
+        let bitpos = bw.bits_written();
+        let byte_off = data_start + (bitpos / 8);
+        if byte_off > u32::MAX as u64 {
+            return Err("rev dense offset overflow".into());
+        }
+        byte_offsets.push(byte_off as u32);
+        cnts.push(write_poslist(&mut bw, poss(id))?);
+    }
+    let _f = bw.finish()?;
+
+    let mut idx0: Vec<u32> = Vec::new();
+    let idx1 = BufWriter::new(File::create(add_suffix(base, ".rev.idx1"))?);
+    let mut bw1 = BitsWriter::new(idx1);
+    let mut block_start = 0usize;
+    while block_start < byte_offsets.len() {
+        bw1.byte_align();
+        let idx1_byte = bw1.bits_written() / 8;
+        if idx1_byte > u32::MAX as u64 {
+            return Err("rev dense idx1 overflow".into());
+        }
+        idx0.push(idx1_byte as u32);
+
+        let mut last_off: u32 = 0;
+        let end = std::cmp::min(block_start + 64, byte_offsets.len());
+        for i in block_start..end {
+            let off = byte_offsets[i];
+            let delta = off.wrapping_sub(last_off);
+            if delta == 0 {
+                return Err("invalid zero delta in rev dense".into());
+            }
+            bw1.delta(delta as u64);
+            bw1.gamma(cnts[i] as u64 + 1);
+            last_off = off;
+        }
+        bw1.delta(1);
+        bw1.gamma(1);
+        block_start += 64;
+    }
+    let mut idx1_file = bw1.finish()?;
+    idx1_file.flush()?;
+    let idx1_end = idx1_file.stream_position()?;
+    if idx1_end > u32::MAX as u64 {
+        return Err("rev dense idx1 overflow".into());
+    }
+    idx0.push(idx1_end as u32);
+
+    let mut f = BufWriter::new(File::create(add_suffix(base, ".rev.idx0"))?);
+    for off in idx0 {
+        f.write_all(&off.to_le_bytes())?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+pub fn open_text(base: &Path) -> Result<Box<dyn Text>, Box<dyn std::error::Error>> {
+    if add_suffix(base, ".text.off").exists() {
+        Ok(Box::new(text::GigaDelta::open(base.to_str().ok_or("bad path")?)?))
+    } else if add_suffix(base, ".text.seg").exists() {
+        Ok(Box::new(text::Delta::open(base.to_str().ok_or("bad path")?)?))
+    } else {
+        Ok(Box::new(text::Int::open(base.to_str().ok_or("bad path")?)?))
+    }
+}
+
+/// Positions of each id in the text at `base` from `from` on. With
+/// `multisep`, positions also count for the components of the value,
+/// which have to be in the lexicon.
+fn collect_positions(base: &Path, from: u64, multisep: Option<&str>)
+        -> Result<Vec<Vec<u32>>, Box<dyn std::error::Error>> {
+    let text = open_text(base)?;
+    let size = text.size() as u64;
+    let mut positions: Vec<Vec<u32>> = Vec::new();
+    if from >= size {
+        return Ok(positions);
+    }
+    let lex = match multisep {
+        Some(_) => Some(MapLex::open(base.to_str().ok_or("bad path")?)?),
+        None => None,
+    };
+    let mut components: Vec<Option<Vec<u32>>> = Vec::new();
+    let mut it: Box<dyn Iterator<Item = u32>> = if let Some(it) = text.posat(from) {
+        Box::new(it)
+    } else if let Some(it) = text.structat(from) {
+        Box::new(it)
+    } else {
+        return Err("text type not supported".into());
+    };
+    for pos in from..size {
+        let id = it.next().ok_or("text underflow")?;
+        let idx = id as usize;
+        if idx >= positions.len() {
+            positions.resize_with(idx + 1, Vec::new);
+        }
+        positions[idx].push(pos as u32);
+        if let (Some(lex), Some(sep)) = (&lex, multisep) {
+            if idx >= components.len() {
+                components.resize(idx + 1, None);
+            }
+            let comps = components[idx].get_or_insert_with(|| component_ids(lex, id, sep));
+            for &c in comps.iter() {
+                let c = c as usize;
+                if c >= positions.len() {
+                    positions.resize_with(c + 1, Vec::new);
+                }
+                positions[c].push(pos as u32);
+            }
+        }
+    }
+    Ok(positions)
+}
+
+/// Components of a multivalue attribute value; none for values without
+/// the separator.
+pub fn split_multivalue<'a>(value: &'a str, sep: &'a str) -> impl Iterator<Item = &'a str> {
+    let parts = if value.contains(sep) { Some(value.split(sep)) } else { None };
+    parts.into_iter().flatten().filter(|c| !c.is_empty())
+}
+
+/// Ids of the components of the multivalue `id` other than `id` itself,
+/// sorted; the rev lists the positions of `id` under these as well.
+pub fn component_ids(lex: &MapLex, id: u32, sep: &str) -> Vec<u32> {
+    let mut ids: Vec<u32> = split_multivalue(lex.id2str(id), sep)
+        .filter_map(|c| lex.str2id(c))
+        .filter(|&c| c != id)
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// Builds the rev files for the text at `base`, as mkrev does; `multisep`
+/// is the separator of a multivalue attribute.
+pub fn build_rev(base: &Path, multisep: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut positions = collect_positions(base, 0, multisep)?;
+    if multisep.is_some() {
+        // components need not occur as values on their own
+        let nids = fs::metadata(add_suffix(base, ".lex.idx"))?.len() as usize / 4;
+        positions.resize_with(nids.max(positions.len()), Vec::new);
+    }
+    write_rev(base, USE_DELTA_DENSE_REV, positions.len(),
+              |id| positions[id as usize].iter().map(|&p| p as u64))
+}
+
+/// Writes the rev files for the text at `base`, which extends the text at
+/// `from` by the positions `old_size..`, merging the postings of the
+/// existing rev at `from`. The rev format of `from` is kept.
+pub fn append_rev(from: &Path, base: &Path, old_size: u64, multisep: Option<&str>)
+        -> Result<(), Box<dyn std::error::Error>> {
+    let mut magic = [0u8; 6];
+    File::open(add_suffix(from, ".rev"))?.read_exact(&mut magic)?;
+    let dense = magic == REV_DENSE_MAGIC;
+    let old = rev::open(from.to_str().ok_or("bad path")?)?;
+    let old_nids = fs::metadata(add_suffix(from, ".lex.idx"))?.len() as usize / 4;
+    let nids = fs::metadata(add_suffix(base, ".lex.idx"))?.len() as usize / 4;
+    let new = collect_positions(base, old_size, multisep)?;
+    let (old, new) = (&old, &new);
+    write_rev(base, dense, nids.max(new.len()), |id| {
+        ((id as usize) < old_nids).then(|| old.id2poss(id)).into_iter().flatten()
+            .chain(new.get(id as usize).into_iter().flatten().map(|&p| p as u64))
+    })
+}
@@ -0,0 +1,414 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::bits;
+use crate::util::{add_suffix, as_slice_ref};
+use crate::wrbits::BitsWriter;
+
+pub const TEXT_MAGIC: [u8; 6] = [0xa3, b'f', b'i', b'n', b'D', b'T'];
+pub const INT_MAGIC: [u8; 6] = [0xa3, b'f', b'i', b'n', b'I', b'T'];
+pub const DEFAULT_SEGMENT_SIZE: usize = 128;
+
+const DATA_ALIGN: u64 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextType {
+    Delta,
+    Int,
+    GigaDelta,
+}
+
+impl TextType {
+    /// text type for a positional attribute, as encodevert understands
+    /// the attribute `TYPE`
+    pub fn from_conf(attr: &corpconf::Block) -> TextType {
+        match attr.value("TYPE") {
+            Some("MD_MI") | Some("Int") => TextType::Int,
+            Some("MD_MGD") | Some("FD_MGD") | Some("FD_FGD") | Some("NoMem") => TextType::GigaDelta,
+            _ => TextType::Delta,
+        }
+    }
+}
+
+pub trait TextWriter: Send {
+    fn push(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>>;
+    fn finalize(self: Box<Self>) -> Result<(), Box<dyn std::error::Error>>;
+    /// number of positions written so far, including appended-to data
+    fn count(&self) -> u64;
+}
+
+pub fn new_text_writer(base: &Path, text_type: TextType, segment_size: usize)
+        -> Result<Box<dyn TextWriter>, Box<dyn std::error::Error>> {
+    Ok(match text_type {
+        TextType::Delta => Box::new(DeltaTextWriter::new(base, segment_size)?),
+        TextType::Int => Box::new(IntTextWriter::new(base)?),
+        TextType::GigaDelta => Box::new(GigaDeltaTextWriter::new(base)?),
+    })
+}
+
+/// Opens a writer that continues the text at `from`, writing the extended
+/// text to `base`; `from` itself is left untouched.
+pub fn append_text_writer(from: &Path, base: &Path, text_type: TextType)
+        -> Result<Box<dyn TextWriter>, Box<dyn std::error::Error>> {
+    Ok(match text_type {
+        TextType::Delta => Box::new(DeltaTextWriter::append(from, base)?),
+        TextType::Int => Box::new(IntTextWriter::append(from, base)?),
+        TextType::GigaDelta => Box::new(GigaDeltaTextWriter::append(from, base)?),
+    })
+}
+
+pub struct DeltaTextWriter {
+    base: PathBuf,
+    bw: Option<BitsWriter>,
+    seg: BufWriter<File>,
+    segment_size: usize,
+    data_start: u64,
+    count: u64,
+}
+
+impl DeltaTextWriter {
+    pub fn new(base: &Path, segment_size: usize) -> Result<DeltaTextWriter, Box<dyn std::error::Error>> {
+        let mut f = BufWriter::new(File::create(add_suffix(base, ".text"))?);
+        f.write_all(&TEXT_MAGIC)?;
+        f.write_all(&[0u8; 10])?;
+        f.flush()?;
+
+        f.seek(SeekFrom::Start(16))?;
+        let mut hbw = BitsWriter::new(f);
+        hbw.delta(segment_size as u64 + 1);
+        hbw.delta(1);
+        let mut f = hbw.finish()?;
+        let header_end = f.stream_position()?;
+        let data_start = align_writer(&mut f, header_end, DATA_ALIGN)?;
+        f.seek(SeekFrom::Start(data_start))?;
+
+        Ok(DeltaTextWriter {
+            base: base.to_path_buf(),
+            bw: Some(BitsWriter::new(f)),
+            seg: BufWriter::new(File::create(add_suffix(base, ".text.seg"))?),
+            segment_size,
+            data_start,
+            count: 0,
+        })
+    }
+
+    pub fn append(from: &Path, base: &Path) -> Result<DeltaTextWriter, Box<dyn std::error::Error>> {
+        let (segment_size, count) = read_text_header(from)?;
+        let mut seg = read_u32s(&add_suffix(from, ".text.seg"))?;
+        // the last entry is the end of the data, the segment table
+        // proper continues in its place
+        let end_bitpos = seg.pop().ok_or("empty text segment table")? as u64;
+        let (bw, data_start) = resume_bits(from, base, end_bitpos)?;
+
+        let mut segf = BufWriter::new(File::create(add_suffix(base, ".text.seg"))?);
+        for s in seg {
+            segf.write_all(&s.to_le_bytes())?;
+        }
+        Ok(DeltaTextWriter {
+            base: base.to_path_buf(),
+            bw: Some(bw),
+            seg: segf,
+            segment_size: segment_size as usize,
+            data_start,
+            count,
+        })
+    }
+
+    fn finish_data(&mut self) -> Result<(Vec<u32>, u64), Box<dyn std::error::Error>> {
+        let bw = self.bw.take().ok_or("delta writer already finished")?;
+        let total_bits = bw.bits_written();
+        let _f = bw.finish()?;
+        let end_bitpos = self.data_start * 8 + total_bits;
+        if end_bitpos > u32::MAX as u64 {
+            return Err("text segment offset overflow".into());
+        }
+        self.seg.write_all(&(end_bitpos as u32).to_le_bytes())?;
+        self.seg.flush()?;
+        Ok((Vec::new(), self.count))
+    }
+}
+
+impl TextWriter for DeltaTextWriter {
+    fn push(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        if (self.count as usize).is_multiple_of(self.segment_size) {
+            let bw = self.bw.as_ref().ok_or("delta writer already finished")?;
+            let bitpos = self.data_start * 8 + bw.bits_written();
+            if bitpos > u32::MAX as u64 {
+                return Err("text segment offset overflow".into());
+            }
+            self.seg.write_all(&(bitpos as u32).to_le_bytes())?;
+        }
+        let bw = self.bw.as_mut().ok_or("delta writer already finished")?;
+        bw.delta(id as u64 + 1);
+        self.count += 1;
+        Ok(())
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), Box<dyn std::error::Error>> {
+        let (_seg, count) = self.finish_data()?;
+        write_text_header(&self.base, self.segment_size as u64, count)
+    }
+}
+
+pub struct GigaDeltaTextWriter {
+    base: PathBuf,
+    bw: Option<BitsWriter>,
+    offsets: BufWriter<File>,
+    segments: BufWriter<File>,
+    data_start: u64,
+    count: u64,
+    seg_base_bits: u64,
+}
+
+impl GigaDeltaTextWriter {
+    pub fn new(base: &Path) -> Result<GigaDeltaTextWriter, Box<dyn std::error::Error>> {
+        let mut f = BufWriter::new(File::create(add_suffix(base, ".text"))?);
+        f.write_all(&TEXT_MAGIC)?;
+        f.write_all(&[0u8; 10])?;
+        f.flush()?;
+
+        f.seek(SeekFrom::Start(16))?;
+        let mut hbw = BitsWriter::new(f);
+        hbw.delta(64 + 1);
+        hbw.delta(1);
+        let mut f = hbw.finish()?;
+        let header_end = f.stream_position()?;
+        let data_start = align_writer(&mut f, header_end, DATA_ALIGN)?;
+        f.seek(SeekFrom::Start(data_start))?;
+
+        Ok(GigaDeltaTextWriter {
+            base: base.to_path_buf(),
+            bw: Some(BitsWriter::new(f)),
+            offsets: BufWriter::new(File::create(add_suffix(base, ".text.off"))?),
+            segments: BufWriter::new(File::create(add_suffix(base, ".text.seg"))?),
+            data_start,
+            count: 0,
+            seg_base_bits: 0,
+        })
+    }
+
+    pub fn append(from: &Path, base: &Path) -> Result<GigaDeltaTextWriter, Box<dyn std::error::Error>> {
+        let (_, count) = read_text_header(from)?;
+        let offsets = read_u16s(&add_suffix(from, ".text.off"))?;
+        let segments = read_u32s(&add_suffix(from, ".text.seg"))?;
+
+        // GigaDelta keeps no end marker, decode the tail of the last block
+        let (end_bitpos, seg_base_bits) = if count == 0 {
+            (DATA_ALIGN * 8, 0)
+        } else {
+            let last = count - 1;
+            let seg_base_bits = *segments.get(last as usize / (64 * 16))
+                .ok_or("truncated text segment table")? as u64 * 2048 * 8;
+            let block_start = seg_base_bits + *offsets.get(last as usize / 64)
+                .ok_or("truncated text offset table")? as u64;
+            let f = File::open(add_suffix(from, ".text"))?;
+            let map = unsafe { memmap::MmapOptions::new().map(&f)? };
+            let mut rb = bits::Reader::open(as_slice_ref(&map), block_start as usize);
+            for _ in 0..(last % 64) + 1 {
+                rb.delta();
+            }
+            (rb.tell() as u64, seg_base_bits)
+        };
+        let (bw, data_start) = resume_bits(from, base, end_bitpos)?;
+
+        let mut offf = BufWriter::new(File::create(add_suffix(base, ".text.off"))?);
+        for o in &offsets[..count.div_ceil(64) as usize] {
+            offf.write_all(&o.to_le_bytes())?;
+        }
+        let mut segf = BufWriter::new(File::create(add_suffix(base, ".text.seg"))?);
+        for s in &segments[..count.div_ceil(64 * 16) as usize] {
+            segf.write_all(&s.to_le_bytes())?;
+        }
+        Ok(GigaDeltaTextWriter {
+            base: base.to_path_buf(),
+            bw: Some(bw),
+            offsets: offf,
+            segments: segf,
+            data_start,
+            count,
+            seg_base_bits,
+        })
+    }
+
+    fn finish_data(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        let bw = self.bw.take().ok_or("gigadelta writer already finished")?;
+        let _f = bw.finish()?;
+        self.offsets.flush()?;
+        self.segments.flush()?;
+        Ok(self.count)
+    }
+}
+
+impl TextWriter for GigaDeltaTextWriter {
+    fn push(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let i = self.count as usize;
+        if i.is_multiple_of(64 * 16) {
+            let bw = self.bw.as_ref().ok_or("gigadelta writer already finished")?;
+            let bitpos = self.data_start * 8 + bw.bits_written();
+            let base_block = bitpos / (2048 * 8);
+            if base_block > u32::MAX as u64 {
+                return Err("text segment offset overflow".into());
+            }
+            self.segments.write_all(&(base_block as u32).to_le_bytes())?;
+            self.seg_base_bits = base_block * (2048 * 8);
+        }
+        if i.is_multiple_of(64) {
+            let bw = self.bw.as_ref().ok_or("gigadelta writer already finished")?;
+            let bitpos = self.data_start * 8 + bw.bits_written();
+            let rel = bitpos - self.seg_base_bits;
+            if rel > u16::MAX as u64 {
+                return Err("text offset overflow".into());
+            }
+            self.offsets.write_all(&(rel as u16).to_le_bytes())?;
+        }
+        let bw = self.bw.as_mut().ok_or("gigadelta writer already finished")?;
+        bw.delta(id as u64 + 1);
+        self.count += 1;
+        Ok(())
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), Box<dyn std::error::Error>> {
+        let count = self.finish_data()?;
+        write_text_header(&self.base, 64, count)
+    }
+}
+
+pub struct IntTextWriter {
+    f: BufWriter<File>,
+    count: u64,
+}
+
+impl IntTextWriter {
+    pub fn new(base: &Path) -> Result<IntTextWriter, Box<dyn std::error::Error>> {
+        let mut f = BufWriter::new(File::create(add_suffix(base, ".text"))?);
+        f.write_all(&INT_MAGIC)?;
+        f.write_all(&[0u8; 10])?;
+        Ok(IntTextWriter {
+            f,
+            count: 0,
+        })
+    }
+
+    pub fn append(from: &Path, base: &Path) -> Result<IntTextWriter, Box<dyn std::error::Error>> {
+        let target = add_suffix(base, ".text");
+        fs::copy(add_suffix(from, ".text"), &target)?;
+        let f = OpenOptions::new().append(true).open(&target)?;
+        let count = (f.metadata()?.len().saturating_sub(16)) / 4;
+        Ok(IntTextWriter {
+            f: BufWriter::new(f),
+            count,
+        })
+    }
+}
+
+impl TextWriter for IntTextWriter {
+    fn push(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.f.write_all(&id.to_le_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), Box<dyn std::error::Error>> {
+        self.f.flush()?;
+        Ok(())
+    }
+}
+
+fn write_text_header(base: &Path, segment_size: u64, count: u64)
+        -> Result<(), Box<dyn std::error::Error>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(add_suffix(base, ".text"))?;
+    let mut f = BufWriter::new(file);
+    f.seek(SeekFrom::Start(16))?;
+    let mut hbw = BitsWriter::new(f);
+    hbw.delta(segment_size + 1);
+    hbw.delta(count + 1);
+    let mut f = hbw.finish()?;
+    f.flush()?;
+    Ok(())
+}
+
+/// (segment size, number of positions) from a delta-coded text header
+fn read_text_header(base: &Path) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let f = File::open(add_suffix(base, ".text"))?;
+    let map = unsafe { memmap::MmapOptions::new().map(&f)? };
+    if map.len() < 32 || map[0..6] != TEXT_MAGIC {
+        return Err(format!("bad text header: {}", base.display()).into());
+    }
+    let mut rb = bits::Reader::open(as_slice_ref(&map), 16 * 8);
+    let segment_size = rb.delta() - 1;
+    let count = rb.delta() - 1;
+    Ok((segment_size, count))
+}
+
+/// Copies the delta-coded text `from` up to bit `end_bitpos` into `base`
+/// and returns a writer positioned right after it, together with the
+/// byte offset the writer's bit count is relative to.
+fn resume_bits(from: &Path, base: &Path, end_bitpos: u64)
+        -> Result<(BitsWriter, u64), Box<dyn std::error::Error>> {
+    let target = add_suffix(base, ".text");
+    fs::copy(add_suffix(from, ".text"), &target)?;
+    let keep = end_bitpos / 8;
+    let rest_bits = end_bitpos % 8;
+    let mut partial = [0u8];
+    {
+        use std::io::Read;
+        let mut f = File::open(&target)?;
+        f.seek(SeekFrom::Start(keep))?;
+        if rest_bits > 0 {
+            f.read_exact(&mut partial)?;
+        }
+    }
+    let f = OpenOptions::new().write(true).open(&target)?;
+    f.set_len(keep)?;
+    let mut f = BufWriter::new(f);
+    f.seek(SeekFrom::Start(keep))?;
+    let mut bw = BitsWriter::new(f);
+    for i in 0..rest_bits {
+        bw.bit(partial[0] >> i & 1 != 0);
+    }
+    Ok((bw, keep))
+}
+
+fn read_u32s(path: &Path) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    Ok(fs::read(path)?.chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+fn read_u16s(path: &Path) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+    Ok(fs::read(path)?.chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect())
+}
+
+pub fn align_writer(
+    writer: &mut BufWriter<File>,
+    pos: u64,
+    align: u64,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    if align == 0 {
+        return Ok(pos);
+    }
+    let rem = pos % align;
+    if rem == 0 {
+        return Ok(pos);
+    }
+    let pad = align - rem;
+    writer.write_all(&vec![0u8; pad as usize])?;
+    Ok(pos + pad)
+}
@@ -1,10 +1,11 @@
 // use fs_err::File;
 
 use crate::bits;
-use crate::util::as_slice_ref;
+use crate::util::{as_slice_ref, SplitMix64};
 
 use crate::lex::MapLex;
 use crate::corp::Attr;
+use crate::structure::{Struct, StructCursor};
 
 use std::fmt;
 use std::fmt::Write;
@@ -124,6 +125,118 @@ impl WMap {
             idx: 0, id: 0
         }
     }
+
+    /// Multiword sketch: the sketch of `head` restricted to the sentences
+    /// in which `head` occurs together with `coll` in relation `rel`,
+    /// walked like the one from `find_id`. Collocates keep the hits of
+    /// every other (relation, collocate) pair of `head` whose position
+    /// falls into one of those sentences; `rnk` is the logDice against
+    /// the base collocation. Returns `None` when the base collocation is
+    /// not in the sketch.
+    pub fn multiword(&self, head: u32, rel: u32, coll: u32,
+                     sentences: &dyn Struct) -> Option<MwsItem1> {
+        let h = self.find_id(head)?;
+        let baserel = h.iter().find(|r| r.id == rel)?;
+        let base = baserel.iter().find(|c| c.id == coll)?;
+
+        let mut ranges = Vec::<(u64, u64)>::new();
+        let mut cursor = StructCursor::new(sentences);
+        for (pos, _) in base.iter() {
+            if let Some(num) = cursor.num_at(pos as u64) {
+                let rng = (sentences.beg_at(num), sentences.end_at(num));
+                if ranges.last() != Some(&rng) {
+                    ranges.push(rng);
+                }
+            }
+        }
+        let basecnt = base.cnt;
+        let logdice = |cnt: u64, frq: u64| 14f32 + (2f32 * cnt as f32 / (basecnt + frq) as f32).log2();
+
+        let mut rels = Vec::new();
+        for r in h.iter() {
+            let mut colls = Vec::new();
+            for c in r.iter() {
+                if r.id == rel && c.id == coll { continue; }
+                let mut poss = Vec::new();
+                let mut rngno = 0;
+                for (pos, collpos) in c.iter() {
+                    while rngno < ranges.len() && ranges[rngno].1 <= pos as u64 {
+                        rngno += 1;
+                    }
+                    if rngno == ranges.len() { break; }
+                    if ranges[rngno].0 <= pos as u64 { poss.push((pos, collpos)); }
+                }
+                if poss.is_empty() { continue; }
+                let cnt = poss.len() as u64;
+                colls.push(MwsItem3 { id: c.id, cnt, frq: c.frq, rnk: logdice(cnt, c.frq),
+                                      lcm: c.lcm, poss });
+            }
+            if !colls.is_empty() {
+                let frq = colls.iter().map(|c| c.cnt).sum();
+                rels.push(MwsItem2 { id: r.id, cnt: colls.len() as u64, frq,
+                                     rnk: logdice(frq, r.frq), colls });
+            }
+        }
+        Some(MwsItem1 { id: head, cnt: rels.len() as u64, frq: basecnt, rels })
+    }
+}
+
+// Multiword sketch
+
+/// The head of a multiword sketch, the in-memory counterpart of
+/// `WMapItem1`; `frq` is the frequency of the base collocation.
+#[derive(Debug, Clone)]
+pub struct MwsItem1 {
+    pub id: u32,
+    pub cnt: u64,
+    pub frq: u64,
+    rels: Vec<MwsItem2>,
+}
+
+impl MwsItem1 {
+    pub fn iter(&self) -> std::slice::Iter<'_, MwsItem2> {
+        self.rels.iter()
+    }
+}
+
+/// A relation in a multiword sketch, the in-memory counterpart of
+/// `WMapItem2`; `frq` is the number of hits left in it.
+#[derive(Debug, Clone)]
+pub struct MwsItem2 {
+    pub id: u32,
+    pub cnt: u64,
+    pub frq: u64,
+    pub rnk: f32,
+    colls: Vec<MwsItem3>,
+}
+
+impl MwsItem2 {
+    pub fn iter(&self) -> std::slice::Iter<'_, MwsItem3> {
+        self.colls.iter()
+    }
+}
+
+/// A collocate in a multiword sketch, the in-memory counterpart of
+/// `WMapItem3` holding the hits left in it.
+#[derive(Debug, Clone)]
+pub struct MwsItem3 {
+    pub id: u32,
+    pub cnt: u64,
+    pub frq: u64,
+    pub rnk: f32,
+    pub lcm: Vec<i32>,
+    poss: Vec<(usize, Option<i32>)>,
+}
+
+impl MwsItem3 {
+    pub fn iter(&self) -> std::iter::Copied<std::slice::Iter<'_, (usize, Option<i32>)>> {
+        self.poss.iter().copied()
+    }
+
+    /// concordance of this collocation, see `CollConc`
+    pub fn conc(&self) -> CollConc<'_> {
+        CollConc::new(Box::new(self.iter()))
+    }
 }
 
 fn read_record(rb: &mut bits::Reader<'_>,
@@ -288,6 +401,117 @@ impl WMapItem3<'_> {
     pub fn iter(&self) -> WMapRevStream<'_> {
         self.wmap.rev.poss(self.idx, self.cnt as usize)
     }
+
+    /// concordance of this collocation, see `CollConc`
+    pub fn conc(&self) -> CollConc<'_> {
+        CollConc::new(Box::new(self.iter()))
+    }
+}
+
+// Concordance
+
+const DEFAULT_CONC_SEED: u64 = 0x5eed;
+
+/// One occurrence of a collocation. `coll` is the collocate position
+/// (head position plus the relative offset stored in the word sketch),
+/// `None` when the relation records no collocate position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollHit {
+    pub head: u64,
+    pub coll: Option<u64>,
+}
+
+impl CollHit {
+    /// the span covering both the head and the collocate, as a half-open
+    /// range suitable for concordance display
+    pub fn range(&self) -> (u64, u64) {
+        match self.coll {
+            Some(c) if c < self.head => (c, self.head + 1),
+            Some(c) => (self.head, c + 1),
+            None => (self.head, self.head + 1),
+        }
+    }
+}
+
+/// Builder for the "show examples" concordance of a collocation:
+///
+/// ```ignore
+/// let hits = coll.conc().within(subc.as_ref()).sample(20).hits();
+/// ```
+///
+/// Hits are returned in corpus order unless `shuffle` is requested.
+/// Sampling and shuffling are deterministic for a given seed.
+pub struct CollConc<'a> {
+    stream: Box<dyn ExactSizeIterator<Item = (usize, Option<i32>)> + 'a>,
+    within: Option<&'a dyn Struct>,
+    sample: Option<usize>,
+    shuffle: bool,
+    seed: u64,
+}
+
+impl<'a> CollConc<'a> {
+    fn new(stream: Box<dyn ExactSizeIterator<Item = (usize, Option<i32>)> + 'a>) -> Self {
+        CollConc { stream, within: None, sample: None, shuffle: false, seed: DEFAULT_CONC_SEED }
+    }
+
+    /// keep only hits whose head lies inside one of the ranges of `subc`
+    /// (a structure or any other sorted list of ranges)
+    pub fn within(mut self, subc: &'a dyn Struct) -> Self {
+        self.within = Some(subc);
+        self
+    }
+
+    /// keep at most `n` hits, chosen uniformly at random
+    pub fn sample(mut self, n: usize) -> Self {
+        self.sample = Some(n);
+        self
+    }
+
+    /// return hits in random order instead of corpus order
+    pub fn shuffle(mut self) -> Self {
+        self.shuffle = true;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn hits(self) -> Vec<CollHit> {
+        let mut hits = Vec::with_capacity(self.stream.len());
+        let mut rngno = 0u64;
+        let rnglen = self.within.map_or(0, |s| s.len() as u64);
+        for (pos, rel) in self.stream {
+            let head = pos as u64;
+            if let Some(subc) = self.within {
+                while rngno < rnglen && subc.end_at(rngno) <= head {
+                    rngno += 1;
+                }
+                if rngno == rnglen { break; }
+                if subc.beg_at(rngno) > head { continue; }
+            }
+            let coll = rel.and_then(|r| head.checked_add_signed(r as i64));
+            hits.push(CollHit { head, coll });
+        }
+
+        let mut rng = SplitMix64::new(self.seed);
+        if let Some(n) = self.sample && n < hits.len() {
+            // partial Fisher-Yates: the first n items become the sample
+            for i in 0..n {
+                let j = i + rng.below(hits.len() - i);
+                hits.swap(i, j);
+            }
+            hits.truncate(n);
+            if !self.shuffle {
+                hits.sort_unstable_by_key(|h| h.head);
+            }
+        }
+        if self.shuffle {
+            rng.shuffle(&mut hits);
+        }
+        hits
+    }
 }
 
 // Rev
@@ -0,0 +1,96 @@
+//! Appending vertical text to a compiled corpus.
+
+mod common;
+
+use corp::corp::Corpus;
+use corp::encode::{self, Encoder};
+
+const CONF: &str = "\
+ATTRIBUTE word
+ATTRIBUTE lemma {
+    TYPE \"MD_MGD\"
+}
+STRUCTURE doc {
+    ATTRIBUTE id
+}
+STRUCTURE g
+";
+
+const PART1: &str = "<doc id=\"a\">\nDogs\tdog\nbark\tbark\n<g/>\n.\t.\n</doc>\n";
+const PART2: &str = "<doc id=\"b\">\nA\ta\ndog\tdog\n</doc>\n<doc id=\"a\">\ncats\tcat\n</doc>\n";
+
+fn values(corp: &Corpus, attr: &str) -> Vec<String> {
+    let a = corp.open_attribute(attr).unwrap();
+    let n = a.text().size();
+    a.iter_ids(0).take(n).map(|id| a.id2str(id).to_string()).collect()
+}
+
+fn poss(corp: &Corpus, attr: &str, value: &str) -> Vec<u64> {
+    let a = corp.open_attribute(attr).unwrap();
+    a.revidx().id2poss(a.str2id(value).unwrap()).collect()
+}
+
+fn ranges(corp: &Corpus, name: &str) -> Vec<(u64, u64)> {
+    let s = corp.open_struct(name).unwrap();
+    (0..s.len() as u64).map(|n| (s.beg_at(n), s.end_at(n))).collect()
+}
+
+fn assert_complete(corp: &Corpus) {
+    assert_eq!(values(corp, "word"), ["Dogs", "bark", ".", "A", "dog", "cats"]);
+    assert_eq!(values(corp, "lemma"), ["dog", "bark", ".", "a", "dog", "cat"]);
+    assert_eq!(poss(corp, "lemma", "dog"), [0, 4]);
+    assert_eq!(poss(corp, "word", "cats"), [5]);
+    assert_eq!(ranges(corp, "doc"), [(0, 3), (3, 5), (5, 6)]);
+    assert_eq!(ranges(corp, "g"), [(2, 2)]);
+    assert_eq!(values(corp, "doc.id"), ["a", "b", "a"]);
+}
+
+#[test]
+fn append_extends_all_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = common::compile(dir.path(), CONF, &[PART1]);
+    let conf = common::open(&path).conf;
+    let frq = dir.path().join("data/word.frq");
+    std::fs::write(&frq, b"").unwrap();
+    let mut enc = Encoder::append(&conf, &dir.path().join("data")).unwrap();
+    enc.encode(PART2.as_bytes()).unwrap();
+    assert_eq!(enc.size(), 6);
+    enc.finish().unwrap();
+    assert!(!frq.exists());
+    assert!(!dir.path().join("data").join(encode::APPEND_COMMIT).exists());
+    assert_complete(&common::open(&path));
+
+    // the same as compiling both parts at once
+    let whole = tempfile::tempdir().unwrap();
+    let path = common::compile(whole.path(), CONF, &[&(PART1.to_string() + PART2)]);
+    assert_complete(&common::open(&path));
+}
+
+#[test]
+fn open_finishes_an_interrupted_commit() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = common::compile(dir.path(), CONF, &[PART1]);
+    let data = dir.path().join("data");
+    let stale = data.join("lemma.frq");
+    std::fs::write(&stale, b"").unwrap();
+
+    // stage the appended corpus as the encoder does and move only part of
+    // it into place, as if the commit had been cut short
+    let full = tempfile::tempdir().unwrap();
+    common::compile(full.path(), CONF, &[PART1, PART2]);
+    let staging = data.join(".append.tmp");
+    std::fs::rename(full.path().join("data"), &staging).unwrap();
+    std::fs::write(data.join(encode::APPEND_COMMIT), "lemma.frq\n").unwrap();
+    let mut files: Vec<_> = std::fs::read_dir(&staging).unwrap().map(|e| e.unwrap().file_name()).collect();
+    files.sort();
+    for f in files.iter().step_by(2) {
+        std::fs::rename(staging.join(f), data.join(f)).unwrap();
+    }
+
+    let corp = common::open(&path);
+    assert_complete(&corp);
+    assert!(!stale.exists());
+    assert!(!staging.exists());
+    assert!(!data.join(encode::APPEND_COMMIT).exists());
+    assert!(!encode::complete_append(&data).unwrap());
+}
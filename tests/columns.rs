@@ -0,0 +1,53 @@
+//! Attribute values computed from vertical columns with `COLUMN`.
+
+mod common;
+
+use corp::columns::ColumnMap;
+
+const ATTRS: &str = "\
+ATTRIBUTE word {
+    COLUMN word
+}
+ATTRIBUTE lempos {
+    COLUMN \"{lemma}-{tag:1}\"
+    DEFAULTVALUE \"?\"
+}
+ATTRIBUTE tag {
+    COLUMN 2
+}
+";
+
+fn fields(line: &str) -> Vec<String> {
+    line.split('\t').map(str::to_string).collect()
+}
+
+#[test]
+fn lemma_and_tag_prefix_template() {
+    let conf = corpconf::parse_conf_opt(ATTRS).unwrap();
+    let mut map = ColumnMap::from_conf(&conf).unwrap().unwrap();
+    assert_eq!(map.names(), ["lemma", "tag", "word"]);
+    assert!(map.needs_header());
+    assert!(map.apply(&fields("dogs\tdog\tNNS")).is_err());
+    assert!(!map.header(" word lemma"));
+    assert!(map.header(" word lemma tag"));
+    assert_eq!(map.width(), Some(3));
+    assert_eq!(map.apply(&fields("dogs\tdog\tNNS")).unwrap(), ["dogs", "dog-N", "NNS"]);
+    // the prefix counts characters, and a shorter tag is taken whole
+    assert_eq!(map.apply(&fields("žluté\tžlutý\tŽ")).unwrap(), ["žluté", "žlutý-Ž", "Ž"]);
+    assert_eq!(map.apply(&fields("x\ty\t")).unwrap(), ["x", "y-", ""]);
+    assert_eq!(map.apply(&fields("x\ty")).unwrap(), ["x", "?", "===NONE==="]);
+    // columns in another order in the next file
+    map.reset();
+    assert!(map.header("tag\tlemma\tword"));
+    assert_eq!(map.apply(&fields("VBZ\tbark\tbarks")).unwrap(), ["barks", "bark-V", "barks"]);
+}
+
+#[test]
+fn encoded_with_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = common::compile(dir.path(), ATTRS, &["# word lemma tag\ndogs\tdog\tNNS\nbark\tbark\tVBP\n"]);
+    let corp = common::open(&path);
+    let a = corp.open_attribute("lempos").unwrap();
+    let got: Vec<&str> = a.iter_ids(0).take(2).map(|id| a.id2str(id)).collect();
+    assert_eq!(got, ["dog-N", "bark-V"]);
+}
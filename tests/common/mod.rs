@@ -0,0 +1,38 @@
+//! Corpora compiled into temporary directories, for the tests of the
+//! encoder and of the tools working on the corpus files.
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use corp::corp::Corpus;
+use corp::encode::Encoder;
+
+/// Writes `corpus.conf` into `dir`, with the data in `dir/data` and the
+/// rest of the config `conf`; returns its path and the parsed config.
+pub fn write_conf(dir: &Path, conf: &str) -> (PathBuf, corpconf::Block) {
+    let text = format!("PATH \"{}/\"\n{}", dir.join("data").display(), conf);
+    let path = dir.join("corpus.conf");
+    std::fs::write(&path, &text).unwrap();
+    (path, corpconf::parse_conf_opt(&text).unwrap())
+}
+
+/// Compiles a corpus configured by `conf` in `dir` from the vertical
+/// `parts`, the first one encoded into a new corpus and the rest
+/// appended, with the revs built; returns the path of the config.
+pub fn compile(dir: &Path, conf: &str, parts: &[&str]) -> PathBuf {
+    let (path, conf) = write_conf(dir, conf);
+    let data = dir.join("data");
+    for (n, part) in parts.iter().enumerate() {
+        let mut enc = if n == 0 { Encoder::new(&conf, &data) } else { Encoder::append(&conf, &data) }
+            .unwrap();
+        enc.set_build_revs(true);
+        enc.encode(part.as_bytes()).unwrap();
+        enc.finish().unwrap();
+    }
+    path
+}
+
+pub fn open(path: &Path) -> Corpus {
+    Corpus::open(path.to_str().unwrap()).unwrap()
+}
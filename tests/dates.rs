@@ -0,0 +1,76 @@
+//! Date conditions over the date index of structure attributes.
+
+use chrono::NaiveDate;
+
+use corp::corp::Corpus;
+use corp::dates::{DateParser, Period};
+use corp::mem::MemBuilder;
+
+const DATES: [&str; 9] = ["2004-01-01", "2004-12-31", "2005-06-30", "2005-07-01", "2003-12-31",
+                          "2005-06-15T10:00", "2004-05", "n/a", "17.3.2005"];
+
+fn corpus() -> Corpus {
+    let mut b = MemBuilder::new(&["word"]);
+    b.option("doc.date", "DATEFORMAT", "%d.%m.%Y");
+    for date in DATES {
+        b.open("doc", &[("date", date)]);
+        b.token(&["w"]);
+        b.close("doc").unwrap();
+    }
+    b.build().unwrap()
+}
+
+fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+}
+
+#[test]
+fn spans_of_values() {
+    let parser = DateParser { formats: vec!["%d.%m.%Y".to_string()] };
+    assert_eq!(parser.parse_span("2004"), Some((ymd(2004, 1, 1), ymd(2004, 12, 31))));
+    assert_eq!(parser.parse_span("2004-02"), Some((ymd(2004, 2, 1), ymd(2004, 2, 29))));
+    assert_eq!(parser.parse_span("2005-12 "), Some((ymd(2005, 12, 1), ymd(2005, 12, 31))));
+    assert_eq!(parser.parse_span("2005-06-15T10:00"), Some((ymd(2005, 6, 15), ymd(2005, 6, 15))));
+    assert_eq!(parser.parse_span("1.2.2005"), Some((ymd(2005, 2, 1), ymd(2005, 2, 1))));
+    assert_eq!(parser.parse("2004-05"), Some(ymd(2004, 5, 1)));
+    for bad in ["2004-13", "2004-5", "04", "2004-02-30"] {
+        assert_eq!(parser.parse_span(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn lower_precision_conditions_cover_whole_periods() {
+    let corp = corpus();
+    let index = corp.open_dates("doc.date").unwrap();
+    for (cond, want) in [
+        ("date=2004", &[0, 1, 6][..]),
+        ("date=2005-06", &[2, 5]),
+        ("date=2005-06-30", &[2]),
+        ("date=2004..2005-06", &[0, 1, 2, 5, 6, 8]),
+        ("date=2004-05..2004-05", &[6]),
+        ("date<2004", &[4]),
+        ("date<=2004", &[0, 1, 4, 6]),
+        ("date>2004", &[2, 3, 5, 8]),
+        ("date>=2005-06", &[2, 3, 5]),
+        ("date>2005-06", &[3]),
+        ("date<2005-03-17", &[0, 1, 4, 6]),
+        ("date=17.3.2005", &[8]),
+    ] {
+        assert_eq!(index.select(cond).unwrap(), want, "{}", cond);
+    }
+    assert!(index.select("date=2004-13").is_err());
+    assert_eq!(index.between(ymd(2004, 5, 1), ymd(2004, 12, 31)), [1, 6]);
+}
+
+#[test]
+fn periods() {
+    let d = ymd(2005, 6, 15);
+    assert_eq!(Period::Week.start(d), ymd(2005, 6, 13));
+    assert_eq!(Period::Week.next(ymd(2005, 6, 13)), ymd(2005, 6, 20));
+    assert_eq!(Period::Quarter.start(d), ymd(2005, 4, 1));
+    assert_eq!(Period::Decade.next(Period::Decade.start(d)), ymd(2010, 1, 1));
+    // the last periods end with the calendar
+    for period in [Period::Day, Period::Week, Period::Month, Period::Year] {
+        assert_eq!(period.next(period.start(NaiveDate::MAX)), NaiveDate::MAX, "{:?}", period);
+    }
+}
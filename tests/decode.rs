@@ -0,0 +1,78 @@
+//! Export of encoded corpora back to vertical text.
+
+mod common;
+
+use corp::corp::Corpus;
+use corp::decode;
+
+const CONF: &str = "\
+ATTRIBUTE word
+ATTRIBUTE lemma
+STRUCTURE doc {
+    ATTRIBUTE id
+}
+STRUCTURE p
+STRUCTURE g
+";
+
+fn decoded(corp: &Corpus, ranges: &[(u64, u64)]) -> String {
+    let mut out = Vec::new();
+    decode::decode_vert(corp, ranges, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn empty_structures_nest_in_opening_tags() {
+    let vert = "\
+<doc id=\"a\">
+<p/>
+one\t1
+<g/>
+two\t2
+</doc>
+<doc id=\"b\">
+<p>
+<g/>
+three\t3
+</p>
+<p/>
+</doc>
+";
+    let dir = tempfile::tempdir().unwrap();
+    let corp = common::open(&common::compile(dir.path(), CONF, &[vert]));
+    assert_eq!(decoded(&corp, &[(0, 3)]), vert);
+    // the range starting at doc b opens it before its empty tags
+    assert!(decoded(&corp, &[(2, 3)]).starts_with("<doc id=\"b\">\n<p>\n<g/>\n"));
+}
+
+#[test]
+fn struct_nums_by_value() {
+    let vert = "<doc id=\"a\">\nx\t1\n</doc>\n<doc id=\"b\">\ny\t2\n</doc>\n<doc id=\"a\">\nz\t3\n</doc>\n";
+    let dir = tempfile::tempdir().unwrap();
+    let corp = common::open(&common::compile(dir.path(), CONF, &[vert]));
+    assert_eq!(decode::struct_nums_by_value(&corp, "doc", "id", &["a", "c"]).unwrap(), [0, 2]);
+    assert_eq!(decode::struct_nums_by_value(&corp, "doc", "id", &["b", "a"]).unwrap(), [0, 1, 2]);
+}
+
+#[test]
+fn selections_of_decodevert() {
+    let conf = "ATTRIBUTE word\nSTRUCTURE doc {\n    ATTRIBUTE id\n    ATTRIBUTE year {\n        \
+                NUMERIC yes\n    }\n}\n";
+    let vert: String = [("a", 1990), ("b", 2000), ("c", 2010), ("d", 2020)].iter().enumerate()
+        .map(|(n, (id, year))| format!("<doc id=\"{}\" year=\"{}\">\nw{}\n</doc>\n", id, year, n))
+        .collect();
+    let dir = tempfile::tempdir().unwrap();
+    let path = common::compile(dir.path(), conf, &[&vert]);
+    let run = |args: &[&str]| {
+        let out = std::process::Command::new(env!("CARGO_BIN_EXE_decodevert"))
+            .arg(&path).args(args).arg("--text")
+            .output().unwrap();
+        assert!(out.status.success(), "{:?}", args);
+        String::from_utf8(out.stdout).unwrap()
+    };
+    // --nums and --ids add up, every --where filters them
+    assert_eq!(run(&["--nums", "1", "--ids", "c,d"]), "w1\nw2\nw3\n");
+    assert_eq!(run(&["--nums", "1", "--ids", "c,d", "--where", "year>=2000", "--where", "year<2020"]),
+               "w1\nw2\n");
+    assert_eq!(run(&["--where", "year<2020", "--where", "year>1990"]), "w1\nw2\n");
+}
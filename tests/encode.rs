@@ -0,0 +1,127 @@
+//! Compilation of vertical text with the encoder.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use corp::encode::Encoder;
+
+const CONF: &str = "\
+ATTRIBUTE word
+ATTRIBUTE lemma {
+    TYPE \"MD_MGD\"
+}
+ATTRIBUTE tag {
+    TYPE \"MD_MI\"
+    MULTIVALUE yes
+}
+STRUCTURE doc {
+    ATTRIBUTE id
+}
+STRUCTURE s
+";
+
+fn encode(dir: &Path, conf: &str, vert: &str, parallel: bool) {
+    let data = dir.join("data");
+    let conf = corpconf::parse_conf_opt(&format!("PATH \"{}/\"\n{}", data.display(), conf)).unwrap();
+    let mut enc = Encoder::new(&conf, &data).unwrap();
+    enc.set_parallel(parallel);
+    enc.set_build_revs(true);
+    enc.encode(vert.as_bytes()).unwrap();
+    enc.finish().unwrap();
+}
+
+fn files(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    std::fs::read_dir(dir.join("data")).unwrap()
+        .map(|e| e.unwrap())
+        .map(|e| (e.file_name().into_string().unwrap(), std::fs::read(e.path()).unwrap()))
+        .collect()
+}
+
+#[test]
+fn parallel_output_is_identical() {
+    // several batches of tokens with a lexicon that keeps growing
+    let mut vert = String::new();
+    for d in 0..7 {
+        vert += &format!("<doc id=\"d{}\">\n<s>\n", d);
+        for i in 0..1500u32 {
+            let n = i * 7919 % (300 + d * 500);
+            vert += &format!("w{}\tl{}\tT{},X{}\n", n, n / 3, n % 5, n % 2);
+            if i % 17 == 16 {
+                vert += "</s>\n<s>\n";
+            }
+        }
+        vert += "</s>\n</doc>\n";
+    }
+    let (serial, parallel) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    encode(serial.path(), CONF, &vert, false);
+    encode(parallel.path(), CONF, &vert, true);
+    let (want, got) = (files(serial.path()), files(parallel.path()));
+    assert_eq!(got.keys().collect::<Vec<_>>(), want.keys().collect::<Vec<_>>());
+    assert!(want.contains_key("tag.rev"));
+    for (name, data) in &want {
+        assert!(&got[name] == data, "{} differs", name);
+    }
+}
+
+#[test]
+fn lexicons_do_not_depend_on_the_memory_limit() {
+    let mut vert = String::from("<doc id=\"d\">\n<s>\n");
+    for i in 0..3000u32 {
+        let n = i * 7919 % 2500;
+        vert += &format!("w{}\tl{}\tT{},X{}\n", n, n / 3, n % 5, n % 2);
+    }
+    vert += "</s>\n</doc>\n";
+    let (default, tiny) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    encode(default.path(), CONF, &vert, false);
+    // every new value spills the map to a run on disk
+    encode(tiny.path(), &format!("LEXMEMLIMIT 1\n{}", CONF), &vert, false);
+    let (want, got) = (files(default.path()), files(tiny.path()));
+    assert_eq!(got.keys().collect::<Vec<_>>(), want.keys().collect::<Vec<_>>());
+    for name in want.keys().filter(|n| n.contains(".lex")) {
+        assert!(got[name] == want[name], "{} differs", name);
+    }
+}
+
+#[test]
+fn multivalue_structure_attribute_revs() {
+    let conf = format!("{}STRUCTURE p {{\n    ATTRIBUTE topic {{\n        MULTIVALUE yes\n        \
+                        MULTISEP \";\"\n    }}\n}}\n", CONF);
+    let vert = "<doc id=\"d\">\n<p topic=\"x;y\">\na\ta\tT\n</p>\n<p topic=\"y,z\">\nb\tb\tT\n</p>\n</doc>\n";
+    let dir = tempfile::tempdir().unwrap();
+    let path = common::compile(dir.path(), &conf, &[vert]);
+    let corp = common::open(&path);
+    let topic = corp.open_attribute("p.topic").unwrap();
+    for (value, nums) in [("x;y", &[0][..]), ("x", &[0]), ("y", &[0]), ("y,z", &[1])] {
+        let id = topic.str2id(value).unwrap();
+        assert_eq!(topic.revidx().id2poss(id).collect::<Vec<_>>(), nums, "{}", value);
+    }
+    assert!(topic.str2id("z").is_none());
+    // rebuilt by mkrev from the config
+    let rev = dir.path().join("data/p.topic.rev");
+    let built = std::fs::read(&rev).unwrap();
+    std::fs::remove_file(&rev).unwrap();
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_mkrev"))
+        .args([path.to_str().unwrap(), "p.topic"])
+        .status().unwrap();
+    assert!(status.success());
+    assert!(std::fs::read(&rev).unwrap() == built);
+}
+
+#[test]
+fn numeric_and_date_indexes() {
+    let conf = "ATTRIBUTE word\nSTRUCTURE doc {\n    ATTRIBUTE year {\n        NUMERIC yes\n    }\n    \
+                ATTRIBUTE date {\n        DATE yes\n    }\n}\n";
+    let docs = [("2000", "2004-05-17"), ("n/a", "2004"), ("1990", "2003-12-31"), ("2005.5", "n/a")];
+    let vert: String = docs.iter().map(|(y, d)| format!("<doc year=\"{}\" date=\"{}\">\nw\n</doc>\n", y, d))
+        .collect();
+    let dir = tempfile::tempdir().unwrap();
+    let corp = common::open(&common::compile(dir.path(), conf, &[&vert]));
+    assert!(dir.path().join("data/doc.year.nidx").exists());
+    assert!(dir.path().join("data/doc.date.didx").exists());
+    let years = corp.open_numeric("doc.year").unwrap();
+    assert_eq!(years.len(), 3);
+    assert_eq!(years.ge(2000.0), [0, 3]);
+    assert_eq!(corp.open_dates("doc.date").unwrap().select("date=2004").unwrap(), [0, 1]);
+}
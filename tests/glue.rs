@@ -0,0 +1,47 @@
+//! Glue tags and the running text.
+
+mod common;
+
+use std::path::Path;
+
+use corp::corp::Corpus;
+
+const VERT: &str = "<doc>\nHello\n<g/>\n,\nworld\n<g/>\n!\n</doc>\n";
+
+fn compile(dir: &Path, conf: &str, vert: &str) -> Corpus {
+    common::open(&common::compile(dir, &format!("ATTRIBUTE word\nSTRUCTURE doc\n{}", conf), &[vert]))
+}
+
+#[test]
+fn declared_glue_structure() {
+    let dir = tempfile::tempdir().unwrap();
+    let corp = compile(dir.path(), "STRUCTURE g\n", VERT);
+    assert_eq!(corp.glue_positions(0, 4).unwrap(), [1, 3]);
+    assert_eq!(corp.running_text(0, 4).unwrap(), "Hello, world!");
+    assert_eq!(corp.running_text(1, 3).unwrap(), ", world");
+}
+
+#[test]
+fn custom_glue_structure() {
+    let dir = tempfile::tempdir().unwrap();
+    let vert = VERT.replace("<g/>", "<join/>").replace("<doc>\n", "<doc>\n<g>\n</g>\n");
+    let corp = compile(dir.path(), "GLUESTRUCT join\nSTRUCTURE join\n", &vert);
+    // <g> is not a declared structure, so it is read as a token
+    assert_eq!(corp.running_text(0, 6).unwrap(), "<g> </g> Hello, world!");
+    assert!(!dir.path().join("data/g.rng").exists());
+}
+
+#[test]
+fn undeclared_glue_structure() {
+    let dir = tempfile::tempdir().unwrap();
+    let corp = compile(dir.path(), "", VERT);
+    assert_eq!(corp.running_text(0, 6).unwrap(), "Hello <g/> , world <g/> !");
+    assert!(!dir.path().join("data/g.rng").exists());
+
+    // declared on the fly like any other structure
+    let dir = tempfile::tempdir().unwrap();
+    let corp = compile(dir.path(), "UNKNOWNSTRUCT declare\n", VERT);
+    assert_eq!(corp.running_text(0, 4).unwrap(), "Hello , world !");
+    assert_eq!(std::fs::read_to_string(dir.path().join("data/autostructs.conf")).unwrap(),
+               "STRUCTURE g\n");
+}
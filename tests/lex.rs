@@ -0,0 +1,87 @@
+//! Lexicon lookups on lexicons written by `LexWriter`.
+
+use std::path::Path;
+
+use corp::collate::{self, Collator, Strength};
+use corp::lex::MapLex;
+use corp::wrlex::{self, LexWriter};
+
+fn write_lex(base: &Path, values: &[&str]) -> MapLex {
+    let mut w = LexWriter::new(base).unwrap();
+    for v in values {
+        w.id_for(v).unwrap();
+    }
+    w.finalize().unwrap();
+    MapLex::open(base.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn str2id_outside_and_between_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let lex = write_lex(&dir.path().join("word"), &["d", "b", "f"]);
+    assert_eq!(lex.str2id("d"), Some(0));
+    assert_eq!(lex.str2id("b"), Some(1));
+    assert_eq!(lex.str2id("f"), Some(2));
+    // before the first entry, between entries and after the last one
+    for s in ["", "a", "c", "e", "g"] {
+        assert_eq!(lex.str2id(s), None, "{:?}", s);
+    }
+}
+
+#[test]
+fn str2id_single_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let lex = write_lex(&dir.path().join("word"), &["m"]);
+    assert_eq!(lex.str2id("m"), Some(0));
+    assert_eq!(lex.str2id("a"), None);
+    assert_eq!(lex.str2id("z"), None);
+}
+
+fn collated(base: &Path, locale: &str) -> Vec<String> {
+    collate::write_lsrt(base, &Collator::new(locale)).unwrap();
+    let lex = MapLex::open(base.to_str().unwrap()).unwrap();
+    lex.iter_collated().unwrap().map(|id| lex.id2str(id).to_string()).collect()
+}
+
+#[test]
+fn czech_ch_ordering() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("word");
+    write_lex(&base, &["ibis", "Chata", "hrad", "chata", "cena", "čaj", "dům", "CHATA", "cihla"]);
+    // "ch" is a letter between "h" and "i", "č" one after "c"
+    assert_eq!(collated(&base, "cs_CZ.UTF-8"),
+               ["cena", "cihla", "čaj", "dům", "hrad", "chata", "Chata", "CHATA", "ibis"]);
+    assert_eq!(collated(&base, "en_US"),
+               ["čaj", "cena", "chata", "Chata", "CHATA", "cihla", "dům", "hrad", "ibis"]);
+
+    let coll = Collator::new("cs");
+    let lex = MapLex::open(base.to_str().unwrap()).unwrap();
+    let mut ids = lex.find_collated(&coll, "chata", Strength::Primary);
+    ids.sort_unstable();
+    assert_eq!(ids, [1, 3, 7]);
+    assert_eq!(lex.find_collated(&coll, "Chata", Strength::Tertiary), [1]);
+    assert_eq!(coll.compare("chata", "cihla"), std::cmp::Ordering::Greater);
+    assert_eq!(coll.compare("Ch", "ch"), std::cmp::Ordering::Greater);
+}
+
+#[test]
+fn str2ids_folded() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("word");
+    let values = ["Cafe", "cafés", "café", "CAFÉ", "cafe", "caf", "Žluť", "zlut"];
+    let scan = write_lex(&base, &values);
+    let check = |lex: &MapLex| {
+        let mut ids = lex.str2ids_folded("CAFE");
+        ids.sort_unstable();
+        assert_eq!(ids, [0, 2, 3, 4]);
+        let mut ids = lex.str2ids_folded("žlut");
+        ids.sort_unstable();
+        assert_eq!(ids, [6, 7]);
+        assert_eq!(lex.str2ids_folded("cafeteria"), Vec::<u32>::new());
+        assert_eq!(lex.str2ids("CAFÉ", "c"), [2, 3]);
+        assert_eq!(lex.str2ids("cafe", "d"), [2, 4]);
+    };
+    check(&scan);
+    wrlex::write_folded(&base).unwrap();
+    check(&MapLex::open(base.to_str().unwrap()).unwrap());
+}
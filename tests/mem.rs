@@ -0,0 +1,157 @@
+//! In-memory corpora built from tokens and from vertical text.
+
+use corp::corp::Corpus;
+use corp::mem::MemBuilder;
+use proptest::prelude::*;
+
+const VERT: &str = "\
+<doc id=\"d1\" year=\"2001\">
+<s>
+Dogs\tdog
+bark\tbark
+<g/>
+.\t.
+</s>
+</doc>
+<doc id=\"d2\">
+<s>
+A\ta
+dog\tdog
+</s>
+<s/>
+</doc>
+";
+
+fn from_vert() -> Corpus {
+    let mut b = MemBuilder::new(&["word", "lemma"]);
+    b.read_vert(VERT.as_bytes()).unwrap();
+    b.build().unwrap()
+}
+
+fn values(corp: &Corpus, attr: &str) -> Vec<String> {
+    let a = corp.open_attribute(attr).unwrap();
+    let n = a.text().size() as u64;
+    a.iter_ids(0).take(n as usize).map(|id| a.id2str(id).to_string()).collect()
+}
+
+#[test]
+fn attributes_from_vert() {
+    let corp = from_vert();
+    assert_eq!(values(&corp, "word"), ["Dogs", "bark", ".", "A", "dog"]);
+    let lemma = corp.open_attribute("lemma").unwrap();
+    let dog = lemma.str2id("dog").unwrap();
+    assert_eq!(lemma.revidx().id2poss(dog).collect::<Vec<_>>(), [0, 4]);
+    assert_eq!(lemma.revidx().count(dog), 2);
+    assert_eq!(lemma.get_freq("frq").unwrap().frq(dog), 2);
+    assert_eq!(lemma.text().get(3), lemma.str2id("a").unwrap());
+    assert_eq!(lemma.str2id("cat"), None);
+    let word = corp.open_attribute("word").unwrap();
+    assert_eq!(word.str2ids("DOG", "c"), [word.str2id("dog").unwrap()]);
+    assert!(corp.open_attribute("tag").is_err());
+}
+
+#[test]
+fn structures_from_vert() {
+    let corp = from_vert();
+    let s = corp.open_struct("s").unwrap();
+    let ranges: Vec<(u64, u64)> = (0..s.len() as u64).map(|n| (s.beg_at(n), s.end_at(n))).collect();
+    assert_eq!(ranges, [(0, 3), (3, 5), (5, 5)]);
+    assert_eq!(s.num_at_pos(4), Some(1));
+    assert_eq!(values(&corp, "doc.id"), ["d1", "d2"]);
+    assert_eq!(values(&corp, "doc.year"), ["2001", "===NONE==="]);
+    let year = corp.open_attribute("doc.year").unwrap();
+    let none = year.str2id("===NONE===").unwrap();
+    assert_eq!(year.revidx().id2poss(none).collect::<Vec<_>>(), [1]);
+    assert_eq!(corp.running_text(0, 5).unwrap(), "Dogs bark. A dog");
+    assert!(corp.open_numeric("doc.year").is_err());
+}
+
+#[test]
+fn builder_spans_and_tags() {
+    let mut b = MemBuilder::new(&["word", "tag"]);
+    b.open("p", &[]);
+    b.token(&["one"]);
+    let pos = b.token(&["two", "N", "extra"]);
+    b.span("ne", 0, 2, &[("type", "num")]);
+    assert!(b.close("s").is_err());
+    b.close("p").unwrap();
+    assert_eq!(pos, 1);
+    b.open("p", &[]);
+    assert!(MemBuilder::new(&["word"]).close("p").is_err());
+    let mut unclosed = MemBuilder::new(&["word"]);
+    unclosed.open("p", &[]);
+    assert!(unclosed.build().is_err());
+    b.close("p").unwrap();
+    let corp = b.build().unwrap();
+    assert_eq!(values(&corp, "tag"), ["===NONE===", "N"]);
+    assert_eq!(values(&corp, "ne.type"), ["num"]);
+    assert_eq!(corp.open_struct("p").unwrap().len(), 2);
+    assert!(corp.conf.structure("ne").unwrap().attribute("type").is_some());
+}
+
+#[test]
+fn spans_out_of_order() {
+    let mut b = MemBuilder::new(&["word"]);
+    for w in ["a", "b", "c", "d"] {
+        b.token(&[w]);
+    }
+    b.span("s", 2, 4, &[("id", "second")]);
+    b.span("s", 0, 2, &[("id", "first"), ("n", "1")]);
+    b.span("s", 2, 2, &[("id", "empty")]);
+    let corp = b.build().unwrap();
+    let s = corp.open_struct("s").unwrap();
+    let ranges: Vec<(u64, u64)> = (0..s.len() as u64).map(|n| (s.beg_at(n), s.end_at(n))).collect();
+    assert_eq!(ranges, [(0, 2), (2, 2), (2, 4)]);
+    assert_eq!(s.num_at_pos(3), Some(2));
+    assert_eq!(values(&corp, "s.id"), ["first", "empty", "second"]);
+    assert_eq!(values(&corp, "s.n"), ["1", "===NONE===", "===NONE==="]);
+    let id = corp.open_attribute("s.id").unwrap();
+    assert_eq!(id.revidx().id2poss(id.str2id("second").unwrap()).collect::<Vec<_>>(), [2]);
+
+    // an empty span added before one ending at the same position
+    let mut b = MemBuilder::new(&["word"]);
+    for w in ["a", "b", "c", "d"] {
+        b.token(&[w]);
+    }
+    b.span("s", 2, 2, &[("id", "empty")]);
+    b.span("s", 0, 2, &[("id", "first")]);
+    let corp = b.build().unwrap();
+    let s = corp.open_struct("s").unwrap();
+    let ranges: Vec<(u64, u64)> = (0..s.len() as u64).map(|n| (s.beg_at(n), s.end_at(n))).collect();
+    assert_eq!(ranges, [(0, 2), (2, 2)]);
+    assert_eq!(s.num_at_pos(1), Some(0));
+    assert_eq!(values(&corp, "s.id"), ["first", "empty"]);
+
+    for spans in [&[(3, 2)][..], &[(2, 5)], &[(0, 3), (2, 4)], &[(0, 4), (1, 2)], &[(0, 4), (2, 2)]] {
+        let mut b = MemBuilder::new(&["word"]);
+        for w in ["a", "b", "c", "d"] {
+            b.token(&[w]);
+        }
+        for &(beg, end) in spans {
+            b.span("s", beg, end, &[]);
+        }
+        assert!(b.build().is_err(), "{:?}", spans);
+    }
+}
+
+proptest! {
+    #[test]
+    fn texts_and_revs_round_trip(ids in prop::collection::vec(0u32..300, 0..600), from in 0usize..700) {
+        let mut b = MemBuilder::new(&["word"]);
+        for id in &ids {
+            b.token(&[id.to_string()]);
+        }
+        let corp = b.build().unwrap();
+        let a = corp.open_attribute("word").unwrap();
+        let from = from.min(ids.len());
+        let got: Vec<&str> = a.iter_ids(from as u64).map(|id| a.id2str(id)).collect();
+        let want: Vec<String> = ids[from..].iter().map(|id| id.to_string()).collect();
+        prop_assert_eq!(got, want);
+        for id in 0..a.id_range() {
+            let value: u32 = a.id2str(id).parse().unwrap();
+            let poss: Vec<u64> = (0..ids.len() as u64).filter(|&p| ids[p as usize] == value).collect();
+            prop_assert_eq!(a.revidx().id2poss(id).collect::<Vec<_>>(), poss.clone());
+            prop_assert_eq!(a.revidx().count(id), poss.len() as u64);
+        }
+    }
+}
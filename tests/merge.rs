@@ -0,0 +1,55 @@
+//! Merging compiled corpora.
+
+mod common;
+
+use corp::merge;
+
+use common::{compile, open};
+
+const CONF: &str = "\
+ATTRIBUTE word
+ATTRIBUTE lemma {
+    TYPE \"MD_MGD\"
+}
+STRUCTURE doc {
+    ATTRIBUTE id
+}
+";
+
+// the lexicons overlap in both directions: "dog" and "the" are in both
+// inputs, but with different ids
+const PART1: &str = "<doc id=\"a\">\nthe\tthe\ndog\tdog\nbarks\tbark\n</doc>\n";
+const PART2: &str = "<doc id=\"b\">\ncats\tcat\nsee\tsee\nthe\tthe\ndog\tdog\n</doc>\n<doc id=\"a\">\nbarks\tbark\n</doc>\n";
+
+#[test]
+fn merge_equals_compiling_the_concatenation() {
+    let (one, two, whole, merged) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap(),
+                                     tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let inputs = [open(&compile(one.path(), CONF, &[PART1])), open(&compile(two.path(), CONF, &[PART2]))];
+    compile(whole.path(), CONF, &[&(PART1.to_string() + PART2)]);
+    // every new value spills the lexicon writers to disk
+    let (path, conf) = common::write_conf(merged.path(), &format!("LEXMEMLIMIT 1\n{}", CONF));
+    merge::merge_corpora(&inputs, &conf, &merged.path().join("data")).unwrap();
+
+    let mut files: Vec<_> = std::fs::read_dir(merged.path().join("data")).unwrap()
+        .map(|e| e.unwrap().file_name()).collect();
+    files.sort();
+    for f in ["word.lex", "lemma.rev", "doc.rng", "doc.id.text"] {
+        assert!(files.iter().any(|n| n == f), "{} not written", f);
+    }
+    for f in &files {
+        let got = std::fs::read(merged.path().join("data").join(f)).unwrap();
+        let want = std::fs::read(whole.path().join("data").join(f)).unwrap();
+        assert!(got == want, "{:?} differs", f);
+    }
+
+    let corp = open(&path);
+    let lemma = corp.open_attribute("lemma").unwrap();
+    let the = lemma.str2id("the").unwrap();
+    assert_eq!(lemma.revidx().id2poss(the).collect::<Vec<_>>(), [0, 5]);
+    assert_eq!(lemma.id_range(), 5);
+
+    let conf = corpconf::parse_conf_opt(&format!("LEXMEMLIMIT x\n{}", CONF)).unwrap();
+    let err = merge::merge_corpora(&inputs, &conf, &merged.path().join("data")).unwrap_err();
+    assert!(err.to_string().contains("LEXMEMLIMIT"), "{}", err);
+}
@@ -0,0 +1,48 @@
+//! Normalization of attribute values to NFC and NFKC.
+
+mod common;
+
+use corp::normalize::{is_normalized, normalize, NormForm};
+
+#[test]
+fn non_latin_decomposed_input() {
+    for (nfd, nfc) in [
+        // Cyrillic short i
+        ("и\u{306}", "й"),
+        ("Сергеи\u{306}", "Сергей"),
+        // Greek tonos, and a breathing with an accent
+        ("α\u{301}", "ά"),
+        ("ε\u{313}\u{301}", "ἔ"),
+        // Vietnamese stacked marks, also given in the wrong order
+        ("e\u{323}\u{302}", "ệ"),
+        ("e\u{302}\u{323}", "ệ"),
+        ("Vie\u{323}\u{302}t", "Việt"),
+        // Hangul jamo
+        ("\u{1112}\u{1161}\u{11AB}", "한"),
+    ] {
+        assert_eq!(normalize(nfd, NormForm::Nfc), nfc, "{:?}", nfd);
+        assert!(!is_normalized(nfd, NormForm::Nfc), "{:?}", nfd);
+        assert!(is_normalized(nfc, NormForm::Nfc), "{:?}", nfc);
+        assert_eq!(normalize(nfd, NormForm::Nfkc), nfc, "{:?}", nfd);
+    }
+}
+
+#[test]
+fn compatibility_forms() {
+    for (s, nfkc) in [("\u{FB01}le", "file"), ("①", "1"), ("Ｒｕｓｔ", "Rust"), ("x²", "x2"),
+                      ("ｶ", "カ")] {
+        assert_eq!(normalize(s, NormForm::Nfkc), nfkc);
+        assert_eq!(normalize(s, NormForm::Nfc), s);
+        assert!(is_normalized(s, NormForm::Nfc) && !is_normalized(s, NormForm::Nfkc));
+    }
+}
+
+#[test]
+fn encoder_merges_spellings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = common::compile(dir.path(), "ATTRIBUTE word {\n    NORMALIZE NFC\n}\nATTRIBUTE raw\n",
+                               &["й\tй\nи\u{306}\tи\u{306}\n"]);
+    let corp = common::open(&path);
+    assert_eq!(corp.open_attribute("word").unwrap().id_range(), 1);
+    assert_eq!(corp.open_attribute("raw").unwrap().id_range(), 2);
+}
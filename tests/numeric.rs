@@ -0,0 +1,58 @@
+//! Range queries over the numeric index of structure attributes.
+
+use corp::corp::Corpus;
+use corp::mem::MemBuilder;
+use corp::numeric;
+
+const YEARS: [&str; 8] = ["2000", "1990", "n/a", "2005", " 1995 ", "2000", "-3.5", "2005.5"];
+
+fn corpus() -> Corpus {
+    let mut b = MemBuilder::new(&["word"]);
+    b.option("doc.year", "NUMERIC", "yes");
+    for year in YEARS {
+        b.open("doc", &[("year", year)]);
+        b.token(&["w"]);
+        b.close("doc").unwrap();
+    }
+    b.build().unwrap()
+}
+
+fn select(corp: &Corpus, cond: &str) -> Vec<u64> {
+    let (name, range) = numeric::parse_condition(cond).unwrap();
+    assert_eq!(name, "year");
+    corp.open_numeric(&format!("doc.{}", name)).unwrap().range(range)
+}
+
+#[test]
+fn each_comparison() {
+    let corp = corpus();
+    for (cond, want) in [
+        ("year<2000", &[1, 4, 6][..]),
+        ("year<=2000", &[0, 1, 4, 5, 6]),
+        ("year>2000", &[3, 7]),
+        ("year>=2000", &[0, 3, 5, 7]),
+        ("year=2000", &[0, 5]),
+        ("year==1995", &[4]),
+        ("year = 2005", &[3]),
+        ("year=1990..2005", &[0, 1, 3, 4, 5]),
+        ("year=1995..1995", &[4]),
+        ("year=-10..0", &[6]),
+        ("year=2005..1990", &[]),
+        ("year>2005.5", &[]),
+    ] {
+        assert_eq!(select(&corp, cond), want, "{}", cond);
+    }
+    let index = corp.open_numeric("doc.year").unwrap();
+    assert_eq!(index.len(), 7);
+    assert_eq!(index.lt(1995.0), [1, 6]);
+    assert_eq!(index.le(1995.0), [1, 4, 6]);
+    assert_eq!(index.gt(2005.0), [7]);
+    assert_eq!(index.ge(2005.0), [3, 7]);
+}
+
+#[test]
+fn bad_conditions() {
+    for cond in ["year", "<2000", "year<x", "year=1990..", "year=>2000"] {
+        assert!(numeric::parse_condition(cond).is_err(), "{}", cond);
+    }
+}
@@ -0,0 +1,308 @@
+//! Random vertical corpora encoded, indexed and read back.
+//!
+//! Each case renders documents with tokens, named entities (which nest,
+//! may be empty and may be self-closing), self-closing `<br/>` and glue
+//! tags into vertical text, several tags of a structure often at the same
+//! position. The expected values and structure ranges are kept on the
+//! side by a model of how the encoder writes structures.
+//! The text is compiled into a temporary directory with the encoder and
+//! `mkrev` (unless the encoder builds the positional revs), opened with
+//! `Corpus::open` and every attribute and structure is compared with the
+//! expectation.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use corp::corp::Corpus;
+use corp::encode::Encoder;
+use corp::wrrev;
+use proptest::prelude::*;
+
+const ATTRS: [&str; 3] = ["word", "lemma", "tag"];
+const NONE: &str = "===NONE===";
+
+#[derive(Debug, Clone)]
+enum Op {
+    Token(Vec<String>),
+    Break,
+    Glue,
+    Open(Option<String>),
+    Empty(Option<String>),
+    Close,
+}
+
+#[derive(Debug, Clone)]
+struct Doc {
+    title: String,
+    ops: Vec<Op>,
+}
+
+#[derive(Debug, Clone)]
+struct Spec {
+    types: Vec<&'static str>,
+    doc64: bool,
+    parallel: bool,
+    mkrev: bool,
+    docs: Vec<Doc>,
+}
+
+fn value() -> impl Strategy<Value = String> {
+    "[abcé中😀Ωž.-]{1,3}"
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        6 => prop::collection::vec(value(), ATTRS.len()).prop_map(Op::Token),
+        1 => Just(Op::Break),
+        1 => Just(Op::Glue),
+        1 => prop::option::of("(PER|LOC|ORG)").prop_map(Op::Open),
+        1 => prop::option::of("(PER|LOC|ORG)").prop_map(Op::Empty),
+        1 => Just(Op::Close),
+    ]
+}
+
+fn doc() -> impl Strategy<Value = Doc> {
+    ("[a-zé中]{1,4}( [a-zé中]{1,4})?", prop::collection::vec(op(), 0..120))
+        .prop_map(|(title, mut ops)| {
+            // empty documents would start where the next one does
+            if !ops.iter().any(|op| matches!(op, Op::Token(_))) {
+                ops.push(Op::Token(vec!["x".to_string(); ATTRS.len()]));
+            }
+            Doc { title, ops }
+        })
+}
+
+fn spec() -> impl Strategy<Value = Spec> {
+    let text_type = prop::sample::select(vec!["MD_MD", "MD_MGD", "MD_MI"]);
+    (prop::collection::vec(text_type, ATTRS.len()), any::<bool>(), any::<bool>(), any::<bool>(),
+     prop::collection::vec(doc(), 1..5))
+        .prop_map(|(types, doc64, parallel, mkrev, docs)| Spec { types, doc64, parallel, mkrev, docs })
+}
+
+/// How the encoder writes the tags of one structure: an empty tag is
+/// held back until a token or another tag of the structure at another
+/// position follows, replaced by a later empty tag at its position and
+/// dropped if the structure opens there.
+#[derive(Debug, Default)]
+struct StructModel {
+    /// ranges with the attribute values in the order they are written
+    records: Vec<(u64, u64, Vec<String>)>,
+    pending: Option<(u64, Vec<String>)>,
+    open: Vec<(u64, Vec<String>)>,
+}
+
+impl StructModel {
+    fn flush(&mut self) {
+        if let Some((pos, values)) = self.pending.take() {
+            self.records.push((pos, pos, values));
+        }
+    }
+
+    fn flush_unless_at(&mut self, pos: u64) {
+        if self.pending.as_ref().is_some_and(|p| p.0 != pos) {
+            self.flush();
+        }
+    }
+
+    fn empty(&mut self, pos: u64, values: Vec<String>) {
+        self.flush_unless_at(pos);
+        self.pending = Some((pos, values));
+    }
+
+    fn open(&mut self, pos: u64, values: Vec<String>) {
+        self.flush_unless_at(pos);
+        self.pending = None;
+        self.open.push((pos, values));
+    }
+
+    fn close(&mut self, pos: u64) {
+        self.flush_unless_at(pos);
+        let (beg, values) = self.open.pop().expect("closing a structure that is not open");
+        self.records.push((beg, pos, values));
+    }
+}
+
+/// Vertical text with the values and structures it should encode to.
+#[derive(Debug, Default)]
+struct Expected {
+    vert: String,
+    /// values of each positional attribute by position
+    attrs: Vec<Vec<String>>,
+    /// ranges of each structure by structure number
+    structs: BTreeMap<&'static str, Vec<(u64, u64)>>,
+    /// values of each structure attribute by structure number
+    struct_attrs: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl Expected {
+    fn new(spec: &Spec) -> Expected {
+        let mut e = Expected { attrs: vec![Vec::new(); ATTRS.len()], ..Expected::default() };
+        let mut models: BTreeMap<&'static str, StructModel> = BTreeMap::new();
+        let mut pos = 0u64;
+        let ne_type = |kind: &Option<String>| vec![kind.clone().unwrap_or(NONE.to_string())];
+        let ne_tag = |kind: &Option<String>| kind.as_ref()
+            .map_or("ne".to_string(), |k| format!("ne type=\"{}\"", k));
+        for (n, doc) in spec.docs.iter().enumerate() {
+            e.vert += &format!("<doc id=\"d{}\" title=\"{}\">\n", n, doc.title);
+            models.entry("doc").or_default().open(pos, vec![format!("d{}", n), doc.title.clone()]);
+            let mut nes = 0;
+            for op in &doc.ops {
+                match op {
+                    Op::Token(values) => {
+                        e.vert += &values.join("\t");
+                        e.vert.push('\n');
+                        for (a, v) in e.attrs.iter_mut().zip(values) {
+                            a.push(v.clone());
+                        }
+                        for m in models.values_mut() {
+                            if m.pending.as_ref().is_some_and(|p| p.0 == pos) {
+                                m.flush();
+                            }
+                        }
+                        pos += 1;
+                    }
+                    Op::Break | Op::Glue => {
+                        let name = if matches!(op, Op::Break) { "br" } else { "g" };
+                        e.vert += &format!("<{}/>\n", name);
+                        models.entry(name).or_default().empty(pos, Vec::new());
+                    }
+                    Op::Open(kind) => {
+                        e.vert += &format!("<{}>\n", ne_tag(kind));
+                        models.entry("ne").or_default().open(pos, ne_type(kind));
+                        nes += 1;
+                    }
+                    Op::Empty(kind) => {
+                        e.vert += &format!("<{}/>\n", ne_tag(kind));
+                        models.entry("ne").or_default().empty(pos, ne_type(kind));
+                    }
+                    Op::Close if nes > 0 => {
+                        e.vert += "</ne>\n";
+                        models.entry("ne").or_default().close(pos);
+                        nes -= 1;
+                    }
+                    Op::Close => {}
+                }
+            }
+            for _ in 0..nes {
+                e.vert += "</ne>\n";
+                models.entry("ne").or_default().close(pos);
+            }
+            e.vert += "</doc>\n";
+            models.entry("doc").or_default().close(pos);
+        }
+        for (name, mut m) in models {
+            m.flush();
+            e.structs.insert(name, m.records.iter().map(|r| (r.0, r.1)).collect());
+            let attrs: &[&'static str] = match name {
+                "doc" => &["doc.id", "doc.title"],
+                "ne" => &["ne.type"],
+                _ => &[],
+            };
+            for (i, &attr) in attrs.iter().enumerate() {
+                e.struct_attrs.insert(attr, m.records.iter().map(|r| r.2[i].clone()).collect());
+            }
+        }
+        e
+    }
+
+    /// Config of the corpus in `data`; structures that do not occur are
+    /// left out since their empty files cannot be opened.
+    fn conf(&self, spec: &Spec, data: &Path) -> String {
+        let mut conf = format!("PATH \"{}/\"\n", data.display());
+        for (name, t) in ATTRS.iter().zip(&spec.types) {
+            conf += &format!("ATTRIBUTE {} {{\n    TYPE \"{}\"\n}}\n", name, t);
+        }
+        conf += "STRUCTURE doc {\n";
+        if spec.doc64 {
+            conf += "    TYPE \"file64\"\n";
+        }
+        conf += "    ATTRIBUTE id\n    ATTRIBUTE title\n}\n";
+        if self.structs.contains_key("ne") {
+            conf += "STRUCTURE ne {\n    ATTRIBUTE type\n}\n";
+        }
+        for name in ["br", "g"] {
+            if self.structs.contains_key(name) {
+                conf += &format!("STRUCTURE {}\n", name);
+            }
+        }
+        conf
+    }
+}
+
+fn compile(spec: &Spec, e: &Expected, dir: &Path) -> Result<Corpus, Box<dyn std::error::Error>> {
+    let data = dir.join("data");
+    let conf_path = dir.join("corpus.conf");
+    let conf_text = e.conf(spec, &data);
+    std::fs::write(&conf_path, &conf_text)?;
+    let conf = corpconf::parse_conf_opt(&conf_text)?;
+    let mut enc = Encoder::new(&conf, &data)?;
+    enc.set_parallel(spec.parallel);
+    enc.set_build_revs(spec.mkrev);
+    enc.encode(e.vert.as_bytes())?;
+    enc.finish()?;
+    if !spec.mkrev {
+        for name in ATTRS.iter().copied().chain(e.struct_attrs.keys().copied()) {
+            wrrev::build_rev(&data.join(name), None)?;
+        }
+    }
+    Corpus::open(conf_path.to_str().ok_or("bad path")?)
+}
+
+/// Compares the attribute `name` with `values`, its value at each position
+/// (structure number for structure attributes).
+fn check_attr(corp: &Corpus, name: &str, values: &[String]) -> Result<(), TestCaseError> {
+    let a = corp.open_attribute(name).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let n = values.len();
+    prop_assert_eq!(a.text().size(), n, "size of {}", name);
+    let mut froms: Vec<usize> = (0..n).step_by(37).collect();
+    froms.push(n - 1);
+    for from in froms {
+        let got: Vec<&str> = a.iter_ids(from as u64).take(n - from).map(|id| a.id2str(id)).collect();
+        prop_assert_eq!(&got, &values[from..], "{} from {}", name, from);
+    }
+    let mut poss: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for (pos, v) in values.iter().enumerate() {
+        let id = a.str2id(v);
+        prop_assert!(id.is_some(), "{}: no id for {:?}", name, v);
+        prop_assert_eq!(a.text().get(pos as u64), id.unwrap(), "{} at {}", name, pos);
+        poss.entry(v).or_default().push(pos as u64);
+    }
+    prop_assert_eq!(a.id_range() as usize, poss.len(), "lexicon size of {}", name);
+    prop_assert_eq!(a.str2id("nothing like this"), None);
+    let frq = a.get_freq("frq").map_err(|e| TestCaseError::fail(e.to_string()))?;
+    for (v, want) in poss {
+        let id = a.str2id(v).unwrap();
+        let got: Vec<u64> = a.revidx().id2poss(id).collect();
+        prop_assert_eq!(&got, &want, "positions of {} {:?}", name, v);
+        prop_assert_eq!(a.revidx().count(id), want.len() as u64, "count of {} {:?}", name, v);
+        prop_assert_eq!(frq.frq(id), want.len() as u64);
+    }
+    Ok(())
+}
+
+fn check(corp: &Corpus, e: &Expected) -> Result<(), TestCaseError> {
+    for (name, values) in ATTRS.iter().zip(&e.attrs) {
+        check_attr(corp, name, values)?;
+    }
+    for (&name, ranges) in &e.structs {
+        let s = corp.open_struct(name).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        let got: Vec<(u64, u64)> = (0..s.len() as u64).map(|n| (s.beg_at(n), s.end_at(n))).collect();
+        prop_assert_eq!(&got, ranges, "ranges of {}", name);
+    }
+    for (&name, values) in &e.struct_attrs {
+        check_attr(corp, name, values)?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn encoded_corpus_reads_back(spec in spec()) {
+        let e = Expected::new(&spec);
+        let dir = tempfile::tempdir().unwrap();
+        let corp = compile(&spec, &e, dir.path()).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        check(&corp, &e)?;
+    }
+}
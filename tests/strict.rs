@@ -0,0 +1,108 @@
+//! Strict mode reports of the encoder.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use corp::encode::Encoder;
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+const CONF: &str = "\
+MAXVALUELEN 10
+ATTRIBUTE word
+ATTRIBUTE lemma
+STRUCTURE doc {
+    ATTRIBUTE id
+}
+STRUCTURE s
+";
+
+fn check(conf: &str, vert: &[u8]) -> (Vec<String>, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("data");
+    let conf = corpconf::parse_conf_opt(&format!("PATH \"{}/\"\n{}", data.display(), conf)).unwrap();
+    let report = Shared::default();
+    let mut enc = Encoder::new(&conf, &data).unwrap();
+    enc.set_strict(Box::new(report.clone()));
+    enc.encode(vert).unwrap();
+    let err = enc.finish().unwrap_err();
+    let out = String::from_utf8(report.0.lock().unwrap().clone()).unwrap();
+    (out.lines().map(str::to_string).collect(), err.to_string())
+}
+
+#[test]
+fn one_report_line_per_error_kind() {
+    let mut vert = b"\
+</s>
+<doc id=\"a\">
+a\ta
+<s foo=\"x\">
+b
+</s>
+<s>
+</doc>
+</s>
+</doc>
+<unk>
+ccccccccccccccc\tc
+<doc id=\"b>
+<=x>
+".to_vec();
+    vert.extend_from_slice(b"\xff\tx\n<doc id=\"c\">\nd\td\n");
+
+    let (lines, err) = check(CONF, &vert);
+    assert_eq!(lines, [
+        r#"{"line":1,"kind":"unopened_tag","text":"</s>"}"#,
+        r#"{"line":4,"kind":"undeclared_attribute","text":"s.foo"}"#,
+        r#"{"line":5,"kind":"field_count","text":"1 fields, expected 2: b"}"#,
+        r#"{"line":8,"kind":"mismatched_tag","text":"</doc> closing <s>"}"#,
+        r#"{"line":11,"kind":"undeclared_structure","text":"<unk>"}"#,
+        r#"{"line":12,"kind":"overlong_value","text":"ccccccccccccccc... (15 bytes)"}"#,
+        r#"{"line":13,"kind":"malformed_attrs","text":"unterminated attribute value: <doc id=\"b>"}"#,
+        r#"{"line":14,"kind":"malformed_tag","text":"bad tag name: <=x>"}"#,
+        r#"{"line":15,"kind":"invalid_utf8","text":"�\tx"}"#,
+        r#"{"line":16,"kind":"unclosed_tag","text":"<doc>"}"#,
+    ]);
+    assert_eq!(err, "10 strict mode violations");
+}
+
+#[test]
+fn empty_mapped_word() {
+    // lines are trimmed, so the word is only empty when taken from another column
+    let conf = "\
+ATTRIBUTE word {
+    COLUMN 1
+}
+ATTRIBUTE lemma {
+    COLUMN 0
+}
+";
+    let (lines, err) = check(conf, b"a\tb\nc\t\td\n");
+    assert_eq!(lines, [r#"{"line":2,"kind":"empty_token","text":"\tc"}"#]);
+    assert_eq!(err, "1 strict mode violations");
+}
+
+#[test]
+fn tags_that_are_not_xml_names() {
+    let vert = b"<doc id=\"a\">\n<3>\n<x/>\na\ta\n</doc>\n";
+    let (lines, _) = check(&format!("UNKNOWNSTRUCT token\n{}", CONF), vert);
+    assert_eq!(lines, [
+        r#"{"line":2,"kind":"undeclared_structure","text":"<3>"}"#,
+        r#"{"line":3,"kind":"undeclared_structure","text":"<x/>"}"#,
+    ]);
+    // only x is declared, <3> is a token in both modes
+    let (lines, _) = check(&format!("UNKNOWNSTRUCT declare\n{}", CONF), vert);
+    assert_eq!(lines, [
+        r#"{"line":2,"kind":"undeclared_structure","text":"<3>"}"#,
+    ]);
+}
@@ -0,0 +1,106 @@
+//! Structure searches against naive linear scans.
+
+use std::path::Path;
+
+use corp::structure::{self, StructCursor};
+use proptest::prelude::*;
+
+/// Structure ranges as encodevert writes them: in order, not overlapping,
+/// empty ones anywhere in between. Each item is a gap before the
+/// structure and its length.
+fn ranges() -> impl Strategy<Value = Vec<(u64, u64)>> {
+    prop::collection::vec((0u64..4, prop_oneof![Just(0u64), 1u64..6]), 0..300)
+        .prop_map(|items| {
+            let mut pos = 0;
+            items.into_iter().map(|(gap, len)| {
+                let beg = pos + gap;
+                pos = beg + len;
+                (beg, pos)
+            }).collect()
+        })
+}
+
+fn write_rng(base: &Path, ranges: &[(u64, u64)], type64: bool) {
+    let mut data = Vec::new();
+    for &(b, e) in ranges {
+        if type64 {
+            data.extend_from_slice(&b.to_le_bytes());
+            data.extend_from_slice(&e.to_le_bytes());
+        } else {
+            data.extend_from_slice(&(b as u32).to_le_bytes());
+            data.extend_from_slice(&(e as u32).to_le_bytes());
+        }
+    }
+    std::fs::write(base.with_extension("rng"), data).unwrap();
+}
+
+fn naive_find_beg(r: &[(u64, u64)], pos: u64) -> u64 {
+    r.iter().map(|&(b, _)| b).filter(|&b| b >= pos).min().unwrap_or(u64::MAX)
+}
+
+fn naive_find_end(r: &[(u64, u64)], pos: u64) -> (u64, u64) {
+    r.iter().position(|&(_, e)| e >= pos)
+        .map_or((u64::MAX, u64::MAX), |n| (n as u64, r[n].0))
+}
+
+fn naive_num_at_pos(r: &[(u64, u64)], pos: u64) -> Option<u64> {
+    r.iter().position(|&(b, e)| b <= pos && pos < e)
+        .or_else(|| r.iter().rposition(|&(b, e)| b == pos && e == pos))
+        .map(|n| n as u64)
+}
+
+fn check(r: &[(u64, u64)], type64: bool) -> Result<(), TestCaseError> {
+    // the mapped file cannot be empty
+    prop_assume!(!r.is_empty());
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("s");
+    write_rng(&base, r, type64);
+    let s = structure::open(base.to_str().unwrap(), type64).unwrap();
+    prop_assert_eq!(s.len(), r.len());
+    let size = r.last().unwrap().1;
+    let mut cursor = StructCursor::new(s.as_ref());
+    for pos in 0..size + 3 {
+        prop_assert_eq!(s.find_beg(pos), naive_find_beg(r, pos), "find_beg({})", pos);
+        prop_assert_eq!(s.find_end(pos), naive_find_end(r, pos), "find_end({})", pos);
+        prop_assert_eq!(s.num_at_pos(pos), naive_num_at_pos(r, pos), "num_at_pos({})", pos);
+        prop_assert_eq!(cursor.num_at(pos), naive_num_at_pos(r, pos), "cursor at {}", pos);
+    }
+    for pos in (0..size + 3).rev().step_by(3) {
+        prop_assert_eq!(cursor.num_at(pos), naive_num_at_pos(r, pos), "cursor back at {}", pos);
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn map_structure32_matches_scan(r in ranges()) {
+        check(&r, false)?;
+    }
+
+    #[test]
+    fn map_structure64_matches_scan(r in ranges()) {
+        check(&r, true)?;
+    }
+}
+
+#[test]
+fn single_structure() {
+    check(&[(0, 5)], false).unwrap();
+    check(&[(3, 3)], true).unwrap();
+}
+
+#[test]
+fn empty_structures() {
+    // <doc/> before, between and after documents
+    let r = [(0, 0), (0, 4), (4, 4), (4, 4), (4, 9), (9, 9)];
+    check(&r, false).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("s");
+    write_rng(&base, &r, false);
+    let s = structure::open(base.to_str().unwrap(), false).unwrap();
+    assert_eq!(s.num_at_pos(0), Some(1));
+    assert_eq!(s.num_at_pos(4), Some(4));
+    assert_eq!(s.num_at_pos(9), Some(5));
+    assert_eq!(s.find_end(4), (1, 0));
+    assert_eq!(s.find_beg(10), u64::MAX);
+}
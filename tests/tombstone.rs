@@ -0,0 +1,87 @@
+//! Deleted structures hidden from revs, refused by merging and dropped
+//! by compacting.
+
+mod common;
+
+use corp::{merge, tombstone};
+
+use common::{compile, open};
+
+const CONF: &str = "\
+ATTRIBUTE word
+ATTRIBUTE tag {
+    MULTIVALUE yes
+    MULTISEP \"|\"
+}
+STRUCTURE doc {
+    ATTRIBUTE id
+}
+";
+
+const VERT: &str = "\
+<doc id=\"a\">\nthe\tD\ndog\tN|V\n</doc>
+<doc id=\"b\">\na\tD\ndog\tN\nbarks\tV\n</doc>
+<doc id=\"a\">\ndog\tN|V\n</doc>
+";
+
+#[test]
+fn counts_leave_out_deleted_positions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = compile(dir.path(), CONF, &[VERT]);
+    tombstone::add(&open(&path), "doc", &[1]).unwrap();
+
+    let corp = open(&path);
+    for (name, value, poss) in [("word", "dog", &[1, 5][..]), ("word", "barks", &[]),
+                                ("tag", "N", &[1, 5]), ("tag", "V", &[1, 5]), ("tag", "D", &[0]),
+                                ("doc.id", "a", &[0, 2]), ("doc.id", "b", &[])] {
+        let a = corp.open_attribute(name).unwrap();
+        let id = a.str2id(value).unwrap();
+        assert_eq!(a.revidx().id2poss(id).collect::<Vec<_>>(), poss, "{} {}", name, value);
+        assert_eq!(a.revidx().count(id), poss.len() as u64, "{} {}", name, value);
+    }
+    // tombstones are read once per corpus
+    tombstone::add(&corp, "doc", &[0]).unwrap();
+    let word = corp.open_attribute("word").unwrap();
+    assert_eq!(word.revidx().count(word.str2id("the").unwrap()), 1);
+    let word = open(&path).open_attribute("word").unwrap();
+    assert_eq!(word.revidx().count(word.str2id("the").unwrap()), 0);
+}
+
+#[test]
+fn merge_refuses_deleted_structures() {
+    let (dir, out) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let path = compile(dir.path(), CONF, &[VERT]);
+    let conf = open(&path).conf;
+    merge::merge_corpora(&[open(&path)], &conf, out.path()).unwrap();
+    tombstone::add(&open(&path), "doc", &[2]).unwrap();
+    let err = merge::merge_corpora(&[open(&path)], &conf, out.path()).unwrap_err();
+    assert!(err.to_string().contains("compact"), "{}", err);
+}
+
+#[test]
+fn compact_equals_compiling_the_rest() {
+    let (dir, whole, out) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap(),
+                             tempfile::tempdir().unwrap());
+    // every new value spills the lexicon writers to disk
+    let conf = format!("LEXMEMLIMIT 1\n{}", CONF);
+    let path = compile(dir.path(), &conf, &[VERT]);
+    tombstone::add(&open(&path), "doc", &[1]).unwrap();
+    tombstone::compact(&open(&path), out.path()).unwrap();
+    let rest: String = VERT.split_inclusive("</doc>\n").filter(|d| !d.contains("\"b\"")).collect();
+    compile(whole.path(), CONF, &[&rest]);
+    let mut files: Vec<_> = std::fs::read_dir(out.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    files.sort();
+    assert!(files.iter().any(|n| n == "tag.lex"));
+    for f in &files {
+        let got = std::fs::read(out.path().join(f)).unwrap();
+        let want = std::fs::read(whole.path().join("data").join(f)).unwrap();
+        assert!(got == want, "{:?} differs", f);
+    }
+
+    let bad = tempfile::tempdir().unwrap();
+    let path = compile(bad.path(), CONF, &[VERT]);
+    let mut corp = open(&path);
+    corp.conf = corpconf::parse_conf_opt(&format!("LEXMEMLIMIT x\n{}", CONF)).unwrap();
+    let err = tombstone::compact(&corp, out.path()).unwrap_err();
+    assert!(err.to_string().contains("LEXMEMLIMIT"), "{}", err);
+}
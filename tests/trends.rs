@@ -0,0 +1,113 @@
+//! Trend statistics and the most rising and falling items.
+
+use chrono::NaiveDate;
+
+use corp::corp::Corpus;
+use corp::dates::Period;
+use corp::mem::MemBuilder;
+use corp::trends::{self, erfc, inc_beta, ln_gamma, mann_kendall, regression, Method};
+
+fn close(got: f64, want: f64, tol: f64) -> bool {
+    (got - want).abs() <= tol * want.abs().max(1e-300)
+}
+
+macro_rules! assert_close {
+    ($got:expr, $want:expr, $tol:expr) => {
+        assert!(close($got, $want, $tol), "{} = {}, expected {}", stringify!($got), $got, $want)
+    };
+}
+
+#[test]
+fn special_functions() {
+    assert_close!(ln_gamma(5.0), 24f64.ln(), 1e-12);
+    assert_close!(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln(), 1e-12);
+    assert_close!(ln_gamma(0.1), 2.252_712_651_734_206, 1e-12);
+    assert_close!(ln_gamma(100.5), 361.435_540_467_778, 1e-12);
+    assert_close!(inc_beta(1.0, 1.0, 0.37), 0.37, 1e-10);
+    assert_close!(inc_beta(3.0, 3.0, 0.5), 0.5, 1e-10);
+    // I_x(2, 3) = 6x²(1-x)² + 4x³(1-x) + x⁴
+    assert_close!(inc_beta(2.0, 3.0, 0.3), 0.3483, 1e-10);
+    assert_close!(inc_beta(0.5, 0.5, 0.2), 0.295_167_235_300_867, 1e-10);
+    assert_eq!(inc_beta(2.0, 3.0, 0.0), 0.0);
+    assert_eq!(inc_beta(2.0, 3.0, 1.0), 1.0);
+    assert_close!(erfc(0.0), 1.0, 1.2e-7);
+    assert_close!(erfc(1.0), 0.157_299_207_050_285_13, 1.2e-7);
+    assert_close!(erfc(-1.0), 1.842_700_792_949_714_8, 1.2e-7);
+    assert_close!(erfc(2.0), 0.004_677_734_981_047_266, 1.2e-7);
+}
+
+#[test]
+fn regression_slope_and_p_value() {
+    // slope 0.8 with t = 0.8 / √0.12 on 3 degrees of freedom, whose
+    // two-sided p-value is 1 - 2/π (atan(t/√3) + (t/√3) / (1 + t²/3))
+    let (slope, p) = regression(&[0.0, 1.0, 2.0, 3.0, 4.0], &[1.0, 3.0, 2.0, 5.0, 4.0]);
+    assert_close!(slope, 0.8, 1e-12);
+    assert_close!(p, 0.104_088_038_661_828, 1e-9);
+    // an exact line, and too few points for a test
+    assert_eq!(regression(&[0.0, 1.0, 2.0], &[5.0, 3.0, 1.0]), (-2.0, 0.0));
+    assert_eq!(regression(&[0.0, 1.0], &[1.0, 2.0]), (1.0, 1.0));
+    assert_eq!(regression(&[0.0], &[1.0]), (0.0, 1.0));
+}
+
+#[test]
+fn mann_kendall_statistic() {
+    // S = 45, Var(S) = 10·9·25/18 = 125, Z = (S - 1)/√Var(S)
+    let ys: Vec<f64> = (1..=10).map(f64::from).collect();
+    let (z, p) = mann_kendall(&ys);
+    assert_close!(z, 44.0 / 125f64.sqrt(), 1e-12);
+    assert_close!(p, 8.303_070_332_645e-5, 1e-6);
+    let (z, _) = mann_kendall(&ys.iter().rev().copied().collect::<Vec<_>>());
+    assert_close!(z, -44.0 / 125f64.sqrt(), 1e-12);
+    // S = 5 with one pair of ties: Var(S) = (4·3·13 - 2·1·9)/18
+    let (z, p) = mann_kendall(&[1.0, 2.0, 2.0, 3.0]);
+    assert_close!(z, 4.0 / (138.0f64 / 18.0).sqrt(), 1e-12);
+    assert_close!(p, 0.148_561_774_891_869, 1e-6);
+    assert_eq!(mann_kendall(&[2.0, 2.0, 2.0]), (0.0, 1.0));
+}
+
+/// Five yearly documents of ten tokens in which `rise` gets more
+/// frequent and `fall` less, and an undated one.
+fn dated_corpus() -> Corpus {
+    let mut b = MemBuilder::new(&["word"]);
+    b.option("doc.date", "DATE", "yes");
+    for (year, n) in (2001..=2005).zip(0..) {
+        let beg = b.len();
+        for (word, count) in [("rise", n), ("fall", 4 - n), ("flat", 2), ("x", 4)] {
+            for _ in 0..count {
+                b.token(&[word]);
+            }
+        }
+        b.span("doc", beg, b.len(), &[("date", &year.to_string())]);
+    }
+    let beg = b.len();
+    for _ in 0..5 {
+        b.token(&["rise"]);
+    }
+    b.span("doc", beg, b.len(), &[]);
+    b.build().unwrap()
+}
+
+#[test]
+fn top_rising_and_falling() {
+    let corp = dated_corpus();
+    let t = trends::trends(&corp, "word", "doc.date", Period::Year, 1).unwrap();
+    let years: Vec<NaiveDate> = (2001..=2005).map(|y| NaiveDate::from_ymd_opt(y, 1, 1).unwrap()).collect();
+    assert_eq!(t.series_starts(), years);
+    assert_eq!(t.sizes, [10; 5]);
+    let word = corp.open_attribute("word").unwrap();
+    let name = |t: &trends::Trend| word.id2str(t.id).to_string();
+    let rise = t.items.iter().find(|t| name(t) == "rise").unwrap();
+    assert_eq!(rise.freq, 10);
+    assert_eq!(rise.series, [0.0, 1e5, 2e5, 3e5, 4e5]);
+    assert_close!(rise.rel_slope, 0.5, 1e-12);
+    assert_eq!(rise.p_regression, 0.0);
+
+    for method in [Method::Regression, Method::MannKendall] {
+        let (rising, falling) = t.top(method, 0.05, 3);
+        assert_eq!(rising.into_iter().map(name).collect::<Vec<_>>(), ["rise"]);
+        assert_eq!(falling.into_iter().map(name).collect::<Vec<_>>(), ["fall"]);
+    }
+    // the Mann-Kendall p-value of five rising values is 0.0275
+    let (rising, falling) = t.top(Method::MannKendall, 0.01, 3);
+    assert!(rising.is_empty() && falling.is_empty());
+}
@@ -0,0 +1,127 @@
+//! Parsing of vertical text.
+
+use std::collections::HashSet;
+
+use corp::vert::{self, ErrorKind, Event, Malformed, Options, Parser};
+
+fn parse(text: &[u8], opts: Options) -> Vec<Result<(u64, Event), ErrorKind>> {
+    Parser::with_options(text, opts)
+        .map(|l| l.map(|l| (l.num, l.event)).map_err(|e| e.kind))
+        .collect()
+}
+
+fn malformed(mode: Malformed) -> Options {
+    Options { malformed: mode, ..Options::default() }
+}
+
+fn token(fields: &[&str]) -> Event {
+    Event::Token(fields.iter().map(|f| f.to_string()).collect())
+}
+
+#[test]
+fn events() {
+    let text = "<doc id=\"a b\" n=1>\n\n# a comment\nword\tlemma\n<g/>\n<br/>\n<!-- note -->\n</doc>\n";
+    let got: Vec<(u64, Event)> = parse(text.as_bytes(), Options::default())
+        .into_iter().map(Result::unwrap).collect();
+    assert_eq!(got, [
+        (1, Event::Open { name: "doc".into(),
+                          attrs: vec![("id".into(), "a b".into()), ("n".into(), "1".into())] }),
+        (3, Event::Comment(" a comment".into())),
+        (4, token(&["word", "lemma"])),
+        (5, Event::Glue),
+        (6, Event::Empty { name: "br".into(), attrs: Vec::new() }),
+        (7, Event::Comment("note".into())),
+        (8, Event::Close { name: "doc".into() }),
+    ]);
+}
+
+#[test]
+fn entities() {
+    assert_eq!(vert::unescape("a &amp; b &lt;&gt;&quot;&apos;"), "a & b <>\"'");
+    assert_eq!(vert::unescape("&#233;&#xE9;&#XE9;"), "ééé");
+    // unknown, invalid and unterminated references stay as they are
+    assert_eq!(vert::unescape("&nbsp; &#0; &#x110000; & &amp"), "&nbsp; &#0; &#x110000; & &amp");
+    assert_eq!(vert::unescape("&#x0010FFFF;"), "\u{10FFFF}");
+    assert_eq!(vert::unescape("&#x00010FFFF;"), "&#x00010FFFF;");
+    assert_eq!(vert::unescape("&#+65; &#x+41; &#-0; &#; &#x;"), "&#+65; &#x+41; &#-0; &#; &#x;");
+    let many = "&".repeat(100_000) + ";";
+    assert_eq!(vert::unescape(&many), many);
+
+    let text = "x&amp;y\tR&amp;D\n<doc t=\"&lt;b&gt;\">\n";
+    let got = parse(text.as_bytes(), Options::default());
+    assert_eq!(got[0], Ok((1, token(&["x&y", "R&D"]))));
+    assert_eq!(got[1], Ok((2, Event::Open { name: "doc".into(),
+                                            attrs: vec![("t".into(), "<b>".into())] })));
+    let raw = parse(text.as_bytes(), Options { entities: false, ..Options::default() });
+    assert_eq!(raw[0], Ok((1, token(&["x&amp;y", "R&amp;D"]))));
+}
+
+#[test]
+fn malformed_tags() {
+    let text = "</a b>\n<=x>\nok\n";
+    assert_eq!(parse(text.as_bytes(), malformed(Malformed::Lenient)), [
+        Ok((1, token(&["</a b>"]))),
+        Ok((2, token(&["<=x>"]))),
+        Ok((3, token(&["ok"]))),
+    ]);
+    assert_eq!(parse(text.as_bytes(), malformed(Malformed::Skip)), [Ok((3, token(&["ok"])))]);
+    assert_eq!(parse(text.as_bytes(), malformed(Malformed::Error)), [
+        Err(ErrorKind::MalformedTag),
+        Err(ErrorKind::MalformedTag),
+        Ok((3, token(&["ok"]))),
+    ]);
+}
+
+#[test]
+fn malformed_attrs() {
+    let text = "<doc id=\"a\" title=\"b>\n";
+    assert_eq!(parse(text.as_bytes(), malformed(Malformed::Lenient)), [
+        Ok((1, Event::Open { name: "doc".into(), attrs: vec![("id".into(), "a".into())] })),
+    ]);
+    assert_eq!(parse(text.as_bytes(), malformed(Malformed::Skip)), []);
+    assert_eq!(parse(text.as_bytes(), malformed(Malformed::Error)), [Err(ErrorKind::MalformedAttrs)]);
+}
+
+#[test]
+fn invalid_utf8() {
+    let text = b"a\xffb\nok\n";
+    assert_eq!(parse(text, malformed(Malformed::Lenient)), [
+        Ok((1, token(&["a\u{FFFD}b"]))),
+        Ok((2, token(&["ok"]))),
+    ]);
+    assert_eq!(parse(text, malformed(Malformed::Skip)), [Ok((2, token(&["ok"])))]);
+    assert_eq!(parse(text, malformed(Malformed::Error)), [
+        Err(ErrorKind::InvalidUtf8),
+        Ok((2, token(&["ok"]))),
+    ]);
+}
+
+#[test]
+fn structures_filter() {
+    let opts = Options {
+        structures: Some(HashSet::from(["doc".to_string()])),
+        ..Options::default()
+    };
+    let text = "<doc>\n<unk>\n<3/>\n</unk>\n</doc>\n";
+    assert_eq!(parse(text.as_bytes(), opts), [
+        Ok((1, Event::Open { name: "doc".into(), attrs: Vec::new() })),
+        Ok((2, token(&["<unk>"]))),
+        Ok((3, token(&["<3/>"]))),
+        Ok((4, token(&["</unk>"]))),
+        Ok((5, Event::Close { name: "doc".into() })),
+    ]);
+}
+
+#[test]
+fn xml_names_filter() {
+    let opts = Options { xml_names: true, ..Options::default() };
+    let text = "<doc>\n<3>\n<-x/>\n<_a:b-c.d/>\n<č>\n</3>\n";
+    assert_eq!(parse(text.as_bytes(), opts), [
+        Ok((1, Event::Open { name: "doc".into(), attrs: Vec::new() })),
+        Ok((2, token(&["<3>"]))),
+        Ok((3, token(&["<-x/>"]))),
+        Ok((4, Event::Empty { name: "_a:b-c.d".into(), attrs: Vec::new() })),
+        Ok((5, Event::Open { name: "č".into(), attrs: Vec::new() })),
+        Ok((6, token(&["</3>"]))),
+    ]);
+}
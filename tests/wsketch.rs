@@ -0,0 +1,218 @@
+//! Word sketches and their concordances read from a small sketch written
+//! here.
+
+use std::path::Path;
+
+use corp::mem::MemBuilder;
+use corp::structure::Struct;
+use corp::wrbits::BitsWriter;
+use corp::wsketch::{CollHit, WMap};
+
+/// Positions of a collocation with the relative collocate positions.
+type Hits = &'static [(u64, Option<i32>)];
+
+/// (head, [(relation, [(collocate, collocate frequency, hits)])])
+type Sketch = &'static [(u32, &'static [(u32, &'static [(u32, u64, Hits)])])];
+
+// rel 0 coll 10 is the base collocation of the multiword sketches below,
+// in the sentences 0..5, 5..10 and 15..20
+const SKETCH: Sketch = &[
+    (1, &[
+        (0, &[(10, 5, &[(1, Some(1)), (6, Some(2)), (16, Some(-1))]),
+              (11, 5, &[(2, Some(-2)), (12, None)])]),
+        (1, &[(20, 13, &[(3, Some(1)), (8, Some(-1)), (17, Some(3)), (18, None)]),
+              (21, 2, &[(13, Some(-1))])]),
+    ]),
+    (2, &[(0, &[(10, 5, &[(4, Some(-3))])])]),
+];
+
+fn bits() -> BitsWriter<Vec<u8>> {
+    BitsWriter::new(Vec::new())
+}
+
+/// Leaves the 16 bytes of a level or rev header zeroed.
+fn header(w: &mut BitsWriter<Vec<u8>>) {
+    for _ in 0..128 {
+        w.bit(false);
+    }
+}
+
+fn finish(w: BitsWriter<Vec<u8>>, path: &str) {
+    let mut data = w.finish().unwrap();
+    data.resize(data.len().next_multiple_of(8), 0);
+    std::fs::write(path, data).unwrap();
+}
+
+/// Writes `sketch` in format version 4 with every record synced, so that
+/// each one holds its seek and id instead of the differences.
+fn write_sketch(base: &Path, sketch: Sketch) {
+    let (mut l1, mut l2, mut l3, mut rev) = (bits(), bits(), bits(), bits());
+    for w in [&mut l1, &mut l2, &mut l3, &mut rev] {
+        header(w);
+    }
+    // level sizes, unused; the last head; no commonest forms, no
+    // adjusted seeks
+    for w in [&mut l1, &mut l2, &mut l3] {
+        w.delta(1);
+    }
+    l1.delta(sketch.last().unwrap().0 as u64);
+    l3.bit(false);
+    l3.bit(false);
+    while l1.bits_written() < 32 * 8 {
+        l1.bit(false);
+    }
+    // alignment 2: byte seeks, positions stored plus one
+    rev.delta(2);
+    rev.delta(1000);
+
+    let record = |w: &mut BitsWriter<Vec<u8>>, seek: u64, id: u32, vals: &[u64]| {
+        w.delta(1);
+        w.delta(seek);
+        w.delta(id as u64 + 1);
+        for &v in vals {
+            w.delta(v);
+        }
+    };
+    for &(head, rels) in sketch {
+        let seek2 = l2.bits_written();
+        let mut headfrq = 0;
+        for &(rel, colls) in rels {
+            let seek3 = l3.bits_written();
+            let mut relfrq = 0;
+            for &(coll, frq, hits) in colls {
+                rev.byte_align();
+                let seek = rev.bits_written() / 8;
+                let mut prev = 0;
+                for &(pos, collpos) in hits {
+                    rev.delta(pos - prev + 1);
+                    prev = pos;
+                    match collpos {
+                        None => rev.gamma(1),
+                        Some(r) => {
+                            rev.gamma(2 * r.unsigned_abs() as u64 + (r < 0) as u64);
+                            rev.gamma(1);
+                        }
+                    }
+                }
+                record(&mut l3, seek, coll, &[hits.len() as u64, 1, frq]);
+                relfrq += hits.len() as u64;
+            }
+            record(&mut l2, seek3, rel, &[colls.len() as u64, 1, relfrq]);
+            headfrq += relfrq;
+        }
+        record(&mut l1, seek2, head, &[rels.len() as u64, headfrq]);
+    }
+    let name = |suffix: &str| format!("{}{}", base.display(), suffix);
+    finish(l1, &name(".map0.com"));
+    finish(l2, &name(".map1.com"));
+    finish(l3, &name(".map2.com"));
+    finish(rev, &name(".rev"));
+    std::fs::write(name(".map0.idx"), [256u32, 0].map(u32::to_le_bytes).concat()).unwrap();
+}
+
+fn open_sketch(dir: &Path) -> WMap {
+    let base = dir.join("ws");
+    write_sketch(&base, SKETCH);
+    WMap::new(base.to_str().unwrap()).unwrap()
+}
+
+/// Sentences of five positions each.
+fn sentences() -> Box<dyn Struct> {
+    let mut b = MemBuilder::new(&["word"]);
+    for _ in 0..25 {
+        b.token(&["w"]);
+    }
+    for beg in (0..25).step_by(5) {
+        b.span("s", beg, beg + 5, &[]);
+    }
+    b.build().unwrap().open_struct("s").unwrap()
+}
+
+#[test]
+fn sketch_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    let ws = open_sketch(dir.path());
+    let heads: Vec<_> = ws.iter_ids().map(|h| (h.id, h.cnt, h.frq)).collect();
+    assert_eq!(heads, [(1, 2, 10), (2, 1, 1)]);
+    let head = ws.find_id(1).unwrap();
+    let rel = head.iter().nth(1).unwrap();
+    assert_eq!((rel.id, rel.cnt, rel.frq), (1, 2, 5));
+    let coll = rel.iter().next().unwrap();
+    assert_eq!((coll.id, coll.cnt, coll.frq), (20, 4, 13));
+    assert_eq!(coll.iter().collect::<Vec<_>>(),
+               [(3, Some(1)), (8, Some(-1)), (17, Some(3)), (18, None)]);
+    assert!(ws.find_id(3).is_none());
+}
+
+#[test]
+fn multiword_sketch() {
+    let dir = tempfile::tempdir().unwrap();
+    let ws = open_sketch(dir.path());
+    let s = sentences();
+    let head = ws.multiword(1, 0, 10, s.as_ref()).unwrap();
+    assert_eq!((head.id, head.cnt, head.frq), (1, 2, 3));
+
+    // walked like a sketch from the disk
+    let mut got = Vec::new();
+    for rel in head.iter() {
+        for coll in rel.iter() {
+            got.push((rel.id, coll.id, coll.cnt, coll.frq, coll.iter().collect::<Vec<_>>()));
+        }
+    }
+    // 12 and 13 are in the sentence 10..15 without the base collocation
+    assert_eq!(got, [
+        (0, 11, 1, 5, vec![(2, Some(-2))]),
+        (1, 20, 4, 13, vec![(3, Some(1)), (8, Some(-1)), (17, Some(3)), (18, None)]),
+    ]);
+    // logDice against the 3 hits of the base: 14 + log2(2 cnt / (3 + frq)),
+    // for relations with the frequency of the relation
+    let rnks: Vec<f32> = head.iter().flat_map(|r| r.iter().map(|c| c.rnk)).collect();
+    assert_eq!(rnks, [12.0, 13.0]);
+    let rels: Vec<_> = head.iter().map(|r| (r.id, r.cnt, r.frq, r.rnk)).collect();
+    assert_eq!(rels, [(0, 1, 1, 12.0), (1, 1, 4, 14.0)]);
+    assert_eq!(head.iter().next().unwrap().iter().next().unwrap().lcm, [-1]);
+
+    assert!(ws.multiword(1, 0, 11, s.as_ref()).is_some());
+    assert!(ws.multiword(1, 1, 10, s.as_ref()).is_none());
+    assert!(ws.multiword(3, 0, 10, s.as_ref()).is_none());
+    let single = ws.multiword(2, 0, 10, s.as_ref()).unwrap();
+    assert_eq!((single.cnt, single.iter().count()), (0, 0));
+}
+
+#[test]
+fn collocation_concordance() {
+    let dir = tempfile::tempdir().unwrap();
+    let ws = open_sketch(dir.path());
+    let head = ws.find_id(1).unwrap();
+    let rel = head.iter().nth(1).unwrap();
+    let coll = rel.iter().next().unwrap();
+    let hit = |head, coll| CollHit { head, coll };
+    let all = [hit(3, Some(4)), hit(8, Some(7)), hit(17, Some(20)), hit(18, None)];
+    assert_eq!(coll.conc().hits(), all);
+    assert_eq!(all.map(|h| h.range()), [(3, 5), (7, 9), (17, 21), (18, 19)]);
+
+    let mut b = MemBuilder::new(&["word"]);
+    for _ in 0..20 {
+        b.token(&["w"]);
+    }
+    b.span("p", 5, 10, &[]);
+    b.span("p", 16, 18, &[]);
+    let subc = b.build().unwrap().open_struct("p").unwrap();
+    assert_eq!(coll.conc().within(subc.as_ref()).hits(), [hit(8, Some(7)), hit(17, Some(20))]);
+
+    let sample = coll.conc().sample(2).seed(7).hits();
+    assert_eq!(sample.len(), 2);
+    assert!(sample[0].head < sample[1].head && sample.iter().all(|h| all.contains(h)));
+    assert_eq!(coll.conc().sample(2).seed(7).hits(), sample);
+    assert_eq!(coll.conc().sample(5).hits(), all);
+    let mut shuffled = coll.conc().shuffle().hits();
+    assert_eq!(coll.conc().shuffle().hits(), shuffled);
+    shuffled.sort_by_key(|h| h.head);
+    assert_eq!(shuffled, all);
+
+    // the hits left in a multiword sketch
+    let s = sentences();
+    let mws = ws.multiword(1, 0, 10, s.as_ref()).unwrap();
+    let coll = mws.iter().nth(1).unwrap().iter().next().unwrap();
+    assert_eq!(coll.conc().within(subc.as_ref()).hits(), [hit(8, Some(7)), hit(17, Some(20))]);
+}